@@ -0,0 +1,309 @@
+//! Heap debugging mode, enabled with the `debug` feature.
+//!
+//! Every block is surrounded by red zones that are checked when it is freed, freed memory is
+//! filled with a poison pattern, and live blocks are recorded so that double frees and
+//! deallocations with a different layout are caught. Any violation panics with the offending
+//! address and size.
+
+use {
+    super::Heap,
+    core::{alloc::Layout, fmt, mem::size_of, ptr::NonNull},
+};
+
+/// Size of the red zone behind a block. The one in front is at least as large.
+pub const REDZONE: usize = 16;
+/// Fill pattern of the red zones.
+pub const REDZONE_BYTE: u8 = 0xfd;
+/// Fill pattern of newly allocated blocks.
+pub const UNINIT_BYTE: u8 = 0xcd;
+/// Fill pattern of freed blocks.
+pub const POISON_BYTE: u8 = 0xdd;
+
+#[derive(Debug, Clone, Copy)]
+pub enum HeapError {
+    /// The block at `ptr` is not allocated, it was probably freed twice.
+    DoubleFree { ptr: usize, size: usize },
+    /// The block at `ptr` is freed with a different layout than it was allocated with.
+    LayoutMismatch {
+        ptr: usize,
+        size: usize,
+        align: usize,
+        expected_size: usize,
+        expected_align: usize,
+    },
+    /// The red zone of the block at `ptr` was overwritten at `addr`.
+    RedZone {
+        ptr: usize,
+        size: usize,
+        addr: usize,
+    },
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            HeapError::DoubleFree { ptr, size } => {
+                write!(f, "double free of {:#x} ({} bytes)", ptr, size)
+            }
+            HeapError::LayoutMismatch {
+                ptr,
+                size,
+                align,
+                expected_size,
+                expected_align,
+            } => write!(
+                f,
+                "{:#x} freed with size {} align {}, but allocated with size {} align {}",
+                ptr, size, align, expected_size, expected_align
+            ),
+            HeapError::RedZone { ptr, size, addr } => write!(
+                f,
+                "red zone of {:#x} ({} bytes) overwritten at {:#x}",
+                ptr, size, addr
+            ),
+        }
+    }
+}
+
+/// Returns the size of the red zone in front of a block, which keeps the block aligned.
+fn front(layout: &Layout) -> usize {
+    REDZONE.max(layout.align())
+}
+
+/// Returns the layout of the block including its red zones.
+pub(crate) fn padded(layout: &Layout) -> Option<Layout> {
+    let size = front(layout)
+        .checked_add(layout.size())?
+        .checked_add(REDZONE)?;
+    Layout::from_size_align(size, layout.align()).ok()
+}
+
+/// Returns the start of the block at `ptr`, its front red zone.
+pub(crate) fn base(ptr: NonNull<u8>, layout: &Layout) -> NonNull<u8> {
+    // the red zone is part of the block, so it does not start at 0
+    unsafe { NonNull::new_unchecked(ptr.as_ptr().wrapping_sub(front(layout))) }
+}
+
+pub(crate) fn alloc(heap: &mut Heap, layout: Layout) -> Result<NonNull<u8>, ()> {
+    let padded = padded(&layout).ok_or(())?;
+
+    // Make room for the record first, so that running out of memory fails the allocation
+    let mut records = core::mem::take(&mut heap.records);
+    let reserved = records.reserve(heap);
+    heap.records = records;
+    reserved?;
+
+    let base = heap.alloc_raw(padded)?.as_ptr();
+    let ptr = unsafe {
+        let ptr = base.add(front(&layout));
+        base.write_bytes(REDZONE_BYTE, front(&layout));
+        ptr.write_bytes(UNINIT_BYTE, layout.size());
+        ptr.add(layout.size()).write_bytes(REDZONE_BYTE, REDZONE);
+        ptr
+    };
+    heap.records.insert(Record {
+        ptr: ptr as usize,
+        size: layout.size(),
+        align: layout.align(),
+    });
+
+    Ok(unsafe { NonNull::new_unchecked(ptr) })
+}
+
+pub(crate) unsafe fn dealloc(heap: &mut Heap, ptr: NonNull<u8>, layout: Layout) {
+    let ptr = ptr.as_ptr();
+    let size = layout.size();
+
+    let record = heap.records.remove(ptr as usize).unwrap_or_else(|| {
+        panic!(
+            "{}",
+            HeapError::DoubleFree {
+                ptr: ptr as usize,
+                size,
+            }
+        )
+    });
+    if record.size != size || record.align != layout.align() {
+        panic!(
+            "{}",
+            HeapError::LayoutMismatch {
+                ptr: ptr as usize,
+                size,
+                align: layout.align(),
+                expected_size: record.size,
+                expected_align: record.align,
+            }
+        );
+    }
+
+    let base = ptr.sub(front(&layout));
+    let zones = [(base, front(&layout)), (ptr.add(size), REDZONE)];
+    for (start, len) in zones {
+        let zone = core::slice::from_raw_parts(start, len);
+        if let Some(offset) = zone.iter().position(|&byte| byte != REDZONE_BYTE) {
+            panic!(
+                "{}",
+                HeapError::RedZone {
+                    ptr: ptr as usize,
+                    size,
+                    addr: start as usize + offset,
+                }
+            );
+        }
+    }
+
+    let padded = padded(&layout).unwrap();
+    base.write_bytes(POISON_BYTE, padded.size());
+    heap.dealloc_raw(NonNull::new_unchecked(base), padded);
+}
+
+/// A live block.
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    ptr: usize,
+    size: usize,
+    align: usize,
+}
+
+impl Record {
+    const EMPTY: usize = 0;
+    const REMOVED: usize = 1;
+}
+
+/// Initial number of slots of the record table.
+const INITIAL_CAPACITY: usize = 1024;
+
+/// An open addressing hash table of live blocks, keyed by address. Its slots are allocated from
+/// the heap itself, bypassing the debugging layer.
+pub struct Records {
+    slots: Option<NonNull<Record>>,
+    capacity: usize,
+    /// Number of live records
+    len: usize,
+    /// Number of removed slots that still occupy the table
+    removed: usize,
+}
+
+/// The table is only reachable through the heap lock.
+unsafe impl Send for Records {}
+
+impl Default for Records {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Records {
+    pub const fn empty() -> Self {
+        Self {
+            slots: None,
+            capacity: 0,
+            len: 0,
+            removed: 0,
+        }
+    }
+
+    fn slots(&mut self) -> &mut [Record] {
+        match self.slots {
+            Some(slots) => unsafe {
+                core::slice::from_raw_parts_mut(slots.as_ptr(), self.capacity)
+            },
+            None => &mut [],
+        }
+    }
+
+    fn layout(capacity: usize) -> Layout {
+        Layout::array::<Record>(capacity).unwrap()
+    }
+
+    /// Returns the first slot for `ptr` in the probe sequence.
+    fn hash(&self, ptr: usize) -> usize {
+        ((ptr / size_of::<usize>()).wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize))
+            & (self.capacity - 1)
+    }
+
+    /// Makes sure that one more record fits without exceeding half of the capacity.
+    fn reserve(&mut self, heap: &mut Heap) -> Result<(), ()> {
+        if (self.len + self.removed + 1) * 2 > self.capacity {
+            self.grow(heap)?;
+        }
+        Ok(())
+    }
+
+    /// Inserts a record, which must have been reserved with [`Records::reserve`].
+    fn insert(&mut self, record: Record) {
+        let mut index = self.hash(record.ptr);
+        let mask = self.capacity - 1;
+        let slots = self.slots();
+        while slots[index].ptr > Record::REMOVED {
+            index = (index + 1) & mask;
+        }
+        if slots[index].ptr == Record::REMOVED {
+            self.removed -= 1;
+        }
+        self.slots()[index] = record;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, ptr: usize) -> Option<Record> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        let mut index = self.hash(ptr);
+        let mask = self.capacity - 1;
+        let slots = self.slots();
+        loop {
+            match slots[index].ptr {
+                Record::EMPTY => return None,
+                found if found == ptr => {
+                    let record = slots[index];
+                    slots[index].ptr = Record::REMOVED;
+                    self.len -= 1;
+                    self.removed += 1;
+                    return Some(record);
+                }
+                _ => index = (index + 1) & mask,
+            }
+        }
+    }
+
+    /// Moves the records into a table that is at least twice as large as the live records.
+    fn grow(&mut self, heap: &mut Heap) -> Result<(), ()> {
+        let capacity = ((self.len + 1) * 4)
+            .next_power_of_two()
+            .max(INITIAL_CAPACITY);
+        let slots = heap.alloc_raw(Self::layout(capacity))?.cast::<Record>();
+
+        let mut records = Records {
+            slots: Some(slots),
+            capacity,
+            len: 0,
+            removed: 0,
+        };
+        records.slots().fill(Record {
+            ptr: Record::EMPTY,
+            size: 0,
+            align: 0,
+        });
+
+        let old_capacity = self.capacity;
+        for record in self.slots().iter() {
+            if record.ptr > Record::REMOVED {
+                let mut index = records.hash(record.ptr);
+                let slots = records.slots();
+                while slots[index].ptr != Record::EMPTY {
+                    index = (index + 1) & (capacity - 1);
+                }
+                slots[index] = *record;
+                records.len += 1;
+            }
+        }
+
+        if let Some(old) = self.slots.take() {
+            unsafe { heap.dealloc_raw(old.cast(), Self::layout(old_capacity)) };
+        }
+        *self = records;
+        Ok(())
+    }
+}
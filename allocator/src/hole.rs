@@ -88,9 +88,7 @@ impl HoleList {
             size = Self::min_size();
         }
         let size = align_up(size, align_of::<Hole>());
-        let layout = Layout::from_size_align(size, layout.align()).unwrap();
-
-        layout
+        Layout::from_size_align(size, layout.align()).unwrap()
     }
 
     /// Searches the list for a big enough hole.
@@ -132,6 +130,41 @@ impl HoleList {
         aligned_layout
     }
 
+    /// Removes the last `size` bytes of the last hole, if it ends at `top` and what is left of it
+    /// is either nothing or at least [`HoleList::min_size`]. Returns whether it did.
+    pub fn truncate(&mut self, top: usize, size: usize) -> bool {
+        let mut previous = &mut self.first;
+        while previous
+            .next
+            .as_ref()
+            .is_some_and(|hole| hole.next.is_some())
+        {
+            previous = previous.next.as_mut().unwrap();
+        }
+
+        let last = match previous.next.as_mut() {
+            Some(last) => last,
+            None => return false,
+        };
+        let HoleInfo { addr, size: hole } = last.info();
+        if addr + hole != top || hole < size {
+            return false;
+        }
+
+        match hole - size {
+            0 => previous.next = None,
+            rest if rest >= Self::min_size() => last.size = rest,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Returns an iterator over the `(address, size)` of the holes, in list order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        core::iter::successors(self.first.next.as_deref(), |hole| hole.next.as_deref())
+            .map(|hole| (hole.info().addr, hole.size))
+    }
+
     /// Returns the minimal allocation size. Smaller allocations or deallocations are not allowed.
     pub fn min_size() -> usize {
         size_of::<usize>() * 2
@@ -238,7 +271,7 @@ fn allocate_first_fit(mut previous: &mut Hole, layout: Layout) -> Result<HoleInf
         let allocation: Option<Allocation> = previous
             .next
             .as_mut()
-            .and_then(|current| split_hole(current.info(), layout.clone()));
+            .and_then(|current| split_hole(current.info(), layout));
         match allocation {
             Some(allocation) => {
                 // link the front/back padding
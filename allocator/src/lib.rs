@@ -0,0 +1,39 @@
+//! The kernel heap allocator: a sorted linked list of holes with size-class slabs in front of
+//! it for small allocations.
+//!
+//! It lives in its own crate so that it can be built and tested on the host.
+
+#![no_std]
+// The API follows the upstream `linked_list_allocator` crate
+#![allow(clippy::result_unit_err, clippy::missing_safety_doc)]
+
+#[cfg(feature = "debug")]
+pub mod debug;
+mod hole;
+mod linked_list_allocator;
+mod slab;
+
+pub use {
+    hole::HoleList,
+    linked_list_allocator::{Heap, LockedHeap},
+    slab::Slabs,
+};
+
+/// Align downwards. Returns the greatest x with alignment `align`
+/// so that x <= addr. The alignment must be a power of 2.
+fn align_down(addr: usize, align: usize) -> usize {
+    if align.is_power_of_two() {
+        addr & !(align - 1)
+    } else if align == 0 {
+        addr
+    } else {
+        panic!("`align` must be a power of 2");
+    }
+}
+
+/// Align upwards. Returns the smallest x with alignment `align`
+/// so that x >= addr. The alignment must be a power of 2.
+#[inline]
+fn align_up(addr: usize, align: usize) -> usize {
+    align_down(addr + align - 1, align)
+}
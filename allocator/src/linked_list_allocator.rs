@@ -24,8 +24,10 @@
 // IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+#[cfg(feature = "debug")]
+use crate::debug::{self, Records};
 use {
-    super::HoleList,
+    super::{HoleList, Slabs},
     core::{
         alloc::{GlobalAlloc, Layout},
         ops::Deref,
@@ -40,15 +42,21 @@ pub struct Heap {
     size: usize,
     used: usize,
     holes: HoleList,
+    slabs: Slabs,
+    #[cfg(feature = "debug")]
+    pub(crate) records: Records,
 }
 
 impl Heap {
-    const fn empty() -> Heap {
+    pub const fn empty() -> Heap {
         Heap {
             bottom: 0,
             size: 0,
             used: 0,
             holes: HoleList::empty(),
+            slabs: Slabs::new(),
+            #[cfg(feature = "debug")]
+            records: Records::empty(),
         }
     }
 
@@ -120,6 +128,91 @@ impl Heap {
         unsafe { Self::new(address, size) }
     } */
 
+    /// Allocates a chunk for `layout`.
+    ///
+    /// With the `debug` feature, the chunk is surrounded by red zones and recorded, see
+    /// [`crate::debug`].
+    pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
+        #[cfg(feature = "debug")]
+        return debug::alloc(self, layout);
+        #[cfg(not(feature = "debug"))]
+        self.alloc_raw(layout)
+    }
+
+    /// Frees an allocation returned by [`Heap::alloc`] with identical layout.
+    ///
+    /// # Safety
+    ///
+    /// Undefined behavior may occur for invalid arguments. With the `debug` feature, double
+    /// frees, layout mismatches and red zone overwrites panic instead.
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "debug")]
+        debug::dealloc(self, ptr, layout);
+        #[cfg(not(feature = "debug"))]
+        self.dealloc_raw(ptr, layout)
+    }
+
+    /// Allocates a chunk for `layout`. Small layouts are served from the size-class [`Slabs`],
+    /// which are refilled from the free list. Larger ones use [`Heap::allocate_first_fit`].
+    pub(crate) fn alloc_raw(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
+        match Self::class(&layout) {
+            Some(class) => {
+                if self.slabs.is_empty(class) {
+                    let chunk = self.allocate_first_fit(Slabs::chunk_layout(class))?;
+                    unsafe { self.slabs.refill(class, chunk) };
+                }
+                self.slabs.allocate(class).ok_or(())
+            }
+            None => self.allocate_first_fit(layout),
+        }
+    }
+
+    /// Frees an allocation returned by [`Heap::alloc_raw`] with identical layout.
+    ///
+    /// # Safety
+    ///
+    /// Undefined behavior may occur for invalid arguments.
+    pub(crate) unsafe fn dealloc_raw(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        match Self::class(&layout) {
+            Some(class) => self.slabs.deallocate(class, ptr),
+            None => self.deallocate(ptr, layout),
+        }
+    }
+
+    /// Whether a block of `layout` comes from the slabs, and has a tag byte, see [`Heap::tag`].
+    pub fn is_tagged(layout: &Layout) -> bool {
+        Self::block_class(layout).is_some()
+    }
+
+    /// Returns the tag byte of the block at `ptr`, allocated with `layout`, if it comes from the
+    /// slabs. The heap zeroes it when the slab chunk is carved and never reads it, so it may
+    /// record anything about the block, e.g. who allocated it.
+    pub fn tag(ptr: NonNull<u8>, layout: &Layout) -> Option<NonNull<u8>> {
+        let class = Self::block_class(layout)?;
+        #[cfg(feature = "debug")]
+        let ptr = debug::base(ptr, layout);
+        Some(Slabs::tag(class, ptr))
+    }
+
+    /// Returns the slab class of a block of `layout`, including the red zones of the `debug`
+    /// feature.
+    fn block_class(layout: &Layout) -> Option<usize> {
+        #[cfg(feature = "debug")]
+        return Self::class(&debug::padded(layout)?);
+        #[cfg(not(feature = "debug"))]
+        Self::class(layout)
+    }
+
+    /// Returns the slab class serving `layout`. With the `first-fit` feature, everything is
+    /// served by the free list instead.
+    fn class(layout: &Layout) -> Option<usize> {
+        if cfg!(feature = "first-fit") {
+            None
+        } else {
+            Slabs::class(layout)
+        }
+    }
+
     /// Allocates a chunk of the given size with the given alignment. Returns a pointer to the
     /// beginning of that chunk if it was successful. Else it returns `None`.
     /// This function scans the list of free memory blocks and uses the first block that is big
@@ -151,27 +244,58 @@ impl Heap {
         self.bottom
     } */
 
-    /* /// Returns the size of the heap.
+    /// Returns the size of the heap.
     pub fn size(&self) -> usize {
         self.size
-    } */
+    }
 
-    /* /// Return the top address of the heap
+    /// Return the top address of the heap
     pub fn top(&self) -> usize {
         self.bottom + self.size
-    } */
+    }
 
-    /* /// Returns the size of the used part of the heap
+    /// Returns the size of the used part of the heap, including all slab chunks
     pub fn used(&self) -> usize {
         self.used
-    } */
+    }
+
+    /// Returns the size of the slab chunks and of the slab blocks in use
+    pub fn slabs(&self) -> (usize, usize) {
+        (self.slabs.size(), self.slabs.used())
+    }
 
-    /* /// Returns the size of the free part of the heap
+    /// Returns the size of the free part of the heap
     pub fn free(&self) -> usize {
         self.size - self.used
-    } */
+    }
 
-    /* /// Extends the size of the heap by creating a new hole at the end
+    /// Returns the number of free blocks and the size of the largest one
+    pub fn holes(&self) -> (usize, usize) {
+        self.holes
+            .iter()
+            .fold((0, 0), |(count, largest), (_, size)| {
+                (count + 1, largest.max(size))
+            })
+    }
+
+    /// Returns the size of the free block at the top of the heap
+    pub fn free_top(&self) -> usize {
+        match self.holes.iter().last() {
+            Some((addr, size)) if addr + size == self.top() => size,
+            _ => 0,
+        }
+    }
+
+    /// Shrinks the heap by `by` bytes, if they are free. Returns whether it did.
+    pub fn shrink(&mut self, by: usize) -> bool {
+        let shrunk = self.holes.truncate(self.top(), by);
+        if shrunk {
+            self.size -= by;
+        }
+        shrunk
+    }
+
+    /// Extends the size of the heap by creating a new hole at the end
     ///
     /// # Safety
     ///
@@ -182,7 +306,7 @@ impl Heap {
         self.holes
             .deallocate(NonNull::new_unchecked(top as *mut u8), layout);
         self.size += by;
-    } */
+    }
 }
 
 pub struct LockedHeap(Mutex<Heap>);
@@ -218,14 +342,12 @@ unsafe impl GlobalAlloc for LockedHeap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         self.0
             .lock()
-            .allocate_first_fit(layout)
+            .alloc(layout)
             .ok()
-            .map_or(0 as *mut u8, |allocation| allocation.as_ptr())
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.0
-            .lock()
-            .deallocate(NonNull::new_unchecked(ptr), layout)
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
     }
 }
@@ -0,0 +1,151 @@
+use core::{alloc::Layout, ptr::NonNull};
+
+/// Smallest size class, big enough to hold a free list link.
+const MIN_CLASS: usize = 8;
+/// Largest size class, larger layouts go to the `HoleList`.
+const MAX_CLASS: usize = 2048;
+/// Number of size classes, one for every power of two in `MIN_CLASS..=MAX_CLASS`.
+const CLASSES: usize = (MAX_CLASS.trailing_zeros() - MIN_CLASS.trailing_zeros() + 1) as usize;
+/// Size of the chunks that are taken from the `HoleList` and carved into blocks. Chunks are
+/// aligned to their size, so that the chunk of a block is found from its address.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A free block, linked into the free list of its size class.
+struct Block {
+    next: Option<NonNull<Block>>,
+}
+
+/// Free lists of fixed-size blocks, one per power-of-two size class.
+///
+/// Every block is aligned to its size, so a layout is served by the smallest class that is not
+/// smaller than its size and alignment. Allocation and deallocation are O(1).
+///
+/// A chunk starts with one tag byte per block, which the user of the heap may use to record
+/// something about each block without making it larger, see [`Slabs::tag`]. The tags take the
+/// first blocks of the chunk, 1/8 of it for the smallest class and a single block from 256
+/// bytes up.
+///
+/// Chunks are never given back to the `HoleList`: that would need a count of the blocks in use
+/// per chunk, and the free lists would have to be searched for the blocks of an empty chunk.
+/// A free block can only be reused by its own class, so the memory held by the slabs is the
+/// peak usage of every class, rounded up to whole chunks. [`Slabs::size`] and [`Slabs::used`]
+/// show how much of it is idle.
+pub struct Slabs {
+    free: [Option<NonNull<Block>>; CLASSES],
+    /// Bytes taken from the `HoleList` as chunks.
+    size: usize,
+    /// Bytes of the blocks handed out.
+    used: usize,
+}
+
+/// The blocks are only reachable through the heap lock.
+unsafe impl Send for Slabs {}
+
+impl Default for Slabs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Slabs {
+    pub const fn new() -> Self {
+        Self {
+            free: [None; CLASSES],
+            size: 0,
+            used: 0,
+        }
+    }
+
+    /// Returns the size class serving `layout`, or `None` if it is too large for the slabs.
+    pub fn class(layout: &Layout) -> Option<usize> {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(MIN_CLASS)
+            .next_power_of_two();
+
+        (size <= MAX_CLASS).then(|| (size.trailing_zeros() - MIN_CLASS.trailing_zeros()) as usize)
+    }
+
+    /// Returns the block size of `class`.
+    pub fn class_size(class: usize) -> usize {
+        MIN_CLASS << class
+    }
+
+    /// Returns the layout of the chunk used by [`Slabs::refill`].
+    pub fn chunk_layout(_class: usize) -> Layout {
+        Layout::from_size_align(CHUNK_SIZE, CHUNK_SIZE).unwrap()
+    }
+
+    /// Returns the size of the tags at the start of a chunk of `class`, in whole blocks.
+    fn tags_size(class: usize) -> usize {
+        let size = Self::class_size(class);
+        crate::align_up(CHUNK_SIZE / size, size)
+    }
+
+    /// Returns the tag byte of the block of `class` at `ptr`. It is zeroed when the chunk is
+    /// carved, and otherwise left alone by the slabs.
+    pub fn tag(class: usize, ptr: NonNull<u8>) -> NonNull<u8> {
+        let addr = ptr.as_ptr() as usize;
+        let chunk = addr & !(CHUNK_SIZE - 1);
+        let index = (addr - chunk) / Self::class_size(class);
+        unsafe { NonNull::new_unchecked((chunk + index) as *mut u8) }
+    }
+
+    /// Whether the free list of `class` is empty.
+    pub fn is_empty(&self, class: usize) -> bool {
+        self.free[class].is_none()
+    }
+
+    /// Carves `chunk` into blocks of `class`.
+    ///
+    /// # Safety
+    ///
+    /// `chunk` must be an unused allocation of [`Slabs::chunk_layout`].
+    pub unsafe fn refill(&mut self, class: usize, chunk: NonNull<u8>) {
+        let size = Self::class_size(class);
+        let tags = Self::tags_size(class);
+        chunk.as_ptr().write_bytes(0, tags);
+        // push in reverse to hand out the blocks in address order
+        for offset in (tags..CHUNK_SIZE).step_by(size).rev() {
+            self.push(class, NonNull::new_unchecked(chunk.as_ptr().add(offset)));
+        }
+        self.size += CHUNK_SIZE;
+    }
+
+    /// Pops a block of `class`.
+    pub fn allocate(&mut self, class: usize) -> Option<NonNull<u8>> {
+        let block = self.free[class]?;
+        self.free[class] = unsafe { block.as_ref() }.next;
+        self.used += Self::class_size(class);
+        Some(block.cast())
+    }
+
+    /// Pushes a block back onto the free list of `class`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a block of `class` that is no longer used.
+    pub unsafe fn deallocate(&mut self, class: usize, ptr: NonNull<u8>) {
+        self.push(class, ptr);
+        self.used -= Self::class_size(class);
+    }
+
+    /// Returns the size of all chunks, which the `HoleList` counts as used.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the size of the blocks in use, a part of [`Slabs::size`].
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    unsafe fn push(&mut self, class: usize, ptr: NonNull<u8>) {
+        let block = ptr.cast::<Block>();
+        block.as_ptr().write(Block {
+            next: self.free[class],
+        });
+        self.free[class] = Some(block);
+    }
+}
@@ -0,0 +1,130 @@
+//! Compares the slabs with plain first-fit on an allocation pattern modelled on the JS engine.
+//!
+//! Run with `cargo test --release --test bench -- --ignored --nocapture`.
+
+mod common;
+
+use {
+    common::{Memory, Rng},
+    core::{alloc::Layout, ptr::NonNull},
+    ingram_allocator::Heap,
+    std::time::{Duration, Instant},
+};
+
+const SIZE: usize = 64 << 20;
+const OBJECTS: usize = 200_000;
+/// Objects allocated between two collections.
+const GC_INTERVAL: usize = 10_000;
+
+/// The allocation primitives under test.
+trait Allocator {
+    fn alloc(heap: &mut Heap, layout: Layout) -> NonNull<u8>;
+    unsafe fn dealloc(heap: &mut Heap, ptr: NonNull<u8>, layout: Layout);
+}
+
+struct Slabs;
+
+impl Allocator for Slabs {
+    fn alloc(heap: &mut Heap, layout: Layout) -> NonNull<u8> {
+        heap.alloc(layout).unwrap()
+    }
+
+    unsafe fn dealloc(heap: &mut Heap, ptr: NonNull<u8>, layout: Layout) {
+        heap.dealloc(ptr, layout)
+    }
+}
+
+struct FirstFit;
+
+impl Allocator for FirstFit {
+    fn alloc(heap: &mut Heap, layout: Layout) -> NonNull<u8> {
+        heap.allocate_first_fit(layout).unwrap()
+    }
+
+    unsafe fn dealloc(heap: &mut Heap, ptr: NonNull<u8>, layout: Layout) {
+        heap.deallocate(ptr, layout)
+    }
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+/// Builds objects like `{ id, name, tags: [] }` into a growing array, and drops most of them at
+/// every collection:
+///
+/// - the object itself and its property table, which doubles when it grows,
+/// - a short string for `name`,
+/// - the backing store of the array, which doubles as well,
+/// - a sweep freeing the garbage in the order the collector finds it.
+fn js_workload<A: Allocator>(seed: u64) -> (Duration, (usize, usize)) {
+    let memory = Memory::new(SIZE);
+    let mut heap = Heap::empty();
+    unsafe { heap.init(memory.bottom, memory.size) };
+    let mut rng = Rng::new(seed);
+
+    let mut live: Vec<(NonNull<u8>, Layout)> = Vec::new();
+    let mut array = (A::alloc(&mut heap, layout(64)), layout(64));
+    let mut length = 0;
+
+    let start = Instant::now();
+    for i in 1..=OBJECTS {
+        live.push((A::alloc(&mut heap, layout(144)), layout(144)));
+
+        let mut table = layout(64);
+        let mut ptr = A::alloc(&mut heap, table);
+        for _ in 0..rng.range(0..3) {
+            let grown = layout(table.size() * 2);
+            let new = A::alloc(&mut heap, grown);
+            unsafe { A::dealloc(&mut heap, ptr, table) };
+            (ptr, table) = (new, grown);
+        }
+        live.push((ptr, table));
+
+        let name = layout(rng.range(24..96));
+        live.push((A::alloc(&mut heap, name), name));
+
+        length += 1;
+        if length * 8 > array.1.size() {
+            let grown = layout(array.1.size() * 2);
+            let new = A::alloc(&mut heap, grown);
+            unsafe { A::dealloc(&mut heap, array.0, array.1) };
+            array = (new, grown);
+        }
+
+        if i % GC_INTERVAL == 0 {
+            // keeps one object in ten
+            let mut kept = Vec::new();
+            for (j, block) in live.drain(..).enumerate() {
+                if (j / 3) % 10 == 0 {
+                    kept.push(block);
+                } else {
+                    unsafe { A::dealloc(&mut heap, block.0, block.1) };
+                }
+            }
+            live = kept;
+            length /= 10;
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let holes = heap.holes();
+    for (ptr, layout) in live {
+        unsafe { A::dealloc(&mut heap, ptr, layout) };
+    }
+    unsafe { A::dealloc(&mut heap, array.0, array.1) };
+    (elapsed, holes)
+}
+
+#[test]
+#[ignore]
+fn slabs_against_first_fit() {
+    for seed in 1..=3 {
+        let (slabs, (slab_holes, _)) = js_workload::<Slabs>(seed);
+        let (first_fit, (first_fit_holes, _)) = js_workload::<FirstFit>(seed);
+        println!(
+            "seed {}: slabs {:?} ({} holes), first-fit {:?} ({} holes)",
+            seed, slabs, slab_holes, first_fit, first_fit_holes
+        );
+    }
+}
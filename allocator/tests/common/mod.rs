@@ -0,0 +1,93 @@
+#![allow(dead_code)]
+
+use {
+    ingram_allocator::HoleList,
+    std::alloc::{alloc, dealloc, Layout},
+};
+
+/// Heap memory borrowed from the host allocator.
+pub struct Memory {
+    pub bottom: usize,
+    pub size: usize,
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Self {
+        let ptr = unsafe { alloc(Self::layout(size)) };
+        assert!(!ptr.is_null());
+        Self {
+            bottom: ptr as usize,
+            size,
+        }
+    }
+
+    pub fn top(&self) -> usize {
+        self.bottom + self.size
+    }
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, 4096).unwrap()
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.bottom as *mut u8, Self::layout(self.size)) };
+    }
+}
+
+/// A small xorshift generator, so that failures are reproducible from the seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in `range`.
+    pub fn range(&mut self, range: std::ops::Range<usize>) -> usize {
+        range.start + (self.next() as usize) % (range.end - range.start)
+    }
+}
+
+/// Checks that the holes are sorted, fully coalesced, not smaller than
+/// `HoleList::min_size()`, and that together with the `live` blocks they tile `memory` exactly.
+pub fn check_invariants(holes: &HoleList, memory: &Memory, live: &[(usize, usize)]) {
+    let holes = holes.iter().collect::<Vec<_>>();
+
+    for window in holes.windows(2) {
+        let ((prev, prev_size), (next, _)) = (window[0], window[1]);
+        assert!(
+            prev + prev_size < next,
+            "holes {:#x}+{} and {:#x} are unsorted or not coalesced",
+            prev,
+            prev_size,
+            next
+        );
+    }
+
+    for &(addr, size) in &holes {
+        assert!(
+            size >= HoleList::min_size(),
+            "hole {:#x} is too small",
+            addr
+        );
+    }
+
+    let mut blocks = holes.iter().chain(live).copied().collect::<Vec<_>>();
+    blocks.sort_unstable();
+
+    let mut expected = memory.bottom;
+    for (addr, size) in blocks {
+        assert_eq!(addr, expected, "gap or overlap at {:#x}", addr);
+        expected = addr + size;
+    }
+    assert_eq!(expected, memory.top(), "blocks do not cover the heap");
+}
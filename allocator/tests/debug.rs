@@ -0,0 +1,114 @@
+//! Heap debugging mode, run with `cargo test --features debug`.
+
+#![cfg(feature = "debug")]
+
+mod common;
+
+use {
+    common::Memory,
+    core::{alloc::Layout, ptr::NonNull},
+    ingram_allocator::{
+        debug::{POISON_BYTE, UNINIT_BYTE},
+        LockedHeap,
+    },
+};
+
+const SIZE: usize = 1 << 20;
+
+fn heap(memory: &Memory) -> LockedHeap {
+    let heap = LockedHeap::empty();
+    unsafe { heap.lock().init(memory.bottom, memory.size) };
+    heap
+}
+
+fn alloc(heap: &LockedHeap, size: usize, align: usize) -> (NonNull<u8>, Layout) {
+    let layout = Layout::from_size_align(size, align).unwrap();
+    (heap.lock().alloc(layout).unwrap(), layout)
+}
+
+#[test]
+fn fills_and_poisons() {
+    let memory = Memory::new(SIZE);
+    let heap = heap(&memory);
+
+    let (ptr, layout) = alloc(&heap, 100, 8);
+    let bytes = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+    assert!(bytes.iter().all(|&byte| byte == UNINIT_BYTE));
+
+    unsafe { heap.lock().dealloc(ptr, layout) };
+    let bytes = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+    assert!(bytes[16..].iter().all(|&byte| byte == POISON_BYTE));
+}
+
+#[test]
+fn keeps_alignment() {
+    let memory = Memory::new(SIZE);
+    let heap = heap(&memory);
+
+    for align in [1, 8, 64, 4096] {
+        let (ptr, layout) = alloc(&heap, 3000, align);
+        assert_eq!(ptr.as_ptr() as usize % align, 0);
+        unsafe { heap.lock().dealloc(ptr, layout) };
+    }
+}
+
+#[test]
+fn many_records() {
+    let memory = Memory::new(SIZE * 4);
+    let heap = heap(&memory);
+
+    let blocks = (0..5000).map(|_| alloc(&heap, 24, 8)).collect::<Vec<_>>();
+    for (ptr, layout) in blocks {
+        unsafe { heap.lock().dealloc(ptr, layout) };
+    }
+}
+
+#[test]
+#[should_panic(expected = "red zone")]
+fn overflow() {
+    let memory = Memory::new(SIZE);
+    let heap = heap(&memory);
+
+    let (ptr, layout) = alloc(&heap, 100, 8);
+    unsafe {
+        ptr.as_ptr().add(layout.size()).write(0);
+        heap.lock().dealloc(ptr, layout);
+    }
+}
+
+#[test]
+#[should_panic(expected = "red zone")]
+fn underflow() {
+    let memory = Memory::new(SIZE);
+    let heap = heap(&memory);
+
+    let (ptr, layout) = alloc(&heap, 5000, 8);
+    unsafe {
+        ptr.as_ptr().sub(1).write(0);
+        heap.lock().dealloc(ptr, layout);
+    }
+}
+
+#[test]
+#[should_panic(expected = "double free")]
+fn double_free() {
+    let memory = Memory::new(SIZE);
+    let heap = heap(&memory);
+
+    let (ptr, layout) = alloc(&heap, 100, 8);
+    unsafe {
+        heap.lock().dealloc(ptr, layout);
+        heap.lock().dealloc(ptr, layout);
+    }
+}
+
+#[test]
+#[should_panic(expected = "allocated with size 100")]
+fn layout_mismatch() {
+    let memory = Memory::new(SIZE);
+    let heap = heap(&memory);
+
+    let (ptr, _) = alloc(&heap, 100, 8);
+    let layout = Layout::from_size_align(200, 8).unwrap();
+    unsafe { heap.lock().dealloc(ptr, layout) };
+}
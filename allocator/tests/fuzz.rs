@@ -0,0 +1,128 @@
+//! Randomized allocation and deallocation against the invariants of the heap.
+
+mod common;
+
+use {
+    common::{check_invariants, Memory, Rng},
+    core::{alloc::Layout, ptr::NonNull},
+    ingram_allocator::{HoleList, LockedHeap},
+};
+
+const SIZE: usize = 1 << 20;
+const SEEDS: u64 = 16;
+const STEPS: usize = 4_000;
+const MAX_LIVE: usize = 256;
+
+/// A live allocation, filled with its own tag to detect overlapping blocks.
+struct Block {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    tag: u8,
+}
+
+impl Block {
+    fn fill(&self) {
+        unsafe { self.ptr.as_ptr().write_bytes(self.tag, self.layout.size()) };
+    }
+
+    fn check(&self) {
+        let bytes = unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) };
+        assert!(
+            bytes.iter().all(|&byte| byte == self.tag),
+            "block at {:p} was overwritten",
+            self.ptr
+        );
+    }
+}
+
+fn random_layout(rng: &mut Rng) -> Layout {
+    let size = match rng.range(0..10) {
+        0 => rng.range(4096..32768),
+        1..=3 => rng.range(256..4096),
+        _ => rng.range(1..256),
+    };
+    let align = 1 << rng.range(0..13);
+    Layout::from_size_align(size, align).unwrap()
+}
+
+#[test]
+fn hole_list() {
+    for seed in 1..=SEEDS {
+        let memory = Memory::new(SIZE);
+        let mut holes = unsafe { HoleList::new(memory.bottom, memory.size) };
+        let mut rng = Rng::new(seed);
+        let mut live: Vec<(Block, Layout)> = Vec::new();
+
+        for step in 0..STEPS {
+            if live.len() < MAX_LIVE && rng.range(0..3) != 0 {
+                let layout = random_layout(&mut rng);
+                if let Ok((ptr, aligned)) = holes.allocate_first_fit(layout) {
+                    assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+                    let block = Block {
+                        ptr,
+                        layout,
+                        tag: step as u8,
+                    };
+                    block.fill();
+                    live.push((block, aligned));
+                }
+            } else if !live.is_empty() {
+                let (block, _) = live.swap_remove(rng.range(0..live.len()));
+                block.check();
+                unsafe { holes.deallocate(block.ptr, block.layout) };
+            }
+
+            let blocks = live
+                .iter()
+                .map(|(block, aligned)| (block.ptr.as_ptr() as usize, aligned.size()))
+                .collect::<Vec<_>>();
+            check_invariants(&holes, &memory, &blocks);
+        }
+
+        for (block, _) in live.drain(..) {
+            block.check();
+            unsafe { holes.deallocate(block.ptr, block.layout) };
+        }
+        assert_eq!(
+            holes.iter().collect::<Vec<_>>(),
+            [(memory.bottom, memory.size)],
+            "seed {}: freed heap is not a single hole",
+            seed
+        );
+    }
+}
+
+#[test]
+fn heap_with_slabs() {
+    for seed in 1..=SEEDS {
+        let memory = Memory::new(SIZE);
+        let heap = LockedHeap::empty();
+        unsafe { heap.lock().init(memory.bottom, memory.size) };
+        let mut rng = Rng::new(seed);
+        let mut live: Vec<Block> = Vec::new();
+
+        for step in 0..STEPS {
+            if live.len() < MAX_LIVE && rng.range(0..3) != 0 {
+                let layout = random_layout(&mut rng);
+                if let Ok(ptr) = heap.lock().alloc(layout) {
+                    assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+                    let block = Block {
+                        ptr,
+                        layout,
+                        tag: step as u8,
+                    };
+                    block.fill();
+                    live.push(block);
+                }
+            } else if !live.is_empty() {
+                let block = live.swap_remove(rng.range(0..live.len()));
+                block.check();
+                unsafe { heap.lock().dealloc(block.ptr, block.layout) };
+            }
+        }
+
+        let heap = heap.lock();
+        assert!(heap.used() <= heap.size());
+        live.iter().for_each(Block::check);
+    }
+}
@@ -0,0 +1,245 @@
+mod common;
+
+use {
+    common::{check_invariants, Memory},
+    core::{alloc::Layout, ptr::NonNull},
+    ingram_allocator::{HoleList, LockedHeap, Slabs},
+};
+
+const SIZE: usize = 4096;
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+fn allocate(holes: &mut HoleList, size: usize, align: usize) -> (usize, usize) {
+    let (ptr, layout) = holes.allocate_first_fit(layout(size, align)).unwrap();
+    (ptr.as_ptr() as usize, layout.size())
+}
+
+unsafe fn deallocate(holes: &mut HoleList, (addr, size): (usize, usize)) {
+    holes.deallocate(NonNull::new(addr as *mut u8).unwrap(), layout(size, 1));
+}
+
+#[test]
+fn empty() {
+    let mut holes = HoleList::empty();
+    assert!(holes.allocate_first_fit(layout(8, 8)).is_err());
+    assert_eq!(holes.iter().count(), 0);
+}
+
+#[test]
+fn allocate_whole_heap() {
+    let memory = Memory::new(SIZE);
+    let mut holes = unsafe { HoleList::new(memory.bottom, memory.size) };
+
+    let block = allocate(&mut holes, SIZE, 1);
+    assert_eq!(block, (memory.bottom, SIZE));
+    assert_eq!(holes.iter().count(), 0);
+    assert!(holes.allocate_first_fit(layout(1, 1)).is_err());
+
+    unsafe { deallocate(&mut holes, block) };
+    assert_eq!(holes.iter().collect::<Vec<_>>(), [(memory.bottom, SIZE)]);
+}
+
+#[test]
+fn small_sizes_are_rounded_up() {
+    let memory = Memory::new(SIZE);
+    let mut holes = unsafe { HoleList::new(memory.bottom, memory.size) };
+
+    let block = allocate(&mut holes, 1, 1);
+    assert_eq!(block.1, HoleList::min_size());
+    check_invariants(&holes, &memory, &[block]);
+}
+
+#[test]
+fn front_padding_becomes_a_hole() {
+    let memory = Memory::new(SIZE);
+    let mut holes = unsafe { HoleList::new(memory.bottom, memory.size) };
+
+    let first = allocate(&mut holes, 16, 1);
+    let aligned = allocate(&mut holes, 64, 1024);
+    assert_eq!(aligned.0 % 1024, 0);
+    assert_eq!(holes.iter().next().unwrap().0, first.0 + first.1);
+    check_invariants(&holes, &memory, &[first, aligned]);
+}
+
+#[test]
+fn too_small_remainder_is_skipped() {
+    let memory = Memory::new(SIZE);
+    let mut holes = unsafe { HoleList::new(memory.bottom, memory.size) };
+
+    // leave a hole that is one word too large for the next request
+    let a = allocate(&mut holes, 64, 8);
+    let b = allocate(&mut holes, 64 + 8, 8);
+    let c = allocate(&mut holes, 64, 8);
+    unsafe { deallocate(&mut holes, b) };
+
+    let d = allocate(&mut holes, 64, 8);
+    assert!(
+        d.0 > c.0,
+        "a remainder smaller than min_size must not be created"
+    );
+    check_invariants(&holes, &memory, &[a, c, d]);
+}
+
+#[test]
+fn deallocate_merges_with_neighbours() {
+    let memory = Memory::new(SIZE);
+    let mut holes = unsafe { HoleList::new(memory.bottom, memory.size) };
+
+    let blocks = (0..5)
+        .map(|_| allocate(&mut holes, 64, 8))
+        .collect::<Vec<_>>();
+
+    // no neighbour
+    unsafe { deallocate(&mut holes, blocks[1]) };
+    unsafe { deallocate(&mut holes, blocks[3]) };
+    check_invariants(&holes, &memory, &[blocks[0], blocks[2], blocks[4]]);
+    assert_eq!(holes.iter().count(), 3);
+
+    // fills the gap between two holes
+    unsafe { deallocate(&mut holes, blocks[2]) };
+    check_invariants(&holes, &memory, &[blocks[0], blocks[4]]);
+    assert_eq!(holes.iter().count(), 2);
+
+    // right before the next hole
+    unsafe { deallocate(&mut holes, blocks[0]) };
+    check_invariants(&holes, &memory, &[blocks[4]]);
+    assert_eq!(holes.iter().count(), 2);
+
+    // right behind the previous hole
+    unsafe { deallocate(&mut holes, blocks[4]) };
+    assert_eq!(holes.iter().collect::<Vec<_>>(), [(memory.bottom, SIZE)]);
+}
+
+#[test]
+#[should_panic(expected = "double free")]
+fn double_free() {
+    let memory = Memory::new(SIZE);
+    let mut holes = unsafe { HoleList::new(memory.bottom, memory.size) };
+
+    let _a = allocate(&mut holes, 64, 8);
+    let b = allocate(&mut holes, 64, 8);
+    let _c = allocate(&mut holes, 64, 8);
+    unsafe { deallocate(&mut holes, b) };
+    unsafe { deallocate(&mut holes, b) };
+}
+
+#[test]
+fn slab_classes() {
+    assert_eq!(Slabs::class(&layout(1, 1)), Some(0));
+    assert_eq!(Slabs::class(&layout(8, 8)), Some(0));
+    assert_eq!(Slabs::class(&layout(9, 1)), Some(1));
+    assert_eq!(Slabs::class(&layout(8, 64)), Some(3));
+    assert_eq!(Slabs::class(&layout(2048, 8)), Some(8));
+    assert_eq!(Slabs::class(&layout(2049, 8)), None);
+    assert_eq!(Slabs::class(&layout(8, 4096)), None);
+
+    for class in 0..=8 {
+        let size = Slabs::class_size(class);
+        assert_eq!(Slabs::class(&layout(size, 1)), Some(class));
+    }
+}
+
+#[test]
+#[cfg(not(feature = "first-fit"))]
+fn slab_usage() {
+    const SIZE: usize = 1 << 20;
+
+    let memory = Memory::new(SIZE);
+    let heap = LockedHeap::empty();
+    unsafe { heap.lock().init(memory.bottom, SIZE) };
+
+    let (_, before) = heap.lock().slabs();
+    let small = layout(24, 8);
+    let ptr = heap.lock().alloc(small).unwrap();
+
+    let (size, used) = heap.lock().slabs();
+    assert!(used >= before + 32);
+    assert!(size >= used && size % (64 * 1024) == 0);
+    // the whole chunk counts as used by the heap
+    assert!(heap.lock().used() >= size);
+
+    unsafe { heap.lock().dealloc(ptr, small) };
+    assert_eq!(heap.lock().slabs(), (size, before));
+}
+
+#[test]
+fn extend_heap() {
+    // large enough for the records of the `debug` feature
+    const SIZE: usize = 1 << 20;
+
+    let memory = Memory::new(SIZE);
+    let heap = LockedHeap::empty();
+    unsafe { heap.lock().init(memory.bottom, SIZE / 2) };
+
+    let big = layout(SIZE / 2 + 1024, 8);
+    assert!(heap.lock().alloc(big).is_err());
+
+    unsafe { heap.lock().extend(SIZE / 2) };
+    assert_eq!(heap.lock().size(), SIZE);
+    assert_eq!(heap.lock().top(), memory.top());
+    let ptr = heap.lock().alloc(big).unwrap();
+    unsafe { heap.lock().dealloc(ptr, big) };
+}
+
+#[test]
+fn shrink_heap() {
+    const SIZE: usize = 1 << 20;
+
+    let memory = Memory::new(SIZE);
+    let heap = LockedHeap::empty();
+    unsafe { heap.lock().init(memory.bottom, SIZE) };
+
+    let big = layout(SIZE / 2, 8);
+    let ptr = heap.lock().alloc(big).unwrap();
+    let free = heap.lock().free_top();
+    assert!(free > 0 && free <= SIZE / 2);
+
+    // more than what is free, and a remainder too small for a hole
+    assert!(!heap.lock().shrink(free + 1));
+    assert!(!heap.lock().shrink(free - 1));
+
+    assert!(heap.lock().shrink(free / 2));
+    assert_eq!(heap.lock().size(), SIZE - free / 2);
+    assert_eq!(heap.lock().free_top(), free - free / 2);
+
+    unsafe { heap.lock().dealloc(ptr, big) };
+    assert!(heap.lock().shrink(SIZE / 4));
+    assert_eq!(heap.lock().top(), memory.top() - free / 2 - SIZE / 4);
+}
+
+#[test]
+#[cfg(not(feature = "first-fit"))]
+fn slab_tags() {
+    use ingram_allocator::Heap;
+
+    const SIZE: usize = 1 << 20;
+
+    let memory = Memory::new(SIZE);
+    let heap = LockedHeap::empty();
+    unsafe { heap.lock().init(memory.bottom, SIZE) };
+
+    assert!(Heap::is_tagged(&layout(1024, 8)));
+    assert!(!Heap::is_tagged(&layout(4096, 8)));
+
+    for size in [8, 16, 24, 512, 1024] {
+        let small = layout(size, 8);
+        let blocks: Vec<_> = (0..100)
+            .map(|_| heap.lock().alloc(small).unwrap())
+            .collect();
+        for (i, &ptr) in blocks.iter().enumerate() {
+            let tag = Heap::tag(ptr, &small).unwrap();
+            unsafe { *tag.as_ptr() = i as u8 };
+            // the block is not made larger, and no tag overlaps a block
+            unsafe { ptr.as_ptr().write_bytes(0xff, size) };
+        }
+        for (i, &ptr) in blocks.iter().enumerate() {
+            let tag = Heap::tag(ptr, &small).unwrap();
+            assert_eq!(unsafe { *tag.as_ptr() }, i as u8);
+            unsafe { heap.lock().dealloc(ptr, small) };
+        }
+    }
+    assert!(Heap::tag(NonNull::dangling(), &layout(4096, 8)).is_none());
+}
@@ -3,21 +3,329 @@ export {};
 interface IKernel {
   RTC_CENTURY_REG: number;
 
-  inb: (port: number) => number;
-  outb: (port: number, value: number) => void;
-  inw: (port: number) => number;
-  outw: (port: number, value: number) => void;
-  inl: (port: number) => number;
-  outl: (port: number, value: number) => void;
+  /**
+   * Grants the ports `[base, base + count)` to `owner` ("js" by default). Throws a RangeError
+   * for an invalid range, and an Error if a port is held by the kernel or another grant.
+   * The kernel holds the legacy devices and PCI configuration ports, the ACPI registers of the
+   * FADT, and the ports power off and reboot fall back to, the keyboard controller included.
+   */
+  requestPorts: (base: number, count: number, owner?: string) => Ports;
+  portGrants: () => PortGrant[];
+
+  console: IConsole;
+  memory: IMemory;
+  /**
+   * Identity maps `[addr, addr + length)` uncached, leaving pages that already are. Throws a
+   * `RangeError` if the range overlaps RAM, if a page is mapped elsewhere, if physical memory
+   * runs out for the page tables, or if `length` is over 1GiB. With `buffer`, `addr` must be a
+   * nonzero multiple of 4KiB. The region is released when it is collected or `release` is
+   * called.
+   */
+  mapMmio: (
+    addr: number,
+    length: number,
+    options?: { buffer?: boolean },
+  ) => Mmio;
+  /**
+   * Allocates a zeroed, physically contiguous buffer, aligned to `align` (a power of two) and to
+   * 4KiB.
+   * The frames are freed when the returned object is collected or `free` is called, which
+   * detaches `buffer`, so keep the object itself alive while a device or `buffer` is used.
+   * Throws a `RangeError` once 256 buffers are live.
+   */
+  allocDma: (
+    size: number,
+    options?: { align?: number; below4G?: boolean },
+  ) => DmaBuffer;
+  memoryUsage: () => MemoryUsage;
+  cpu: { readonly features: CpuFeatures };
+  acpi: IAcpi;
+  pci: IPci;
+
+  /** Powers off right away, see `requestShutdown` to let processes finish. */
+  poweroff: () => never;
+  /** Resets right away, see `requestShutdown` to let processes finish. */
+  reboot: () => never;
 
   spawn: (code: ArrayBuffer) => Process;
+  /** Creates a context whose globals persist between evaluations. */
+  createRepl: () => Repl;
   shouldSchedule: () => boolean;
+
+  /** Calls `listener` as a microtask every time `event` happens. */
+  on: (event: KernelEvent, listener: () => void) => void;
+}
+
+/**
+ * - `powerbutton`: the ACPI power button was pressed.
+ * - `input`: console input became readable.
+ * - `interrupt`: Ctrl-C was typed on the console, in canonical mode.
+ * - `drain`: the console output buffer is empty again after `write` could not take everything.
+ */
+type KernelEvent = "powerbutton" | "input" | "interrupt" | "drain";
+
+/** The serial console, which echoes and edits the input a line at a time in canonical mode. */
+interface IConsole {
+  /**
+   * Takes up to `max` bytes of the readable input, an empty string if there is none. A character
+   * whose UTF-8 bytes do not all fit is left for the next read.
+   */
+  read: (max?: number) => string;
+  /**
+   * Writes `text` as it is, without the newline of `console.log`. Returns how many of its UTF-16
+   * code units were buffered, always whole characters; wait for the `drain` event to write
+   * `text.slice(written)`.
+   */
+  write: (text: string) => number;
+  setMode: (mode: ConsoleMode) => void;
+  mode: () => ConsoleMode;
+  setEcho: (echo: boolean) => void;
+  /** Bytes dropped because they came faster than they were processed. */
+  overruns: () => number;
 }
 
+/** In `raw` mode, every byte is readable as soon as it is received, without echo or editing. */
+type ConsoleMode = "canonical" | "raw";
+
 declare global {
   const Kernel: IKernel;
+  /** The programs of `user/`, by file name without extension */
+  const USER_NAMES: string[];
+  const USER_CODES: Array<number[]>;
 }
 
 interface Process {
+  readonly pid: number;
   steps(): boolean;
 }
+
+interface Repl {
+  /** Starts evaluating `source`, call `steps` until it returns the result. */
+  eval(source: string): void;
+  /**
+   * Runs a slice of the evaluation. Returns `undefined` while it is not done, then the
+   * inspected result or the thrown error as `output`.
+   */
+  steps(): { ok: boolean; output: string } | undefined;
+  /**
+   * Abandons the evaluation. The global variables and functions of the previous evaluations are
+   * kept, with the values the abandoned one left them, but not their `let`, `const` and `class`
+   * declarations. Objects kept keep the prototypes they had, so `instanceof` a builtin is false.
+   */
+  interrupt(): void;
+  /** Returns `false` if `source` ends before a statement does. */
+  isComplete(source: string): boolean;
+}
+
+interface IMemory {
+  /**
+   * Maps `[addr, addr + length)` to new frames, which must not be mapped yet. Defaults to
+   * read-only and no-execute. Throws a `RangeError` for pages both writable and executable.
+   */
+  alloc: (
+    addr: number,
+    length: number,
+    options?: { writable?: boolean; executable?: boolean },
+  ) => void;
+  /** Releases the region of `Kernel.mapMmio` at `[addr, addr + length)`, see `Mmio.release`. */
+  unmap: (addr: number, length: number) => void;
+  /** Unmaps and frees a range mapped by `alloc`, which must be given as it was allocated. */
+  free: (addr: number, length: number) => void;
+  /**
+   * Changes the pages of a range mapped by `alloc`. Defaults to read-only and no-execute.
+   * Throws a `RangeError` for other ranges, and for pages both writable and executable.
+   */
+  protect: (
+    addr: number,
+    length: number,
+    options?: { writable?: boolean; executable?: boolean },
+  ) => void;
+  /** Returns the physical address, or `null` if `addr` is not mapped. */
+  translate: (addr: number) => number | null;
+  mappings: () => Mapping[];
+  /** Prints the live allocations of each process and of the kernel over serial. */
+  dumpAllocations: () => void;
+}
+
+interface IAcpi {
+  /**
+   * Signatures of the tables, the RSDT/XSDT entries first, then the DSDT and SSDTs. Empty if the
+   * firmware tables are invalid.
+   */
+  tables: () => string[];
+  /**
+   * Returns the `index`th table with `signature`, header included, or `null` if there is none.
+   *
+   * The engine has no read-only `ArrayBuffer`, so each call returns a fresh copy instead: writing
+   * to it never changes the firmware tables, and parsers can reuse it as scratch space.
+   */
+  getTable: (signature: string, index?: number) => ArrayBuffer | null;
+  readonly capabilities: PlatformCapabilities;
+}
+
+/** Volatile accesses, which must be aligned and in bounds. */
+interface Mmio {
+  readonly address: number;
+  readonly length: number;
+  /**
+   * Backed directly by the region, if asked for with `buffer`. Accesses through views are
+   * bounds checked but not volatile.
+   */
+  readonly buffer?: ArrayBuffer;
+  read8: (offset: number) => number;
+  read16: (offset: number) => number;
+  read32: (offset: number) => number;
+  read64: (offset: number) => bigint;
+  write8: (offset: number, value: number) => void;
+  write16: (offset: number, value: number) => void;
+  write32: (offset: number, value: number) => void;
+  /** Throws a `RangeError` if `value` is negative or does not fit in 64 bits. */
+  write64: (offset: number, value: bigint | number) => void;
+  /**
+   * Detaches `buffer` and unmaps the pages mapped for this region, except the ones another
+   * region uses. Accesses throw a `TypeError` afterwards.
+   */
+  release: () => void;
+}
+
+/** Accesses at offsets from `base`, which must be in range. */
+interface Ports {
+  readonly base: number;
+  readonly count: number;
+  inb: (offset: number) => number;
+  outb: (offset: number, value: number) => void;
+  inw: (offset: number) => number;
+  outw: (offset: number, value: number) => void;
+  inl: (offset: number) => number;
+  outl: (offset: number, value: number) => void;
+  /** Fills `buffer` with `rep ins`, returning the count of values read. */
+  insb: (offset: number, buffer: ArrayBuffer | ArrayBufferView) => number;
+  insw: (offset: number, buffer: ArrayBuffer | ArrayBufferView) => number;
+  insl: (offset: number, buffer: ArrayBuffer | ArrayBufferView) => number;
+  /** Writes `buffer` with `rep outs`, returning the count of values written. */
+  outsb: (offset: number, buffer: ArrayBuffer | ArrayBufferView) => number;
+  outsw: (offset: number, buffer: ArrayBuffer | ArrayBufferView) => number;
+  outsl: (offset: number, buffer: ArrayBuffer | ArrayBufferView) => number;
+  /**
+   * Runs the accesses in one call, returning what each read, `undefined` for writes. Offsets
+   * are from `base` like the single accesses. This is a method of the grant rather than a
+   * `Kernel.portOps` taking port numbers, so that a batch cannot reach ports it was not granted.
+   */
+  portOps: (ops: PortOp[]) => (number | undefined)[];
+  /** Gives the ports back, which also happens when the handle is collected. */
+  release: () => void;
+}
+
+type PortOp =
+  | ["inb" | "inw" | "inl", number]
+  | ["outb" | "outw" | "outl", number, number];
+
+interface PortGrant {
+  base: number;
+  count: number;
+  owner: string;
+}
+
+interface DmaBuffer {
+  buffer: ArrayBuffer;
+  physicalAddress: number;
+  /** Detaches `buffer` and frees the frames now, instead of when this object is collected. */
+  free: () => void;
+}
+
+interface IPci {
+  /** The functions enumerated at boot, bridges included. */
+  devices: () => PciDevice[];
+}
+
+interface PciDevice {
+  segment: number;
+  bus: number;
+  device: number;
+  function: number;
+  vendorId: number;
+  deviceId: number;
+  class: number;
+  subclass: number;
+  progIf: number;
+  revision: number;
+  /** Without the multi-function bit. */
+  headerType: number;
+  interruptLine: number;
+  /** 1 to 4 for INTA# to INTD#, 0 if none. */
+  interruptPin: number;
+  /** Only the implemented BARs, sized at boot. */
+  bars: PciBar[];
+  capabilities: PciCapability[];
+  /** PCI Express capabilities, only found with ECAM. */
+  extendedCapabilities: PciCapability[];
+}
+
+interface PciBar {
+  index: number;
+  kind: "memory" | "io";
+  address: number;
+  size: number;
+  prefetchable: boolean;
+  is64: boolean;
+}
+
+interface PciCapability {
+  id: number;
+  /** Offset in the configuration space. */
+  offset: number;
+}
+
+interface Mapping {
+  virt: number;
+  phys: number;
+  size: number;
+  writable: boolean;
+  executable: boolean;
+  cached: boolean;
+}
+
+/** What the ACPI tables describe. */
+interface PlatformCapabilities {
+  /** Otherwise the PIT calibrates the local APIC timer. */
+  pmTimer: boolean;
+  hpet: boolean;
+  /** Otherwise the 8259 PIC and the PIT are used. */
+  apic: boolean;
+  /** Memory-mapped PCI configuration space, from the MCFG. */
+  pciExpress: boolean;
+  processorInfo: boolean;
+  /** CMOS index of the RTC century register. */
+  century: number | null;
+}
+
+/** Protection features enabled at boot. */
+interface CpuFeatures {
+  nx: boolean;
+  smep: boolean;
+  smap: boolean;
+  umip: boolean;
+  writeProtect: boolean;
+}
+
+/** Sizes in bytes. */
+interface MemoryUsage {
+  heapTotal: number;
+  heapUsed: number;
+  heapFree: number;
+  /** Number of free heap blocks, a measure of fragmentation. */
+  freeBlocks: number;
+  largestFreeBlock: number;
+  /** Size of the chunks carved into small blocks, included in `heapUsed`. */
+  slabTotal: number;
+  /** Size of the small blocks in use, a part of `slabTotal`. */
+  slabUsed: number;
+  /**
+   * Heap bytes freed by the last full garbage collection forced by the kernel, e.g. when out of
+   * memory. The engine's own collections are not reported.
+   */
+  gcLastFreed: number;
+  physicalTotal: number;
+  physicalUsed: number;
+  physicalFree: number;
+}
@@ -1,7 +1,10 @@
 /// <reference path="./index.d.ts" />
 
 import { getDate } from "./rtc.ts";
-import { checkAllBuses } from "./pci.ts";
+import { printDevices } from "./pci.ts";
+import { requestShutdown } from "./power.ts";
+import { spawn, wake } from "./scheduler.ts";
+import { startRepl } from "./repl.ts";
 
 const date = getDate();
 
@@ -9,21 +12,16 @@ console.log(
   `${date.year}-${date.month}-${date.day} ${date.hour}:${date.minute}:${date.second}`,
 );
 
-checkAllBuses();
+printDevices();
 
-declare const USER_CODES: Array<number[]>;
-const procs = USER_CODES.map((i) => Kernel.spawn(Uint8Array.from(i).buffer));
+// `system_powerdown` in the QEMU monitor presses the power button
+Kernel.on("powerbutton", () => {
+  requestShutdown("poweroff");
+  wake();
+});
 
-let idx = 0;
-const schedule = () => {
-  if (!procs[idx].steps()) {
-    procs.splice(idx, 1);
-    if (procs.length === 0) return;
-    if (idx === procs.length) idx = 0;
-  }
-  if (Kernel.shouldSchedule()) {
-    idx = (idx + 1) % procs.length;
-  }
-  queueMicrotask(schedule);
-};
-queueMicrotask(schedule);
+USER_CODES.forEach((code, i) =>
+  spawn(USER_NAMES[i], Uint8Array.from(code).buffer)
+);
+
+startRepl();
@@ -0,0 +1,43 @@
+/// <reference path="./index.d.ts" />
+
+export type ShutdownKind = "poweroff" | "reboot";
+
+/** Schedule rounds the processes get to finish once a shutdown is requested. */
+const GRACE_ROUNDS = 100_000;
+
+const hooks: Array<(kind: ShutdownKind) => void> = [];
+let pending: ShutdownKind | null = null;
+let rounds = 0;
+
+/** Runs `hook` right before powering off or rebooting. */
+export function onShutdown(hook: (kind: ShutdownKind) => void) {
+  hooks.push(hook);
+}
+
+/**
+ * Asks for a shutdown once the running processes have finished, or after
+ * {@link GRACE_ROUNDS} schedule rounds.
+ */
+export function requestShutdown(kind: ShutdownKind) {
+  if (pending === null) pending = kind;
+}
+
+/** Called by the scheduler every round, returns whether it should shut down now. */
+export function shutdownDue(processes: number) {
+  if (pending === null) return false;
+  return processes === 0 || ++rounds >= GRACE_ROUNDS;
+}
+
+export function shutdown(): never {
+  const kind = pending ?? "poweroff";
+  for (const hook of hooks) {
+    try {
+      hook(kind);
+    } catch (err) {
+      console.log(`shutdown hook failed: ${err}`);
+    }
+  }
+
+  if (kind === "reboot") Kernel.reboot();
+  Kernel.poweroff();
+}
@@ -0,0 +1,149 @@
+/// <reference path="./index.d.ts" />
+
+import { requestShutdown } from "./power.ts";
+import { kill, processes, spawn, wake } from "./scheduler.ts";
+
+const PROMPT = "> ";
+const CONTINUATION = "... ";
+
+const repl = Kernel.createRepl();
+/** Received input that does not end a line yet */
+let pending = "";
+/** The lines of a statement that needs more of them */
+let source = "";
+/** Counts the evaluations, so that the steps of an interrupted one stop */
+let evaluation = 0;
+let running = false;
+
+function prompt() {
+  Kernel.console.write(source === "" ? PROMPT : CONTINUATION);
+}
+
+const commands: Record<string, [string, (args: string[]) => void]> = {
+  help: ["list the commands", () => {
+    for (const [name, [help]] of Object.entries(commands)) {
+      console.log(`.${name.padEnd(10)}${help}`);
+    }
+  }],
+  ps: ["list the processes", () => {
+    console.log("PID\tNAME");
+    for (const { pid, name } of processes()) console.log(`${pid}\t${name}`);
+  }],
+  kill: ["kill the process of a pid", ([pid]) => {
+    if (!kill(Number(pid))) console.log(`no process ${pid}`);
+  }],
+  mem: ["show the memory usage", () => {
+    const usage = Kernel.memoryUsage();
+    const kib = (bytes: number) => `${Math.round(bytes / 1024)}KiB`;
+    console.log(
+      `heap ${kib(usage.heapUsed)}/${kib(usage.heapTotal)}, ` +
+        `${usage.freeBlocks} free blocks, largest ${
+          kib(usage.largestFreeBlock)
+        }`,
+    );
+    console.log(
+      `physical ${kib(usage.physicalUsed)}/${kib(usage.physicalTotal)}`,
+    );
+  }],
+  spawn: ["spawn a program of user/, by name", ([name]) => {
+    const i = USER_NAMES.indexOf(name);
+    if (i === -1) {
+      console.log(`no program ${name}, try one of ${USER_NAMES.join(" ")}`);
+      return;
+    }
+    const pid = spawn(name, Uint8Array.from(USER_CODES[i]).buffer);
+    console.log(`spawned ${pid}`);
+  }],
+  poweroff: ["power off once the processes finish", () => {
+    requestShutdown("poweroff");
+    wake();
+  }],
+  reboot: ["reboot once the processes finish", () => {
+    requestShutdown("reboot");
+    wake();
+  }],
+};
+
+function runCommand(line: string) {
+  const [name, ...args] = line.split(/\s+/);
+  const command = commands[name];
+  if (command === undefined) {
+    console.log(`unknown command .${name}, see .help`);
+    return;
+  }
+  command[1](args);
+}
+
+function handleLine(line: string) {
+  if (source === "" && line.trimStart().startsWith(".")) {
+    runCommand(line.trim().slice(1));
+    return;
+  }
+
+  source += `${line}\n`;
+  if (source.trim() === "") {
+    source = "";
+    return;
+  }
+  if (!repl.isComplete(source)) return;
+
+  repl.eval(source);
+  source = "";
+  running = true;
+  const id = ++evaluation;
+  queueMicrotask(() => evaluate(id));
+}
+
+/** Runs a slice of the evaluation at a time, so that processes and input go on meanwhile. */
+function evaluate(id: number) {
+  if (id !== evaluation) return;
+
+  const result = repl.steps();
+  if (result === undefined) {
+    queueMicrotask(() => evaluate(id));
+    return;
+  }
+
+  running = false;
+  console.log(result.ok ? result.output : `Uncaught ${result.output}`);
+  prompt();
+  handleInput();
+}
+
+/** Handles the complete lines received, until one starts an evaluation. */
+function handleInput() {
+  let end;
+  while (!running && (end = pending.indexOf("\n")) !== -1) {
+    const line = pending.slice(0, end);
+    pending = pending.slice(end + 1);
+    handleLine(line);
+    if (!running) prompt();
+  }
+}
+
+/**
+ * Evaluates the lines typed on the console in a context of its own, or runs the
+ * commands that start with a dot, see `.help`.
+ */
+export function startRepl() {
+  console.log("Type .help for the commands");
+  prompt();
+
+  Kernel.on("input", () => {
+    pending += Kernel.console.read();
+    handleInput();
+  });
+
+  // The console has dropped the line being typed, and the evaluation stops
+  Kernel.on("interrupt", () => {
+    if (running) {
+      repl.interrupt();
+      running = false;
+      evaluation++;
+      pending = "";
+      console.log("Interrupted, the global variables were kept");
+    }
+    source = "";
+    prompt();
+  });
+}
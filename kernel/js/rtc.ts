@@ -20,9 +20,11 @@ const enum REGISTER {
   STATUS_B = 0x0B,
 }
 
+const cmos = Kernel.requestPorts(CMOS.ADDRESS, 2, "rtc");
+
 function getRegister(reg: number) {
-  Kernel.outb(CMOS.ADDRESS, reg);
-  return Kernel.inb(CMOS.DATA);
+  cmos.outb(0, reg);
+  return cmos.inb(1);
 }
 
 export function getDate() {
@@ -32,7 +34,9 @@ export function getDate() {
   let day = getRegister(REGISTER.DAY_OF_MONTH);
   let month = getRegister(REGISTER.MONTH);
   let year = getRegister(REGISTER.YEAR);
-  let century = getRegister(Kernel.RTC_CENTURY_REG);
+  let century = Kernel.RTC_CENTURY_REG !== 0
+    ? getRegister(Kernel.RTC_CENTURY_REG)
+    : 0;
 
   const format = getRegister(REGISTER.STATUS_B);
 
@@ -57,7 +61,7 @@ export function getDate() {
   if (Kernel.RTC_CENTURY_REG !== 0) {
     year += century * 100;
   } else {
-    year += (2022 / 100) * 100;
+    year += Math.floor(2022 / 100) * 100;
     if (year < 2022) year += 100;
   }
 
@@ -0,0 +1,61 @@
+/// <reference path="./index.d.ts" />
+
+import { shutdown, shutdownDue } from "./power.ts";
+
+interface Task {
+  name: string;
+  proc: Process;
+}
+
+const tasks: Task[] = [];
+let idx = 0;
+let running = false;
+
+function remove(i: number) {
+  tasks.splice(i, 1);
+  if (i < idx) idx--;
+  if (idx >= tasks.length) idx = 0;
+}
+
+/** Runs the processes in turn, and stops when there are none left. */
+function schedule() {
+  if (shutdownDue(tasks.length)) shutdown();
+  if (tasks.length === 0) {
+    running = false;
+    return;
+  }
+
+  if (!tasks[idx].proc.steps()) {
+    remove(idx);
+  } else if (Kernel.shouldSchedule()) {
+    idx = (idx + 1) % tasks.length;
+  }
+  queueMicrotask(schedule);
+}
+
+/** Restarts the scheduler, e.g. so that it sees a requested shutdown. */
+export function wake() {
+  if (running) return;
+  running = true;
+  queueMicrotask(schedule);
+}
+
+/** Spawns a process and returns its pid. */
+export function spawn(name: string, code: ArrayBuffer) {
+  const proc = Kernel.spawn(code);
+  tasks.push({ name, proc });
+  wake();
+  return proc.pid;
+}
+
+/** Drops the process, returns whether it existed. */
+export function kill(pid: number) {
+  const i = tasks.findIndex(({ proc }) => proc.pid === pid);
+  if (i === -1) return false;
+  remove(i);
+  return true;
+}
+
+export function processes() {
+  return tasks.map(({ name, proc }) => ({ pid: proc.pid, name }));
+}
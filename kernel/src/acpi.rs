@@ -1,52 +1,199 @@
 /// https://wiki.osdev.org/ACPI
 use {
-    crate::{constant::LOCAL_APIC_ID, memory::phys2virt, println},
+    crate::{constant::LOCAL_APIC_ID, log, memory::phys2virt},
     acpi::{
         fadt::Fadt,
         platform::{interrupt::Apic, PmTimer, ProcessorInfo},
-        sdt::Signature,
+        sdt::{SdtHeader, Signature},
         AcpiHandler, AcpiTables, HpetInfo, InterruptModel, PciConfigRegions, PhysicalMapping,
         PlatformInfo,
     },
-    core::ptr::NonNull,
+    alloc::vec::Vec,
+    core::{mem::size_of, ptr::NonNull},
+    spin::Once,
     x86_64::structures::paging::{PageSize, Size4KiB},
 };
 
-pub fn init(rsdp_addr: u64) -> (PmTimer, HpetInfo, Apic, PhysicalMapping<AcpiHdl, Fadt>) {
-    let acpi_tables = unsafe { AcpiTables::from_rsdp(AcpiHdl, rsdp_addr as usize) }.unwrap();
+/// The tables found from the RSDP, available after [`init`].
+pub static ACPI_TABLES: Once<AcpiTables<AcpiHdl>> = Once::new();
 
-    let fadt = unsafe { acpi_tables.get_sdt::<Fadt>(Signature::FADT) }
-        .unwrap()
-        .unwrap();
+/// What the firmware describes, available after [`init`]. Nothing if the tables cannot be read.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlatformCapabilities {
+    /// The ACPI PM timer, otherwise the PIT calibrates the local APIC timer
+    pub pm_timer: bool,
+    pub hpet: bool,
+    /// A local APIC and an I/O APIC, otherwise the 8259 PIC and the PIT are used
+    pub apic: bool,
+    /// Memory-mapped PCI configuration space, from the MCFG
+    pub pci_express: bool,
+    /// The processors are listed in the MADT
+    pub processor_info: bool,
+    /// The CMOS index of the RTC century register
+    pub century: Option<u8>,
+}
+
+pub static CAPABILITIES: Once<PlatformCapabilities> = Once::new();
+
+/// The regions of the MCFG, if [`PlatformCapabilities::pci_express`].
+pub static PCI_CONFIG_REGIONS: Once<PciConfigRegions> = Once::new();
+
+/// The components found in the tables, none if the firmware has no valid ones.
+pub struct Platform {
+    pub pm_timer: Option<PmTimer>,
+    pub hpet: Option<HpetInfo>,
+    pub apic: Option<Apic>,
+    pub fadt: Option<PhysicalMapping<AcpiHdl, Fadt>>,
+}
+
+/// Reads the tables from the RSDP. If they are invalid, logs why and carries on without them, with
+/// the PIC, the PIT and the fallbacks of [`crate::power`].
+pub fn init(rsdp_addr: u64) -> Platform {
+    let acpi_tables = match unsafe { AcpiTables::from_rsdp(AcpiHdl, rsdp_addr as usize) } {
+        Ok(acpi_tables) => acpi_tables,
+        Err(err) => {
+            log!("ACPI: invalid tables ({:?})", err);
+            log!(
+                "{:?}",
+                CAPABILITIES.call_once(PlatformCapabilities::default)
+            );
+            return Platform {
+                pm_timer: None,
+                hpet: None,
+                apic: None,
+                fadt: None,
+            };
+        }
+    };
+
+    let fadt = match unsafe { acpi_tables.get_sdt::<Fadt>(Signature::FADT) } {
+        Ok(Some(fadt)) => Some(fadt),
+        Ok(None) => {
+            log!("ACPI: no FADT");
+            None
+        }
+        Err(err) => {
+            log!("ACPI: invalid FADT ({:?})", err);
+            None
+        }
+    };
+
+    match PciConfigRegions::new(&acpi_tables) {
+        Ok(pci_config_regions) => {
+            log!("PCI: {:?}", pci_config_regions);
+            PCI_CONFIG_REGIONS.call_once(|| pci_config_regions);
+        }
+        Err(err) => log!("PCI: no MCFG ({:?})", err),
+    }
+
+    let (interrupt_model, processor_info, pm_timer) = match acpi_tables.platform_info() {
+        Ok(PlatformInfo {
+            power_profile,
+            interrupt_model,
+            processor_info,
+            pm_timer,
+        }) => {
+            log!("Power profile: {:?}", power_profile);
+            (interrupt_model, processor_info, pm_timer)
+        }
+        Err(err) => {
+            log!("No platform info ({:?})", err);
+            (InterruptModel::Unknown, None, None)
+        }
+    };
+
+    match &processor_info {
+        Some(ProcessorInfo {
+            boot_processor: processor,
+            application_processors: app_processor,
+        }) => {
+            assert_eq!(processor.local_apic_id, LOCAL_APIC_ID as u32);
+            assert!(app_processor.is_empty(), "Do not support multi-core");
+        }
+        None => log!("No processor info, assuming a single core"),
+    }
+
+    let hpet = HpetInfo::new(&acpi_tables).ok();
+    let apic = match interrupt_model {
+        InterruptModel::Apic(apic) if !apic.io_apics.is_empty() => Some(apic),
+        _ => None,
+    };
 
-    let pci_config_regions = PciConfigRegions::new(&acpi_tables).unwrap();
-    println!("PCI: {:?}", pci_config_regions);
+    let capabilities = CAPABILITIES.call_once(|| PlatformCapabilities {
+        pm_timer: pm_timer.is_some(),
+        hpet: hpet.is_some(),
+        apic: apic.is_some(),
+        pci_express: PCI_CONFIG_REGIONS.is_completed(),
+        processor_info: processor_info.is_some(),
+        century: fadt
+            .as_ref()
+            .map(|fadt| fadt.century)
+            .filter(|&century| century != 0),
+    });
+    log!("{:?}", capabilities);
 
-    let PlatformInfo {
-        power_profile,
-        interrupt_model,
-        processor_info,
+    ACPI_TABLES.call_once(|| acpi_tables);
+
+    Platform {
         pm_timer,
-    } = acpi_tables.platform_info().unwrap();
-
-    println!("Power profile: {:?}", power_profile);
-
-    let ProcessorInfo {
-        boot_processor: processor,
-        application_processors: app_processor,
-    } = processor_info.unwrap();
-    assert_eq!(processor.local_apic_id, LOCAL_APIC_ID as u32);
-    assert!(app_processor.is_empty(), "Do not support multi-core");
-
-    (
-        pm_timer.unwrap(),
-        HpetInfo::new(&acpi_tables).unwrap(),
-        match interrupt_model {
-            InterruptModel::Apic(apic) => apic,
-            _ => panic!("apic not supported"),
-        },
+        hpet,
+        apic,
         fadt,
-    )
+    }
+}
+
+/// A system description table, header included.
+#[derive(Debug, Clone, Copy)]
+pub struct Table {
+    pub signature: [u8; 4],
+    pub address: usize,
+    pub length: usize,
+}
+
+/// Lists the tables of the RSDT/XSDT, then the DSDT and the SSDTs, which may appear several
+/// times. Empty if [`init`] found no valid tables.
+pub fn tables() -> Vec<Table> {
+    let acpi_tables = match ACPI_TABLES.get() {
+        Some(acpi_tables) => acpi_tables,
+        None => return Vec::new(),
+    };
+    let header = size_of::<SdtHeader>();
+
+    let sdts = acpi_tables.sdts.iter().map(|(signature, sdt)| Table {
+        signature: signature_bytes(signature),
+        address: sdt.physical_address,
+        length: sdt.length as usize,
+    });
+    // The AML tables are recorded without their header
+    let aml = acpi_tables
+        .dsdt
+        .iter()
+        .map(|dsdt| (Signature::DSDT, dsdt))
+        .chain(acpi_tables.ssdts.iter().map(|ssdt| (Signature::SSDT, ssdt)))
+        .map(|(signature, aml)| Table {
+            signature: signature_bytes(&signature),
+            address: aml.address - header,
+            length: aml.length as usize + header,
+        });
+
+    sdts.chain(aml).collect()
+}
+
+fn signature_bytes(signature: &Signature) -> [u8; 4] {
+    signature.as_str().as_bytes().try_into().unwrap()
+}
+
+/// Returns a copy of the `index`th table with `signature`.
+pub fn read_table(signature: &[u8], index: usize) -> Option<Vec<u8>> {
+    let table = tables()
+        .into_iter()
+        .filter(|table| table.signature == signature)
+        .nth(index)?;
+
+    let mapping = unsafe { AcpiHdl.map_physical_region::<u8>(table.address, table.length) };
+    let bytes =
+        unsafe { core::slice::from_raw_parts(mapping.virtual_start().as_ptr(), table.length) };
+    Some(bytes.to_vec())
 }
 
 #[derive(Clone)]
@@ -1,25 +1,33 @@
-mod hole;
-mod linked_list_allocator;
+pub mod oom;
+pub mod trace;
 
 use {
     crate::{
         constant::{HEAP_END, HEAP_SIZE, HEAP_START},
-        memory::alloc_virt,
-        println,
+        log,
+        memory::{alloc_virt, FrameAllocatorAllSizes},
     },
-    hole::HoleList,
-    linked_list_allocator::LockedHeap,
-    x86_64::structures::paging::{FrameAllocator, Mapper, Size4KiB},
+    core::arch::x86_64::_rdtsc,
+    ingram_allocator::Heap,
+    trace::TracedHeap,
+    x86_64::structures::paging::{mapper::MapperAllSizes, FrameDeallocator, Size4KiB},
 };
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: TracedHeap = TracedHeap::new(Heap::empty());
 
+/// Must be called from `kernel_main`, see [`trace::init`].
+#[inline(always)]
 pub fn init(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    mapper: &mut impl MapperAllSizes,
+    frame_allocator: &mut (impl FrameAllocatorAllSizes + FrameDeallocator<Size4KiB>),
 ) {
-    alloc_virt(mapper, frame_allocator, HEAP_START, HEAP_END, None);
+    trace::init();
+
+    let start = unsafe { _rdtsc() };
+    alloc_virt(mapper, frame_allocator, HEAP_START, HEAP_END, None)
+        .unwrap_or_else(|addr| panic!("out of frames mapping the heap at {:#x}", addr));
+    let cycles = unsafe { _rdtsc() } - start;
 
     unsafe {
         ALLOCATOR
@@ -27,24 +35,44 @@ pub fn init(
             .init(HEAP_START as usize, HEAP_SIZE as usize)
     };
 
-    println!("Heap allocated, from {:#x} to {:#x}", HEAP_START, HEAP_END);
+    log!(
+        "Heap allocated, from {:#x} to {:#x} in {} cycles",
+        HEAP_START,
+        HEAP_END,
+        cycles
+    );
+
+    oom::rearm();
 }
 
-/// Align downwards. Returns the greatest x with alignment `align`
-/// so that x <= addr. The alignment must be a power of 2.
-fn align_down(addr: usize, align: usize) -> usize {
-    if align.is_power_of_two() {
-        addr & !(align - 1)
-    } else if align == 0 {
-        addr
-    } else {
-        panic!("`align` must be a power of 2");
-    }
+/// A snapshot of the heap usage.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Number of free blocks
+    pub holes: usize,
+    /// Size of the largest free block
+    pub largest_hole: usize,
+    /// Size of the slab chunks, counted in `used`
+    pub slab_size: usize,
+    /// Size of the slab blocks in use
+    pub slab_used: usize,
 }
 
-/// Align upwards. Returns the smallest x with alignment `align`
-/// so that x >= addr. The alignment must be a power of 2.
-#[inline]
-fn align_up(addr: usize, align: usize) -> usize {
-    align_down(addr + align - 1, align)
+pub fn stats() -> HeapStats {
+    let heap = ALLOCATOR.lock();
+    let (holes, largest_hole) = heap.holes();
+    let (slab_size, slab_used) = heap.slabs();
+
+    HeapStats {
+        size: heap.size(),
+        used: heap.used(),
+        free: heap.free(),
+        holes,
+        largest_hole,
+        slab_size,
+        slab_used,
+    }
 }
@@ -0,0 +1,261 @@
+//! Recovery from out of memory.
+//!
+//! When an allocation fails, the kernel tries in turn to
+//!
+//! 1. collect the garbage of the JS contexts,
+//! 2. grow the heap with more frames, up to a share of the physical memory,
+//! 3. kill the process with the most live bytes.
+//!
+//! The garbage is not collected in the middle of the allocation, which may come from the
+//! collector itself or from code holding borrows of collected objects. The collection is deferred
+//! to the main loop instead (see [`crate::deferred`]), and a reserve block, kept allocated at all
+//! times, is freed so that the allocations succeed until then. Only once the reserve is spent do
+//! allocations grow the heap right away.
+//!
+//! After the collection, the reserve is allocated again, growing the heap if it does not fit.
+//! If it still does not, the process with the most live bytes is killed. Its owner drops it
+//! before its next slice runs and calls [`rearm`]. The heap is then shrunk back as far as
+//! possible.
+//!
+//! The kernel only gives up, and panics, when an allocation fails with the reserve spent and the
+//! heap grown as far as it can.
+
+use {
+    super::{trace, ALLOCATOR},
+    crate::{
+        constant::{HEAP_MAX_SIZE, HEAP_SIZE, HEAP_START},
+        deferred, log,
+        memory::{alloc_virt, dealloc_virt, GlobalFrameAllocator, FRAME_ALLOCATOR, MAPPER},
+        sync::IrqMutex,
+    },
+    core::{
+        alloc::Layout,
+        ptr::NonNull,
+        sync::atomic::{AtomicBool, Ordering},
+    },
+    spin::{MutexGuard, Once},
+    x86_64::{
+        align_down, align_up,
+        structures::paging::{
+            mapper::TranslateResult, OffsetPageTable, PageSize, Size2MiB, Size4KiB, Translate,
+        },
+        VirtAddr,
+    },
+};
+
+/// Size of the reserve block.
+pub const RESERVE_SIZE: usize = 32 * 1024 * 1024;
+/// The heap grows by at least this many bytes at a time.
+const GROW_SIZE: usize = 64 * 1024 * 1024;
+/// The heap grows up to this share of the physical memory, in percent.
+const MAX_PHYSICAL_PERCENT: u64 = 50;
+
+static RESERVE: IrqMutex<Option<NonNull<u8>>> = IrqMutex::new(None);
+
+static COLLECTOR: Once<fn()> = Once::new();
+static KILLER: Once<fn(i32) -> bool> = Once::new();
+
+/// Set while recovering, allocations made by the hooks themselves can only grow the heap.
+static RECOVERING: AtomicBool = AtomicBool::new(false);
+
+/// Set while a collection is queued in [`deferred`].
+static COLLECTION_QUEUED: AtomicBool = AtomicBool::new(false);
+
+/// Set from killing a process until its owner calls [`rearm`].
+static KILLING: AtomicBool = AtomicBool::new(false);
+
+/// Sets the function that runs a full garbage collection.
+///
+/// It runs from the main loop after the heap ran out of memory, never in the middle of an
+/// allocation.
+pub fn set_collector(collect: fn()) {
+    COLLECTOR.call_once(|| collect);
+}
+
+/// Sets the function that kills a process by pid, returning whether the process will be
+/// dropped.
+///
+/// It runs from the main loop after a collection. The process must be dropped before it runs
+/// again, then [`rearm`] called.
+pub fn set_killer(kill: fn(i32) -> bool) {
+    KILLER.call_once(|| kill);
+}
+
+fn reserve_layout() -> Layout {
+    Layout::from_size_align(RESERVE_SIZE, Size2MiB::SIZE as usize).unwrap()
+}
+
+/// Allocates the reserve block again once a killed process is dropped. Does nothing if it is
+/// allocated already or if there is still not enough memory.
+pub fn rearm() {
+    KILLING.store(false, Ordering::Release);
+    allocate_reserve();
+}
+
+/// Allocates the reserve block if it was spent. Returns whether it is allocated.
+fn allocate_reserve() -> bool {
+    let mut reserve = RESERVE.lock();
+    if reserve.is_none() {
+        *reserve = ALLOCATOR.lock().alloc(reserve_layout()).ok();
+    }
+    reserve.is_some()
+}
+
+/// Frees the reserve block, returning whether it was allocated.
+fn release() -> bool {
+    match RESERVE.lock().take() {
+        Some(ptr) => {
+            unsafe { ALLOCATOR.lock().dealloc(ptr, reserve_layout()) };
+            true
+        }
+        None => false,
+    }
+}
+
+/// Queues a garbage collection in the main loop, unless one is queued already.
+fn request_collection() {
+    if !COLLECTION_QUEUED.swap(true, Ordering::AcqRel) && !deferred::defer(collect, 0) {
+        COLLECTION_QUEUED.store(false, Ordering::Release);
+    }
+}
+
+/// Collects, then allocates the reserve again, growing the heap or killing a process if it does
+/// not fit.
+fn collect(_: u64) {
+    COLLECTION_QUEUED.store(false, Ordering::Release);
+    if let Some(collect) = COLLECTOR.get() {
+        collect();
+    }
+
+    if !allocate_reserve()
+        && !(grow(RESERVE_SIZE) && allocate_reserve())
+        && !KILLING.load(Ordering::Acquire)
+    {
+        kill();
+    }
+    shrink();
+}
+
+/// Returns the page table and the frame allocator, if they are installed and not locked, e.g.
+/// by the code that is allocating.
+fn try_lock_paging() -> Option<(
+    MutexGuard<'static, OffsetPageTable<'static>>,
+    MutexGuard<'static, GlobalFrameAllocator>,
+)> {
+    Some((
+        MAPPER.get()?.try_lock()?,
+        FRAME_ALLOCATOR.get()?.try_lock()?,
+    ))
+}
+
+/// Maps at least `size` more bytes at the top of the heap, up to [`MAX_PHYSICAL_PERCENT`] of the
+/// physical memory and [`HEAP_MAX_SIZE`]. Returns whether the heap grew at all.
+fn grow(size: usize) -> bool {
+    let (mut mapper, mut frame_allocator) = match try_lock_paging() {
+        Some(paging) => paging,
+        None => return false,
+    };
+
+    let top = ALLOCATOR.lock().top() as u64;
+    let physical = frame_allocator.total_frames() * Size4KiB::SIZE;
+    let max_size = (physical * MAX_PHYSICAL_PERCENT / 100).clamp(HEAP_SIZE, HEAP_MAX_SIZE);
+    let limit = HEAP_START + align_down(max_size, Size2MiB::SIZE);
+    let size = align_up(size.max(GROW_SIZE) as u64, Size2MiB::SIZE);
+    let end = (top + size).min(limit);
+    if top >= end {
+        return false;
+    }
+
+    let end = match alloc_virt(&mut *mapper, &mut *frame_allocator, top, end - 1, None) {
+        Ok(()) => end,
+        Err(addr) => addr.as_u64(),
+    };
+    if end == top {
+        return false;
+    }
+
+    unsafe { ALLOCATOR.lock().extend((end - top) as usize) };
+    log!("Heap grown by {:#x} bytes, to {:#x}", end - top, end);
+    true
+}
+
+/// Unmaps the free memory at the top of the heap that [`grow`] added, e.g. after a process
+/// was killed and collected.
+pub fn shrink() {
+    let (mut mapper, mut frame_allocator) = match try_lock_paging() {
+        Some(paging) => paging,
+        None => return,
+    };
+
+    let (top, free) = {
+        let heap = ALLOCATOR.lock();
+        (heap.top() as u64, heap.free_top() as u64)
+    };
+    let excess = top - (HEAP_START + HEAP_SIZE);
+    let mut new_top = align_up(top - free.min(excess), Size2MiB::SIZE);
+    // the heap can only end at a page boundary, which may be a 1GiB one
+    if let TranslateResult::Mapped { frame, .. } = mapper.translate(VirtAddr::new(new_top)) {
+        new_top = align_up(new_top, frame.size());
+    }
+    if new_top >= top || !ALLOCATOR.lock().shrink((top - new_top) as usize) {
+        return;
+    }
+
+    dealloc_virt(&mut *mapper, &mut *frame_allocator, new_top, top - 1).unwrap();
+    log!(
+        "Heap shrunk by {:#x} bytes, to {:#x}",
+        top - new_top,
+        new_top
+    );
+}
+
+/// Kills the process with the most live bytes.
+fn kill() {
+    let kill = match KILLER.get() {
+        Some(kill) => kill,
+        None => return,
+    };
+    let (pid, usage) = match trace::largest_process() {
+        Some(largest) => largest,
+        None => return,
+    };
+
+    log!(
+        "Killing process {}, which holds {} bytes in {} blocks",
+        pid,
+        usage.bytes,
+        usage.blocks
+    );
+    KILLING.store(kill(pid), Ordering::Release);
+}
+
+/// Tries to make room for `layout` and allocates it. Returns null if everything failed.
+pub(super) fn recover(layout: Layout) -> *mut u8 {
+    let retry = || {
+        ALLOCATOR
+            .lock()
+            .alloc(layout)
+            .map_or(core::ptr::null_mut(), NonNull::as_ptr)
+    };
+
+    request_collection();
+    if RECOVERING.swap(true, Ordering::Acquire) {
+        return if grow(layout.size()) {
+            retry()
+        } else {
+            core::ptr::null_mut()
+        };
+    }
+
+    log!("Out of memory allocating {} bytes", layout.size());
+    let mut ptr = core::ptr::null_mut();
+    if release() {
+        ptr = retry();
+    }
+    if ptr.is_null() && grow(layout.size()) {
+        ptr = retry();
+    }
+
+    RECOVERING.store(false, Ordering::Release);
+    ptr
+}
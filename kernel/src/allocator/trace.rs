@@ -0,0 +1,476 @@
+//! Allocation tracing.
+//!
+//! Every block records the owner that allocated it, either the kernel or a process, so that the
+//! live bytes and blocks of each owner are known at any time. This is always on, as out of
+//! memory recovery picks the process to kill by its live bytes. Blocks from the slabs keep the
+//! owner in their tag byte (see [`Heap::tag`]), which does not make them larger, the others in a
+//! 4-byte trailer.
+//!
+//! With the `heap-trace` feature, the call stack of each allocation is recorded as well, which
+//! needs the kernel to be built with frame pointers (`deno run -A scripts/build.ts
+//! --heap-trace`). Every block then has a trailer, which moves blocks whose size is a power of
+//! two up a size class.
+//!
+//! The tables are fixed-size and never allocate, so they can be dumped at out of memory.
+
+use {
+    super::oom,
+    crate::{
+        constant::{HEAP_MAX_SIZE, HEAP_START},
+        dma, log,
+        sync::{IrqMutex, IrqMutexGuard},
+    },
+    core::{
+        alloc::{GlobalAlloc, Layout},
+        fmt,
+        ptr::NonNull,
+        sync::atomic::{AtomicI32, Ordering},
+    },
+    ingram_allocator::Heap,
+};
+
+/// Who an allocation is charged to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    Kernel,
+    Process(i32),
+    /// Owners that did not fit in the table
+    Other,
+}
+
+impl Owner {
+    fn from_raw(raw: i32) -> Self {
+        match raw {
+            0 => Owner::Kernel,
+            -1 => Owner::Other,
+            pid => Owner::Process(pid),
+        }
+    }
+
+    fn into_raw(self) -> i32 {
+        match self {
+            Owner::Kernel => 0,
+            Owner::Other => -1,
+            Owner::Process(pid) => pid,
+        }
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Owner::Kernel => write!(f, "kernel"),
+            Owner::Process(pid) => write!(f, "pid {}", pid),
+            Owner::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Live and total allocations of an owner or a call site.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    /// Bytes in live blocks, trailers excluded
+    pub bytes: usize,
+    /// Number of live blocks
+    pub blocks: usize,
+    /// Number of blocks ever allocated
+    pub total: usize,
+}
+
+impl Usage {
+    fn add(&mut self, size: usize) {
+        self.bytes += size;
+        self.blocks += 1;
+        self.total += 1;
+    }
+
+    fn sub(&mut self, size: usize) {
+        self.bytes -= size;
+        self.blocks -= 1;
+    }
+}
+
+/// Stored right behind the blocks without a tag byte, and behind every block with the
+/// `heap-trace` feature. It is read and written unaligned, so that it adds 4 bytes whatever the
+/// alignment of the block.
+#[repr(C)]
+struct Trailer {
+    owner: u16,
+    site: u16,
+}
+
+const TRAILER: usize = core::mem::size_of::<Trailer>();
+
+/// At most 256, the slot of an owner is stored in a tag byte.
+const OWNERS: usize = 64;
+const SITES: usize = 1024;
+/// Number of return addresses recorded per call site
+const DEPTH: usize = 6;
+
+#[derive(Clone, Copy)]
+struct OwnerSlot {
+    raw: i32,
+    usage: Usage,
+}
+
+#[derive(Clone, Copy)]
+struct SiteSlot {
+    frames: [usize; DEPTH],
+    usage: Usage,
+}
+
+struct Tables {
+    owners: [OwnerSlot; OWNERS],
+    /// Site 0 collects the allocations without a recorded call stack.
+    sites: [SiteSlot; SITES],
+    /// Last owner looked up, as `(raw, slot)`
+    cached: (i32, usize),
+}
+
+impl Tables {
+    const fn new() -> Self {
+        let mut owners = [OwnerSlot {
+            raw: i32::MIN,
+            usage: Usage {
+                bytes: 0,
+                blocks: 0,
+                total: 0,
+            },
+        }; OWNERS];
+        owners[0].raw = 0;
+        owners[OWNERS - 1].raw = -1;
+
+        Self {
+            owners,
+            sites: [SiteSlot {
+                frames: [0; DEPTH],
+                usage: Usage {
+                    bytes: 0,
+                    blocks: 0,
+                    total: 0,
+                },
+            }; SITES],
+            cached: (0, 0),
+        }
+    }
+
+    /// Returns the slot of `raw`, taking a free one if needed. Slots without live blocks are
+    /// free, since no block refers to them.
+    fn owner(&mut self, raw: i32) -> usize {
+        if self.cached.0 == raw && self.owners[self.cached.1].raw == raw {
+            return self.cached.1;
+        }
+
+        let slots = &mut self.owners[..OWNERS - 1];
+        let slot = match slots.iter().position(|slot| slot.raw == raw) {
+            Some(slot) => slot,
+            None => match slots[1..].iter().position(|slot| slot.usage.blocks == 0) {
+                Some(slot) => {
+                    slots[slot + 1] = OwnerSlot {
+                        raw,
+                        usage: Usage::default(),
+                    };
+                    slot + 1
+                }
+                None => OWNERS - 1,
+            },
+        };
+
+        self.cached = (raw, slot);
+        slot
+    }
+
+    /// Returns the slot of a call stack. Slots are never freed, stacks that do not fit are
+    /// charged to site 0.
+    #[cfg(feature = "heap-trace")]
+    fn site(&mut self, frames: [usize; DEPTH]) -> usize {
+        if frames[0] == 0 {
+            return 0;
+        }
+
+        let hash = frames.iter().fold(0usize, |hash, &frame| {
+            (hash ^ frame).wrapping_mul(0x100_0000_01b3)
+        });
+        let mut index = hash % (SITES - 1) + 1;
+        for _ in 1..SITES {
+            let slot = &mut self.sites[index];
+            if slot.frames == frames {
+                return index;
+            }
+            if slot.frames[0] == 0 {
+                slot.frames = frames;
+                return index;
+            }
+            index = index % (SITES - 1) + 1;
+        }
+        0
+    }
+}
+
+static TABLES: IrqMutex<Tables> = IrqMutex::new(Tables::new());
+
+/// The owner new allocations are charged to.
+static OWNER: AtomicI32 = AtomicI32::new(0);
+
+/// Runs `f` with its allocations charged to `owner`.
+pub fn with_owner<R>(owner: Owner, f: impl FnOnce() -> R) -> R {
+    let prev = OWNER.swap(owner.into_raw(), Ordering::Relaxed);
+    let result = f();
+    OWNER.store(prev, Ordering::Relaxed);
+    result
+}
+
+/// Returns the owner new allocations are charged to.
+pub fn owner() -> Owner {
+    Owner::from_raw(OWNER.load(Ordering::Relaxed))
+}
+
+/// Returns the usage of `owner`, if it has a slot.
+pub fn usage(owner: Owner) -> Option<Usage> {
+    let raw = owner.into_raw();
+    TABLES
+        .lock()
+        .owners
+        .iter()
+        .find(|slot| slot.raw == raw)
+        .map(|slot| slot.usage)
+}
+
+/// Returns the process with the most live bytes.
+pub fn largest_process() -> Option<(i32, Usage)> {
+    TABLES
+        .lock()
+        .owners
+        .iter()
+        .filter_map(|slot| match Owner::from_raw(slot.raw) {
+            Owner::Process(pid) if slot.usage.blocks > 0 => Some((pid, slot.usage)),
+            _ => None,
+        })
+        .max_by_key(|(_, usage)| usage.bytes)
+}
+
+/// Prints the usage of every owner and the call sites with the most live bytes over serial.
+pub fn dump() {
+    const TOP_SITES: usize = 16;
+
+    let tables = TABLES.lock();
+
+    log!("live allocations by owner:");
+    let mut owners = [0u16; OWNERS];
+    owners
+        .iter_mut()
+        .enumerate()
+        .for_each(|(i, slot)| *slot = i as u16);
+    owners
+        .sort_unstable_by_key(|&slot| core::cmp::Reverse(tables.owners[slot as usize].usage.bytes));
+    for slot in owners {
+        let OwnerSlot { raw, usage } = tables.owners[slot as usize];
+        if usage.total == 0 {
+            continue;
+        }
+        let owner = Owner::from_raw(raw);
+        log!(
+            "{:>12} bytes in {} blocks ({} total) by {}",
+            usage.bytes,
+            usage.blocks,
+            usage.total,
+            owner
+        );
+        let (buffers, bytes) = dma::usage(owner);
+        if buffers > 0 {
+            log!("{:>12} bytes in {} DMA buffers", bytes, buffers);
+        }
+    }
+
+    if !cfg!(feature = "heap-trace") {
+        return;
+    }
+
+    log!("call sites with the most live bytes:");
+    let mut sites = [0u16; SITES];
+    sites
+        .iter_mut()
+        .enumerate()
+        .for_each(|(i, slot)| *slot = i as u16);
+    sites.sort_unstable_by_key(|&slot| core::cmp::Reverse(tables.sites[slot as usize].usage.bytes));
+    for slot in sites.into_iter().take(TOP_SITES) {
+        let SiteSlot { frames, usage } = tables.sites[slot as usize];
+        if usage.bytes == 0 {
+            break;
+        }
+        log!(
+            "{:>12} bytes in {} blocks ({} total) at {}",
+            usage.bytes,
+            usage.blocks,
+            usage.total,
+            Frames(&frames)
+        );
+    }
+}
+
+struct Frames<'a>(&'a [usize]);
+
+impl fmt::Display for Frames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut frames = self.0.iter().take_while(|&&frame| frame != 0);
+        match frames.next() {
+            Some(frame) => write!(f, "{:#x}", frame)?,
+            None => return write!(f, "unknown"),
+        }
+        frames.try_for_each(|frame| write!(f, " <- {:#x}", frame))
+    }
+}
+
+#[cfg(feature = "heap-trace")]
+mod stack {
+    use {
+        super::DEPTH,
+        core::{
+            arch::asm,
+            sync::atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    /// Frame pointer of the outermost frame that is walked. Allocations only happen below it.
+    static TOP: AtomicUsize = AtomicUsize::new(0);
+
+    /// Frames of the tracing code itself: [`frames`] and [`super::TracedHeap::alloc`].
+    const SKIP: usize = 2;
+
+    #[inline(always)]
+    pub fn init() {
+        let rbp: usize;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+        TOP.store(rbp, Ordering::Relaxed);
+    }
+
+    /// Walks the frame pointer chain and returns the return addresses of the callers.
+    #[inline(never)]
+    pub fn frames() -> [usize; DEPTH] {
+        let mut frames = [0; DEPTH];
+        let top = TOP.load(Ordering::Relaxed);
+        let (mut rbp, rsp): (usize, usize);
+        unsafe {
+            asm!("mov {}, rbp", "mov {}, rsp", out(reg) rbp, out(reg) rsp, options(nomem, nostack))
+        };
+
+        // Only follow frames between the current stack pointer and `top`, which are mapped
+        let mut lowest = rsp;
+        for i in 0..SKIP + DEPTH {
+            if rbp <= lowest || rbp > top || rbp % 8 != 0 {
+                break;
+            }
+            let (next, ret) = unsafe { (*(rbp as *const usize), *((rbp + 8) as *const usize)) };
+            if i >= SKIP {
+                frames[i - SKIP] = ret;
+            }
+            lowest = rbp;
+            rbp = next;
+        }
+        frames
+    }
+}
+
+/// Records where the walk of the call stack ends. Must be called from the outermost frame that
+/// allocates, i.e. `kernel_main`.
+#[inline(always)]
+pub fn init() {
+    #[cfg(feature = "heap-trace")]
+    stack::init();
+}
+
+/// The kernel [`Heap`], with every block charged to its [`Owner`].
+///
+/// Its lock disables interrupts, but interrupt handlers should still not allocate, see
+/// [`crate::deferred`].
+pub struct TracedHeap(IrqMutex<Heap>);
+
+impl TracedHeap {
+    pub const fn new(heap: Heap) -> Self {
+        Self(IrqMutex::new(heap))
+    }
+
+    pub fn lock(&self) -> IrqMutexGuard<Heap> {
+        self.0.lock()
+    }
+
+    /// Returns the layout allocated for `layout`, and whether it ends with a [`Trailer`].
+    fn chunk(layout: &Layout) -> Option<(Layout, bool)> {
+        if !cfg!(feature = "heap-trace") && Heap::is_tagged(layout) {
+            return Some((*layout, false));
+        }
+        let size = layout.size().checked_add(TRAILER)?;
+        Some((Layout::from_size_align(size, layout.align()).ok()?, true))
+    }
+}
+
+unsafe impl GlobalAlloc for TracedHeap {
+    #[inline(never)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        #[cfg(feature = "heap-trace")]
+        let frames = stack::frames();
+
+        let (chunk, trailer) = match Self::chunk(&layout) {
+            Some(chunk) => chunk,
+            None => return core::ptr::null_mut(),
+        };
+        let mut ptr = self
+            .lock()
+            .alloc(chunk)
+            .map_or(core::ptr::null_mut(), NonNull::as_ptr);
+        if ptr.is_null() {
+            ptr = oom::recover(chunk);
+        }
+        if ptr.is_null() {
+            return ptr;
+        }
+
+        let (owner, site) = {
+            let mut tables = TABLES.lock();
+            let owner = tables.owner(OWNER.load(Ordering::Relaxed));
+            #[cfg(feature = "heap-trace")]
+            let site = tables.site(frames);
+            #[cfg(not(feature = "heap-trace"))]
+            let site = 0;
+
+            tables.owners[owner].usage.add(layout.size());
+            tables.sites[site].usage.add(layout.size());
+            (owner, site)
+        };
+
+        if trailer {
+            (ptr.add(layout.size()) as *mut Trailer).write_unaligned(Trailer {
+                owner: owner as u16,
+                site: site as u16,
+            });
+        } else {
+            let tag = Heap::tag(NonNull::new_unchecked(ptr), &layout).unwrap();
+            tag.as_ptr().write(owner as u8);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // e.g. a DMA buffer or MMIO that was not taken back from a collection
+        if !(HEAP_START..HEAP_START + HEAP_MAX_SIZE).contains(&(ptr as u64)) {
+            panic!("dealloc of {:p}, which is outside of the heap", ptr);
+        }
+
+        let ptr = NonNull::new_unchecked(ptr);
+        let (chunk, trailer) = Self::chunk(&layout).unwrap();
+        let (owner, site) = if trailer {
+            let trailer = (ptr.as_ptr().add(layout.size()) as *const Trailer).read_unaligned();
+            (trailer.owner as usize, trailer.site as usize)
+        } else {
+            let tag = Heap::tag(ptr, &layout).unwrap();
+            (*tag.as_ptr() as usize, 0)
+        };
+        {
+            let mut tables = TABLES.lock();
+            tables.owners[owner].usage.sub(layout.size());
+            tables.sites[site].usage.sub(layout.size());
+        }
+
+        self.lock().dealloc(ptr, chunk);
+    }
+}
@@ -9,12 +9,17 @@ use {
         constant::{
             IOApicInt, LocalApicInt, HPET_INTERVAL, LOCAL_APIC_ID, LOCAL_APIC_TIMER_INIT_COUNT,
         },
+        cpu::FEATURES,
         interrupt::{nmi_disable, nmi_enable},
+        log,
         memory::alloc_phys,
-        println,
+        pic, pit,
     },
     acpi::{
-        platform::{interrupt::Apic, PmTimer},
+        platform::{
+            interrupt::{Apic, Polarity, TriggerMode},
+            PmTimer,
+        },
         HpetInfo,
     },
     alloc::collections::BTreeMap,
@@ -22,60 +27,132 @@ use {
     core::ptr::{read_volatile, write_volatile},
     spin::Once,
     x2apic::{
-        ioapic::IoApic,
+        ioapic::{IoApic, IrqFlags},
         lapic::{IpiDestMode, LocalApic, LocalApicBuilder, TimerDivide, TimerMode},
     },
     x86_64::{
-        instructions::{
-            interrupts,
-            port::{PortReadOnly, PortWriteOnly},
-        },
-        structures::paging::{FrameAllocator, Mapper, Size4KiB},
+        instructions::{interrupts, port::PortReadOnly},
+        structures::paging::{mapper::MapperAllSizes, FrameAllocator, Size4KiB},
     },
 };
 
 const TIMER_MS: usize = 500;
 
+/// Uses the local APIC and the I/O APIC if the MADT describes them and the local APIC can be
+/// enabled, or the 8259 PIC and the PIT otherwise.
 pub fn init(
-    mapper: &mut impl Mapper<Size4KiB>,
+    mapper: &mut impl MapperAllSizes,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-    pm_timer: PmTimer,
-    hpet_info: HpetInfo,
-    apic: Apic,
+    pm_timer: Option<PmTimer>,
+    hpet_info: Option<HpetInfo>,
+    apic: Option<Apic>,
 ) {
     interrupts::disable();
     nmi_disable();
-    println!("Interrupts disabled");
-
-    unsafe { PortWriteOnly::new(0xa1).write(u8::MAX) };
-    unsafe { PortWriteOnly::new(0x21).write(u8::MAX) };
-    println!("PCI disabled");
-
-    init_local_apic();
-    wait_on_pm_timer(&pm_timer);
-    unsafe {
-        let local_apic = &mut *LOCAL_APIC.as_mut_ptr();
-        local_apic.set_timer_initial(LOCAL_APIC_TIMER_INIT_COUNT - local_apic.timer_current());
-    };
+    log!("Interrupts disabled");
+
+    let apic = apic.filter(
+        |apic| match init_local_apic(mapper, frame_allocator, apic) {
+            Ok(()) => true,
+            Err(err) => {
+                log!("Local apic not enabled: {}", err);
+                false
+            }
+        },
+    );
 
-    init_io_apics(mapper, frame_allocator, &apic);
-    init_hpet(mapper, frame_allocator, &hpet_info);
+    match apic {
+        Some(apic) => {
+            pic::disable();
+            log!("PIC disabled");
+
+            match &pm_timer {
+                Some(pm_timer) => wait_on_pm_timer(pm_timer),
+                None => pit::wait(TIMER_MS as u32),
+            }
+            unsafe {
+                let local_apic = &mut *LOCAL_APIC.as_mut_ptr();
+                local_apic
+                    .set_timer_initial(LOCAL_APIC_TIMER_INIT_COUNT - local_apic.timer_current());
+            };
+
+            init_io_apics(mapper, frame_allocator, &apic);
+            if let Some(hpet_info) = &hpet_info {
+                init_hpet(mapper, frame_allocator, hpet_info);
+            }
+
+            log!("Local apic enabled");
+        }
+        None => {
+            pic::init();
+            pit::start_periodic(TIMER_MS as u32);
+            pic::enable_irq(IOApicInt::Timer);
+            pic::enable_irq(IOApicInt::COM1);
+            log!("No I/O apic, PIC and PIT enabled");
+        }
+    }
 
-    println!("Local apic enabled");
     nmi_enable();
     interrupts::enable();
 
-    println!("Interrupts enabled");
+    log!("Interrupts enabled");
+}
+
+/// Enables an ISA IRQ on the I/O APIC, or on the PIC if there is none. `flags` are the polarity
+/// and trigger mode unless overridden in the MADT, the PIC ignores them.
+pub fn enable_irq(irq: IOApicInt, flags: IrqFlags) {
+    if IO_APICS.is_completed() {
+        unsafe { &mut *IO_APICS.as_mut_ptr() }.enable_irq_with(irq, flags);
+    } else {
+        pic::enable_irq(irq);
+    }
+}
+
+/// Masks an ISA IRQ on the I/O APIC, or on the PIC if there is none.
+pub fn disable_irq(irq: IOApicInt) {
+    if IO_APICS.is_completed() {
+        unsafe { &mut *IO_APICS.as_mut_ptr() }.disable_irq(irq);
+    } else {
+        pic::disable_irq(irq);
+    }
+}
+
+/// Acknowledges the interrupt `vector` to the local APIC, or to the PIC if there is none.
+pub fn end_of_interrupt(vector: u8) {
+    if LOCAL_APIC.is_completed() {
+        unsafe { (&mut *LOCAL_APIC.as_mut_ptr()).end_of_interrupt() };
+    } else {
+        pic::end_of_interrupt(vector);
+    }
+}
+
+/// Returns whether `vector` is a spurious IRQ of the PIC, which must not be acknowledged.
+pub fn is_spurious(vector: u8) -> bool {
+    !LOCAL_APIC.is_completed() && pic::is_spurious(vector)
 }
 
 pub static LOCAL_APIC: Once<LocalApic> = Once::new();
 
-fn init_local_apic() {
-    // Do not provide xapic base address, it is only used in xapic.
-    // We assume the machine support x2apic
+/// Uses the x2APIC mode if supported, or the memory mapped registers of the xAPIC otherwise.
+fn init_local_apic(
+    mapper: &mut impl MapperAllSizes,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    apic: &Apic,
+) -> Result<(), &'static str> {
+    let mut builder = LocalApicBuilder::new();
+    if !FEATURES.x2apic {
+        // The registers fit in one page
+        let addr = apic.local_apic_address;
+        alloc_phys(mapper, frame_allocator, addr, addr + 0xfff, None)
+            .map_err(|_| "cannot map the registers")?;
+        builder.set_xapic_base(addr);
+    }
+    // Mapped here so that the PIC is kept if it cannot be
+    let addr = apic.io_apics[0].address as u64;
+    alloc_phys(mapper, frame_allocator, addr, addr, None)
+        .map_err(|_| "cannot map the I/O apic registers")?;
 
-    // let xapic_base = phys2virt(apic.local_apic_address);
-    let mut local_apic = LocalApicBuilder::new()
+    let mut local_apic = builder
         .timer_vector(LocalApicInt::Timer.into())
         .error_vector(LocalApicInt::Error.into())
         .spurious_vector(LocalApicInt::Spurious.into())
@@ -83,14 +160,15 @@ fn init_local_apic() {
         .timer_divide(TimerDivide::Div256)
         .timer_initial(LOCAL_APIC_TIMER_INIT_COUNT)
         .ipi_destination_mode(IpiDestMode::Physical)
-        // .set_xapic_base(xapic_base.as_u64())
-        .build()
-        .unwrap();
+        .build()?;
 
-    assert!(unsafe { local_apic.is_bsp() });
+    if !unsafe { local_apic.is_bsp() } {
+        return Err("not the bootstrap processor");
+    }
     unsafe { local_apic.enable() };
 
     LOCAL_APIC.call_once(move || local_apic);
+    Ok(())
 }
 
 /// Must be called after [`init_local_apic`]
@@ -109,21 +187,26 @@ fn wait_on_pm_timer(pm_timer: &PmTimer) {
 pub static IO_APICS: Once<IoApics> = Once::new();
 
 fn init_io_apics(
-    mapper: &mut impl Mapper<Size4KiB>,
+    mapper: &mut impl MapperAllSizes,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
     apic: &Apic,
 ) {
     IO_APICS.call_once(|| {
         let mut irq_mappings = BTreeMap::new();
         for i in &apic.interrupt_source_overrides {
-            // TODO: Save `polarity` and `trigger_mode`
-            irq_mappings.insert(i.isa_source, i.global_system_interrupt as u8);
+            irq_mappings.insert(
+                i.isa_source,
+                Override {
+                    gsi: i.global_system_interrupt as u8,
+                    polarity: i.polarity,
+                    trigger_mode: i.trigger_mode,
+                },
+            );
         }
 
         // Only use the first one
         let addr = apic.io_apics[0].address as u64;
         let gsi_base = apic.io_apics[0].global_system_interrupt_base as u8;
-        alloc_phys(mapper, frame_allocator, addr, addr, None);
 
         let mut io_apic = unsafe { IoApic::new(addr) };
         let max_entry = unsafe { io_apic.max_table_entry() };
@@ -141,29 +224,54 @@ fn init_io_apics(
         };
 
         io_apics.enable_irq(IOApicInt::COM1);
-        println!("IRQ {:#?} enabled", IOApicInt::COM1);
+        log!("IRQ {:#?} enabled", IOApicInt::COM1);
 
         io_apics
     });
 
-    println!("I/O apics initialized");
+    log!("I/O apics initialized");
+}
+
+/// An interrupt source override of the MADT.
+struct Override {
+    gsi: u8,
+    polarity: Polarity,
+    trigger_mode: TriggerMode,
 }
 
 pub struct IoApics {
-    irq_mappings: BTreeMap<u8, u8>,
+    irq_mappings: BTreeMap<u8, Override>,
     io_apic: IoApic,
     gsi_base: u8,
     max_entry: u8,
 }
 
 impl IoApics {
-    fn enable_irq(&mut self, irq: IOApicInt) {
+    /// Enables an ISA IRQ, which is edge-triggered and active-high unless overridden.
+    pub fn enable_irq(&mut self, irq: IOApicInt) {
+        self.enable_irq_with(irq, IrqFlags::empty());
+    }
+
+    /// Enables an IRQ with the polarity and trigger mode of `flags`, unless overridden in the
+    /// MADT.
+    pub fn enable_irq_with(&mut self, irq: IOApicInt, mut flags: IrqFlags) {
         let gsi = self.find_io_apic(&irq);
 
+        if let Some(i) = self.irq_mappings.get(&(irq as u8 - IOApicInt::OFFSET)) {
+            match i.polarity {
+                Polarity::ActiveHigh => flags.remove(IrqFlags::LOW_ACTIVE),
+                Polarity::ActiveLow => flags.insert(IrqFlags::LOW_ACTIVE),
+                Polarity::SameAsBus => (),
+            }
+            match i.trigger_mode {
+                TriggerMode::Edge => flags.remove(IrqFlags::LEVEL_TRIGGERED),
+                TriggerMode::Level => flags.insert(IrqFlags::LEVEL_TRIGGERED),
+                TriggerMode::SameAsBus => (),
+            }
+        }
+
         let mut entry = unsafe { self.io_apic.table_entry(gsi) };
-        // let mut entry = RedirectionTableEntry::default();
-        // entry.set_mode(IrqMode::Fixed);
-        // entry.set_flags(IrqFlags::LEVEL_TRIGGERED | IrqFlags::LOW_ACTIVE | IrqFlags::MASKED);
+        entry.set_flags(flags);
         entry.set_vector(irq as u8);
         entry.set_dest(LOCAL_APIC_ID);
         unsafe { self.io_apic.set_table_entry(gsi, entry) };
@@ -179,7 +287,7 @@ impl IoApics {
 
     fn find_io_apic(&mut self, irq: &IOApicInt) -> u8 {
         let irq = *irq as u8 - IOApicInt::OFFSET;
-        let gsi = *self.irq_mappings.get(&irq).unwrap_or(&irq);
+        let gsi = self.irq_mappings.get(&irq).map_or(irq, |i| i.gsi);
         assert!(gsi >= self.gsi_base && gsi <= self.gsi_base + self.max_entry);
         gsi
     }
@@ -187,16 +295,19 @@ impl IoApics {
 
 /// Use HPET to figure Local APIC Timer freq
 fn init_hpet(
-    mapper: &mut impl Mapper<Size4KiB>,
+    mapper: &mut impl MapperAllSizes,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
     hpet_info: &HpetInfo,
 ) {
     let addr = hpet_info.base_address as u64;
-    alloc_phys(mapper, frame_allocator, addr, addr, None);
+    if let Err(err) = alloc_phys(mapper, frame_allocator, addr, addr, None) {
+        log!("HPET not mapped ({:?})", err);
+        return;
+    }
 
     let gen_caps = unsafe { read_volatile(addr as *const u64) };
     assert!(gen_caps.get_bit(13));
-    println!(
+    log!(
         "Found HPET at {:x}, rev. id: {:x}, vendor id: {:x}",
         addr,
         gen_caps.get_bits(0..=7),
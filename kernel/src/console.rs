@@ -0,0 +1,216 @@
+//! Input of the serial console.
+//!
+//! The COM1 interrupt handler only stores the received byte in [`RX`] and defers the rest: the
+//! line discipline runs in the main loop, where it may allocate and echo.
+
+use {
+    crate::{deferred, sync::IrqMutex, uart},
+    alloc::vec::Vec,
+    core::sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    spin::{Mutex, Once},
+};
+
+const RX_CAPACITY: usize = 1024;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+/// Bytes received by the interrupt handler, not yet seen by the line discipline.
+struct Ring {
+    bytes: [u8; RX_CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+static RX: IrqMutex<Ring> = IrqMutex::new(Ring {
+    bytes: [0; RX_CAPACITY],
+    head: 0,
+    len: 0,
+});
+
+/// Number of bytes dropped because [`RX`] was full.
+static OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Set while [`process`] is queued, so that a burst of bytes defers it once.
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Input is edited a line at a time, and becomes readable at the end of the line.
+    Canonical,
+    /// Every byte is readable as soon as it is received.
+    Raw,
+}
+
+struct LineDiscipline {
+    mode: Mode,
+    echo: bool,
+    /// The line being edited in canonical mode
+    line: Vec<u8>,
+    /// The input that can be read
+    ready: Vec<u8>,
+}
+
+impl LineDiscipline {
+    fn echo_bytes(&self, bytes: &[u8]) {
+        if self.echo {
+            uart::write_bytes(bytes);
+        }
+    }
+}
+
+static LINE_DISCIPLINE: Mutex<LineDiscipline> = Mutex::new(LineDiscipline {
+    mode: Mode::Canonical,
+    echo: true,
+    line: Vec::new(),
+    ready: Vec::new(),
+});
+
+static INPUT: Once<fn()> = Once::new();
+static INTERRUPT: Once<fn()> = Once::new();
+
+/// Sets the function called in the main loop when input becomes readable.
+pub fn set_input_handler(handler: fn()) {
+    INPUT.call_once(|| handler);
+}
+
+/// Sets the function called in the main loop when Ctrl-C is typed in canonical mode.
+pub fn set_interrupt_handler(handler: fn()) {
+    INTERRUPT.call_once(|| handler);
+}
+
+/// Stores a byte received by the UART. Called by the interrupt handler, so it must not allocate.
+pub fn receive(byte: u8) {
+    {
+        let mut rx = RX.lock();
+        if rx.len == RX_CAPACITY {
+            OVERRUNS.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let tail = (rx.head + rx.len) % RX_CAPACITY;
+            rx.bytes[tail] = byte;
+            rx.len += 1;
+        }
+    }
+
+    if !PENDING.swap(true, Ordering::AcqRel) && !deferred::defer(process, 0) {
+        PENDING.store(false, Ordering::Release);
+    }
+}
+
+fn pop() -> Option<u8> {
+    let mut rx = RX.lock();
+    if rx.len == 0 {
+        return None;
+    }
+
+    let byte = rx.bytes[rx.head];
+    rx.head = (rx.head + 1) % RX_CAPACITY;
+    rx.len -= 1;
+    Some(byte)
+}
+
+/// Runs the line discipline over the received bytes.
+fn process(_: u64) {
+    PENDING.store(false, Ordering::Release);
+
+    let (mut readable, mut interrupted) = (false, false);
+    {
+        let mut ld = LINE_DISCIPLINE.lock();
+        while let Some(byte) = pop() {
+            match ld.mode {
+                Mode::Raw => {
+                    ld.ready.push(byte);
+                    readable = true;
+                }
+                Mode::Canonical => match byte {
+                    CTRL_C => {
+                        ld.line.clear();
+                        ld.echo_bytes(b"^C\n");
+                        interrupted = true;
+                    }
+                    BACKSPACE | DELETE => {
+                        // Erases a whole UTF-8 sequence
+                        while let Some(byte) = ld.line.pop() {
+                            if !is_continuation(byte) {
+                                ld.echo_bytes(b"\x08 \x08");
+                                break;
+                            }
+                        }
+                    }
+                    b'\r' | b'\n' => {
+                        ld.echo_bytes(b"\n");
+                        let line = core::mem::take(&mut ld.line);
+                        ld.ready.extend(line);
+                        ld.ready.push(b'\n');
+                        readable = true;
+                    }
+                    // End of file: flushes the line, which reads as empty if there is none
+                    CTRL_D => {
+                        let line = core::mem::take(&mut ld.line);
+                        ld.ready.extend(line);
+                        readable = true;
+                    }
+                    _ => {
+                        ld.line.push(byte);
+                        ld.echo_bytes(&[byte]);
+                    }
+                },
+            }
+        }
+    }
+
+    if interrupted {
+        if let Some(handler) = INTERRUPT.get() {
+            handler();
+        }
+    }
+    if readable {
+        if let Some(handler) = INPUT.get() {
+            handler();
+        }
+    }
+}
+
+/// Takes up to `max` bytes of the readable input, without splitting a UTF-8 sequence: the bytes
+/// of a character that does not fit stay for the next read.
+pub fn read(max: usize) -> Vec<u8> {
+    let mut ld = LINE_DISCIPLINE.lock();
+    let mut len = max.min(ld.ready.len());
+    if len < ld.ready.len() {
+        // A sequence is at most 4 bytes, longer runs of continuation bytes are invalid anyway
+        let start = len.saturating_sub(3);
+        if let Some(boundary) = (start..=len).rev().find(|&i| !is_continuation(ld.ready[i])) {
+            len = boundary;
+        }
+    }
+    ld.ready.drain(..len).collect()
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+/// Switches the mode. The line being edited becomes readable when leaving canonical mode.
+pub fn set_mode(mode: Mode) {
+    let mut ld = LINE_DISCIPLINE.lock();
+    if ld.mode == Mode::Canonical && mode == Mode::Raw {
+        let line = core::mem::take(&mut ld.line);
+        ld.ready.extend(line);
+    }
+    ld.mode = mode;
+}
+
+pub fn mode() -> Mode {
+    LINE_DISCIPLINE.lock().mode
+}
+
+pub fn set_echo(echo: bool) {
+    LINE_DISCIPLINE.lock().echo = echo;
+}
+
+/// Returns the number of bytes dropped so far because they came faster than they were processed.
+pub fn overruns() -> usize {
+    OVERRUNS.load(Ordering::Relaxed)
+}
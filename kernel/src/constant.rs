@@ -1,6 +1,9 @@
-use x86_64::{
-    structures::paging::{PageSize, Size4KiB},
-    VirtAddr,
+use {
+    core::ops::Range,
+    x86_64::{
+        structures::paging::{PageSize, Size1GiB, Size2MiB, Size4KiB},
+        VirtAddr,
+    },
 };
 
 pub const PHYS_OFFSET: VirtAddr = unsafe { VirtAddr::new_unsafe(0x0000_4000_0000_0000) };
@@ -13,9 +16,11 @@ pub const LOCAL_APIC_TIMER_INIT_COUNT: u32 = u32::MAX;
 
 pub const HPET_INTERVAL: u32 = 10; // 10ms
 
-pub const HEAP_START: u64 = 0x0004_4444_4440 * Size4KiB::SIZE;
+pub const HEAP_START: u64 = 0x0022_2222 * Size2MiB::SIZE;
 pub const HEAP_SIZE: u64 = 128 * 1024 * Size4KiB::SIZE; /* 512 MiB */
 pub const HEAP_END: u64 = HEAP_START + HEAP_SIZE - 1;
+/// The heap grows up to this size when it runs out of memory.
+pub const HEAP_MAX_SIZE: u64 = 64 * Size1GiB::SIZE;
 
 #[derive(Copy, Clone, Debug)]
 #[repr(u8)]
@@ -40,6 +45,11 @@ pub enum IOApicInt {
 
 impl IOApicInt {
     pub const OFFSET: u8 = 32;
+
+    /// Returns the interrupt of ISA IRQ `irq`, if it is one of the 16 legacy IRQs.
+    pub fn from_isa(irq: u8) -> Option<Self> {
+        (irq < 16).then(|| unsafe { core::mem::transmute(irq + Self::OFFSET) })
+    }
 }
 
 impl From<IOApicInt> for usize {
@@ -48,6 +58,9 @@ impl From<IOApicInt> for usize {
     }
 }
 
+/// Vectors handed out to MSI and MSI-X, between the I/O APIC and the local APIC ones.
+pub const MSI_VECTORS: Range<u8> = IOApicInt::OFFSET + 16..LocalApicInt::OFFSET;
+
 #[derive(Copy, Clone, Debug)]
 #[repr(u8)]
 pub enum LocalApicInt {
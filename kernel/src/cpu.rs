@@ -0,0 +1,103 @@
+/// https://wiki.osdev.org/CPUID
+/// https://wiki.osdev.org/Supervisor_Memory_Protection
+use {
+    crate::log,
+    bit_field::BitField,
+    core::arch::x86_64::{__cpuid, __cpuid_count},
+    spin::Lazy,
+    x86_64::registers::{
+        control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+        model_specific::{Efer, EferFlags},
+    },
+};
+
+/// CPU features reported by CPUID.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Features {
+    /// No-execute pages
+    pub nx: bool,
+    /// 1GiB pages
+    pub page_1gb: bool,
+    pub x2apic: bool,
+    /// Supervisor mode execution prevention
+    pub smep: bool,
+    /// Supervisor mode access prevention
+    pub smap: bool,
+    /// User mode instruction prevention
+    pub umip: bool,
+}
+
+pub static FEATURES: Lazy<Features> = Lazy::new(|| {
+    let mut features = Features::default();
+
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    if max_leaf >= 1 {
+        let leaf = unsafe { __cpuid(1) };
+        features.x2apic = leaf.ecx.get_bit(21);
+    }
+    if max_leaf >= 7 {
+        let leaf = unsafe { __cpuid_count(7, 0) };
+        features.smep = leaf.ebx.get_bit(7);
+        features.smap = leaf.ebx.get_bit(20);
+        features.umip = leaf.ecx.get_bit(2);
+    }
+
+    let max_ext_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    if max_ext_leaf >= 0x8000_0001 {
+        let leaf = unsafe { __cpuid(0x8000_0001) };
+        features.nx = leaf.edx.get_bit(20);
+        features.page_1gb = leaf.edx.get_bit(26);
+    }
+
+    features
+});
+
+/// The protection features that are currently enabled.
+#[derive(Debug, Clone, Copy)]
+pub struct Hardening {
+    /// EFER.NXE
+    pub nx: bool,
+    /// CR4.SMEP
+    pub smep: bool,
+    /// CR4.SMAP
+    pub smap: bool,
+    /// CR4.UMIP
+    pub umip: bool,
+    /// CR0.WP
+    pub write_protect: bool,
+}
+
+impl Hardening {
+    /// Reads the enabled set from the control registers.
+    pub fn current() -> Self {
+        let cr0 = Cr0::read();
+        let cr4 = Cr4::read();
+        Self {
+            nx: Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE),
+            smep: cr4.contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION),
+            smap: cr4.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION),
+            umip: cr4.contains(Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION),
+            write_protect: cr0.contains(Cr0Flags::WRITE_PROTECT),
+        }
+    }
+}
+
+/// Enables CR0.WP and the CR4 protection bits supported by the CPU.
+pub fn init() {
+    let features = *FEATURES;
+
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
+    unsafe {
+        Cr4::update(|flags| {
+            flags.set(
+                Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION,
+                features.smep,
+            );
+            flags.set(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION, features.smap);
+            flags.set(Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION, features.umip);
+        })
+    };
+
+    log!("CPU features: {:?}", features);
+    log!("CPU hardening: {:?}", Hardening::current());
+}
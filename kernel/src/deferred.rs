@@ -0,0 +1,81 @@
+//! Work deferred from interrupt handlers to the main loop.
+//!
+//! Interrupt handlers must not allocate: the heap lock may be held by the code they
+//! interrupted. Instead they record what happened here, in a fixed-size queue, and the main
+//! loop runs the work with interrupts enabled.
+
+use {
+    crate::sync::IrqMutex,
+    core::sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Maximum number of pending work items.
+pub const CAPACITY: usize = 256;
+
+#[derive(Clone, Copy)]
+struct Work {
+    f: fn(u64),
+    arg: u64,
+}
+
+struct Queue {
+    items: [Option<Work>; CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+static QUEUE: IrqMutex<Queue> = IrqMutex::new(Queue {
+    items: [None; CAPACITY],
+    head: 0,
+    len: 0,
+});
+
+/// Number of work items dropped because the queue was full.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Queues `f(arg)` to run in the main loop. Returns `false` and drops the work if the queue is
+/// full. Safe to call from interrupt handlers.
+pub fn defer(f: fn(u64), arg: u64) -> bool {
+    let mut queue = QUEUE.lock();
+    if queue.len == CAPACITY {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+
+    let tail = (queue.head + queue.len) % CAPACITY;
+    queue.items[tail] = Some(Work { f, arg });
+    queue.len += 1;
+    true
+}
+
+fn pop() -> Option<Work> {
+    let mut queue = QUEUE.lock();
+    if queue.len == 0 {
+        return None;
+    }
+
+    let head = queue.head;
+    queue.head = (head + 1) % CAPACITY;
+    queue.len -= 1;
+    queue.items[head].take()
+}
+
+/// Runs the pending work, including work queued meanwhile, and returns how much ran. Must only
+/// be called from the main loop.
+pub fn run() -> usize {
+    let mut count = 0;
+    while let Some(Work { f, arg }) = pop() {
+        f(arg);
+        count += 1;
+    }
+    count
+}
+
+pub fn is_empty() -> bool {
+    QUEUE.lock().len == 0
+}
+
+/// Returns the number of work items dropped so far because the queue was full.
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
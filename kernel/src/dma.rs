@@ -0,0 +1,140 @@
+//! Physically contiguous buffers that devices can read and write.
+//!
+//! A buffer is reached through the physical memory mapping at [`PHYS_OFFSET`], outside of the
+//! heap. Its frames belong to the table of live buffers, which records who allocated each one,
+//! until they are given back by [`release`]. Anything viewing the buffer, e.g. a `Vec` over it,
+//! must be taken back before then, as the global allocator panics if it is asked to free memory
+//! outside of the heap.
+
+use {
+    crate::{
+        allocator::trace::{self, Owner},
+        constant::PHYS_OFFSET,
+        deferred,
+        memory::{phys2virt, FRAME_ALLOCATOR},
+        sync::IrqMutex,
+    },
+    x86_64::{
+        align_up,
+        structures::paging::{FrameDeallocator, PageSize, PhysFrame, Size4KiB},
+        PhysAddr,
+    },
+};
+
+/// Maximum number of live buffers.
+pub const MAX_BUFFERS: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DmaBuffer {
+    pub phys: PhysAddr,
+    pub ptr: *mut u8,
+    /// A multiple of 4KiB
+    pub size: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    phys: u64,
+    size: u64,
+    owner: Owner,
+    /// Released while the frame allocator was locked, the frames are freed by [`drain`].
+    released: bool,
+}
+
+static BUFFERS: IrqMutex<[Option<Entry>; MAX_BUFFERS]> = IrqMutex::new([None; MAX_BUFFERS]);
+
+/// Allocates a zeroed buffer of at least `size` bytes, whose physical address is a multiple of
+/// `align` (a power of two) and, if `below_4g`, ends below 4GiB for 32-bit devices. It is owned
+/// by the current owner of the heap allocations.
+///
+/// Returns `None` if the frames or the slots of the table run out.
+pub fn alloc(size: u64, align: u64, below_4g: bool) -> Option<DmaBuffer> {
+    drain(0);
+
+    let limit = if below_4g { 1 << 32 } else { u64::MAX };
+    let mut frame_allocator = FRAME_ALLOCATOR.get()?.lock();
+    let mut buffers = BUFFERS.lock();
+    let slot = buffers.iter_mut().find(|entry| entry.is_none())?;
+
+    let phys = frame_allocator.allocate_dma(size, align, limit)?;
+    let size = align_up(size, Size4KiB::SIZE);
+    let ptr = phys2virt(phys.as_u64()).as_mut_ptr::<u8>();
+    unsafe { ptr.write_bytes(0, size as usize) };
+
+    *slot = Some(Entry {
+        phys: phys.as_u64(),
+        size,
+        owner: trace::owner(),
+        released: false,
+    });
+    Some(DmaBuffer { phys, ptr, size })
+}
+
+fn free_frames(frame_deallocator: &mut impl FrameDeallocator<Size4KiB>, phys: u64, size: u64) {
+    let start = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(phys));
+    let end = PhysFrame::containing_address(PhysAddr::new(phys + size - 1));
+    for frame in PhysFrame::range_inclusive(start, end) {
+        unsafe { frame_deallocator.deallocate_frame(frame) };
+    }
+}
+
+/// Frees the buffer of [`alloc`] at `ptr`. Returns `false` if `ptr` is not a live buffer.
+///
+/// May be called by a finalizer while the frame allocator is locked, then the frames are freed
+/// by [`drain`] from the main loop.
+pub fn release(ptr: *mut u8) -> bool {
+    let phys = match (ptr as u64).checked_sub(PHYS_OFFSET.as_u64()) {
+        Some(phys) => phys,
+        None => return false,
+    };
+
+    let mut buffers = BUFFERS.lock();
+    let slot = match buffers
+        .iter_mut()
+        .find(|entry| matches!(entry, Some(entry) if entry.phys == phys && !entry.released))
+    {
+        Some(slot) => slot,
+        None => return false,
+    };
+
+    match FRAME_ALLOCATOR
+        .get()
+        .and_then(|frame_allocator| frame_allocator.try_lock())
+    {
+        Some(mut frame_allocator) => {
+            let entry = slot.take().unwrap();
+            free_frames(&mut *frame_allocator, entry.phys, entry.size);
+        }
+        None => {
+            slot.as_mut().unwrap().released = true;
+            deferred::defer(drain, 0);
+        }
+    }
+    true
+}
+
+/// Frees the frames of the buffers released while the frame allocator was locked.
+pub fn drain(_: u64) {
+    let mut frame_allocator = match FRAME_ALLOCATOR.get() {
+        Some(frame_allocator) => frame_allocator.lock(),
+        None => return,
+    };
+    for slot in BUFFERS.lock().iter_mut() {
+        if let Some(entry) = slot.filter(|entry| entry.released) {
+            free_frames(&mut *frame_allocator, entry.phys, entry.size);
+            *slot = None;
+        }
+    }
+}
+
+/// Returns the number of live buffers of `owner` and their bytes.
+pub fn usage(owner: Owner) -> (usize, u64) {
+    BUFFERS
+        .lock()
+        .iter()
+        .flatten()
+        .filter(|entry| entry.owner == owner && !entry.released)
+        .fold((0, 0), |(count, bytes), entry| {
+            (count + 1, bytes + entry.size)
+        })
+}
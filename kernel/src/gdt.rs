@@ -1,5 +1,5 @@
 use {
-    crate::{constant::DOUBLE_FAULT_IST_INDEX, println},
+    crate::{constant::DOUBLE_FAULT_IST_INDEX, log},
     spin::Lazy,
     x86_64::{
         instructions::{
@@ -25,7 +25,7 @@ pub fn init() {
         load_tss(GDT.1.tss);
     }
 
-    println!("GDT loaded at {:p}", &GDT.0)
+    log!("GDT loaded at {:p}", &GDT.0)
 }
 
 static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
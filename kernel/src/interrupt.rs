@@ -2,10 +2,9 @@
 /// https://wiki.osdev.org/NMI
 use {
     crate::{
-        apic::LOCAL_APIC,
-        constant::{IOApicInt, LocalApicInt, DOUBLE_FAULT_IST_INDEX},
-        println,
-        uart::SERIAL1,
+        apic, console,
+        constant::{IOApicInt, LocalApicInt, DOUBLE_FAULT_IST_INDEX, MSI_VECTORS},
+        deferred, log, pci, pit, power, uart,
     },
     spin::Lazy,
     x86_64::{
@@ -23,7 +22,7 @@ use {
 
 pub fn init() {
     IDT.load();
-    println!("IDT loaded at {:p}", &IDT);
+    log!("IDT loaded at {:p}", &IDT);
 }
 
 static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
@@ -33,14 +32,49 @@ static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
         todo!("irq {} {:?} code: {:?}", index, stack_frame, error_code)
     }
 
+    // Dispatches the ISA IRQs whose vectors are only known at runtime, like the SCI
+    fn io_apic_handler(_stack_frame: InterruptStackFrame, index: u8, _error_code: Option<u64>) {
+        if apic::is_spurious(index) {
+            return;
+        }
+        match power::sci() {
+            Some(sci) if sci as u8 == index => power::handle_sci(),
+            // e.g. a device the firmware left enabled, which must not take the kernel down nor
+            // storm it if the line is level-triggered
+            _ => {
+                if let Some(irq) = IOApicInt::from_isa(index - IOApicInt::OFFSET) {
+                    apic::disable_irq(irq);
+                }
+                deferred::defer(unexpected_irq, index as u64);
+            }
+        }
+        apic::end_of_interrupt(index);
+    }
+
+    fn unexpected_irq(index: u64) {
+        log!(
+            "Unexpected IRQ {}, masked",
+            index - IOApicInt::OFFSET as u64
+        );
+    }
+
+    // Runs the handler bound by `pci::msi`, if any
+    fn msi_handler(_stack_frame: InterruptStackFrame, index: u8, _error_code: Option<u64>) {
+        pci::msi::dispatch(index);
+        apic::end_of_interrupt(index);
+    }
+
     // set all entries
     set_general_handler!(&mut idt, my_general_handler);
+    set_general_handler!(&mut idt, io_apic_handler, 32..48);
+    set_general_handler!(&mut idt, msi_handler, MSI_VECTORS);
 
     idt.breakpoint.set_handler_fn(breakpoint_handler);
 
     let double_entry = idt.double_fault.set_handler_fn(double_fault_handler);
     unsafe { double_entry.set_stack_index(DOUBLE_FAULT_IST_INDEX) };
 
+    idt[IOApicInt::Timer.into()].set_handler_fn(pit_timer_handler);
     idt[IOApicInt::COM1.into()].set_handler_fn(io_apic_com1_handler);
 
     idt[LocalApicInt::Timer.into()].set_handler_fn(local_apic_timer_handler);
@@ -49,7 +83,7 @@ static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
 });
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}\n", stack_frame);
+    log!("EXCEPTION: BREAKPOINT\n{:#?}\n", stack_frame);
 }
 
 extern "x86-interrupt" fn double_fault_handler(
@@ -60,17 +94,21 @@ extern "x86-interrupt" fn double_fault_handler(
 }
 
 extern "x86-interrupt" fn io_apic_com1_handler(_stack_frame: InterruptStackFrame) {
-    use core::fmt::Write;
-
-    let serial1 = unsafe { &mut *SERIAL1.as_mut_ptr() };
-    let ch = serial1.receive() as char;
-    serial1.write_char(ch).unwrap();
-    unsafe { (&mut *LOCAL_APIC.as_mut_ptr()).end_of_interrupt() };
+    uart::handle_interrupt(console::receive);
+    apic::end_of_interrupt(IOApicInt::COM1 as u8);
 }
 
 extern "x86-interrupt" fn local_apic_timer_handler(_stack_frame: InterruptStackFrame) {
     unsafe { DS::set_reg(SegmentSelector(1)) };
-    unsafe { (&mut *LOCAL_APIC.as_mut_ptr()).end_of_interrupt() };
+    apic::end_of_interrupt(LocalApicInt::Timer as u8);
+}
+
+/// Replaces the local APIC timer when there is no APIC.
+extern "x86-interrupt" fn pit_timer_handler(_stack_frame: InterruptStackFrame) {
+    if pit::tick() {
+        unsafe { DS::set_reg(SegmentSelector(1)) };
+    }
+    apic::end_of_interrupt(IOApicInt::Timer as u8);
 }
 
 pub fn nmi_enable() {
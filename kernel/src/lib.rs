@@ -1,12 +1,6 @@
 #![no_std]
 #![no_main]
-#![feature(
-    abi_x86_interrupt,
-    alloc_error_handler,
-    const_mut_refs,
-    format_args_nl,
-    type_alias_impl_trait
-)]
+#![feature(abi_x86_interrupt, alloc_error_handler, const_mut_refs, format_args_nl)]
 #![cfg_attr(test, feature(custom_test_frameworks))]
 #![cfg_attr(test, test_runner(test_runner))]
 #![cfg_attr(test, reexport_test_harness_main = "test_main")]
@@ -16,10 +10,19 @@ extern crate alloc;
 pub mod acpi;
 pub mod allocator;
 pub mod apic;
+pub mod console;
 pub mod constant;
+pub mod cpu;
+pub mod deferred;
+pub mod dma;
 pub mod gdt;
 pub mod interrupt;
 pub mod memory;
+pub mod pci;
+pub mod pic;
+pub mod pit;
+pub mod power;
+pub mod sync;
 pub mod uart;
 
 pub use {
@@ -37,13 +40,15 @@ pub fn hlt_loop() -> ! {
 
 #[alloc_error_handler]
 fn default_handler(layout: core::alloc::Layout) -> ! {
+    allocator::trace::dump();
     panic!("memory allocation of {} bytes failed", layout.size())
 }
 
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
+    uart::set_synchronous();
+    log!("{}", info);
     hlt_loop();
 }
 
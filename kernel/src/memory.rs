@@ -1,17 +1,40 @@
 use {
-    crate::constant::PHYS_OFFSET,
-    alloc::collections::VecDeque,
+    crate::{constant::PHYS_OFFSET, cpu::FEATURES, log},
+    alloc::vec::Vec,
     bootloader::boot_info::{MemoryRegionKind, MemoryRegions},
+    spin::{Mutex, Once},
     x86_64::{
-        registers::control::Cr3,
+        align_down, align_up,
+        registers::{
+            control::Cr3,
+            model_specific::{Efer, EferFlags},
+        },
         structures::paging::{
+            mapper::{MapToError, MappedFrame, MapperAllSizes, TranslateResult},
             FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
-            PageTableFlags, PhysFrame, Size4KiB,
+            PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
         },
         PhysAddr, VirtAddr,
     },
 };
 
+/// The kernel page table, available after [`install`].
+pub static MAPPER: Once<Mutex<OffsetPageTable<'static>>> = Once::new();
+
+/// The global frame allocator, available after [`install`].
+pub static FRAME_ALLOCATOR: Once<Mutex<GlobalFrameAllocator>> = Once::new();
+
+/// An empty convenience trait that requires the `FrameAllocator` trait for all page sizes.
+pub trait FrameAllocatorAllSizes:
+    FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB> + FrameAllocator<Size1GiB>
+{
+}
+
+impl<T> FrameAllocatorAllSizes for T where
+    T: FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB> + FrameAllocator<Size1GiB>
+{
+}
+
 /// Initialize a new OffsetPageTable.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -21,6 +44,11 @@ use {
 pub unsafe fn init(
     memory_regions: &'static MemoryRegions,
 ) -> (OffsetPageTable<'static>, GlobalFrameAllocator) {
+    // `NO_EXECUTE` is a reserved bit until EFER.NXE is set, see `supported`
+    if FEATURES.nx {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+    }
+
     let level_4_table = active_level_4_table();
     (
         OffsetPageTable::new(level_4_table, PHYS_OFFSET),
@@ -44,64 +72,614 @@ unsafe fn active_level_4_table() -> &'static mut PageTable {
     &mut *page_table_ptr // unsafe
 }
 
+/// Hands the page table and frame allocator over to [`MAPPER`] and [`FRAME_ALLOCATOR`], so that
+/// they can be used after boot (e.g. by kernel JavaScript).
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: GlobalFrameAllocator) {
+    MAPPER.call_once(|| Mutex::new(mapper));
+    FRAME_ALLOCATOR.call_once(|| Mutex::new(frame_allocator));
+}
+
 pub fn phys2virt(addr: u64) -> VirtAddr {
     PHYS_OFFSET + addr
 }
 
+/// Translates a virtual address to the physical address it is mapped to.
+pub fn virt2phys(mapper: &impl Translate, addr: u64) -> Option<PhysAddr> {
+    mapper.translate_addr(VirtAddr::try_new(addr).ok()?)
+}
+
+/// Maps `[start, end]` to newly allocated frames.
+///
+/// The range is covered with the largest pages that the alignment, the remaining length and
+/// the frame allocator allow (1GiB, then 2MiB), falling back to 4KiB pages.
+///
+/// If the frames run out, returns the address of the first page that could not be mapped. The
+/// pages before it stay mapped.
 pub fn alloc_virt(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    mapper: &mut impl MapperAllSizes,
+    frame_allocator: &mut (impl FrameAllocatorAllSizes + FrameDeallocator<Size4KiB>),
     start: u64,
     end: u64,
     flags: Option<PageTableFlags>,
-) {
-    let page_range = {
-        let start = VirtAddr::new(start);
-        let end = VirtAddr::new(end);
-        let start_page = Page::containing_address(start);
-        let end_page = Page::containing_address(end);
-        Page::range_inclusive(start_page, end_page)
-    };
+) -> Result<(), VirtAddr> {
+    let flags = PageTableFlags::PRESENT
+        | flags.unwrap_or(PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE);
+    let mut addr = Page::<Size4KiB>::containing_address(VirtAddr::new(start))
+        .start_address()
+        .as_u64();
+
+    while addr <= end {
+        let remaining = end - addr + 1;
+
+        if FEATURES.page_1gb && fits::<Size1GiB>(addr, remaining) {
+            if let Some(frame) = FrameAllocator::<Size1GiB>::allocate_frame(frame_allocator) {
+                map_new_page(mapper, frame_allocator, addr, frame, flags)?;
+                addr += Size1GiB::SIZE;
+                continue;
+            }
+        }
+
+        if fits::<Size2MiB>(addr, remaining) {
+            if let Some(frame) = FrameAllocator::<Size2MiB>::allocate_frame(frame_allocator) {
+                map_new_page(mapper, frame_allocator, addr, frame, flags)?;
+                addr += Size2MiB::SIZE;
+                continue;
+            }
+        }
 
-    for page in page_range {
-        let frame = frame_allocator.allocate_frame().unwrap();
-        let flags = PageTableFlags::PRESENT | flags.unwrap_or(PageTableFlags::WRITABLE);
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator) }
-            .unwrap()
-            .flush();
+        let frame = FrameAllocator::<Size4KiB>::allocate_frame(frame_allocator)
+            .ok_or(VirtAddr::new(addr))?;
+        map_new_page(mapper, frame_allocator, addr, frame, flags)?;
+        addr += Size4KiB::SIZE;
     }
+
+    Ok(())
 }
 
+/// Identity maps `[start, end]`.
+///
+/// Like [`alloc_virt`], large ranges are mapped with 1GiB and 2MiB pages when possible. A huge
+/// page that would overlap existing mappings is split into smaller ones, and pages that are
+/// identity mapped already are left as they are, like [`map_mmio`] does.
+///
+/// On failure, the pages mapped before the one at the returned address stay mapped.
 pub fn alloc_phys(
-    mapper: &mut impl Mapper<Size4KiB>,
+    mapper: &mut impl MapperAllSizes,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
     start: u64,
     end: u64,
     flags: Option<PageTableFlags>,
+) -> Result<(), PageRangeError> {
+    let flags = PageTableFlags::PRESENT
+        | flags.unwrap_or(
+            PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | PageTableFlags::NO_EXECUTE,
+        );
+    let mut addr = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(start))
+        .start_address()
+        .as_u64();
+
+    while addr <= end {
+        let remaining = end - addr + 1;
+
+        addr += if FEATURES.page_1gb
+            && fits::<Size1GiB>(addr, remaining)
+            && map_identity::<Size1GiB>(mapper, frame_allocator, addr, flags)?
+        {
+            Size1GiB::SIZE
+        } else if fits::<Size2MiB>(addr, remaining)
+            && map_identity::<Size2MiB>(mapper, frame_allocator, addr, flags)?
+        {
+            Size2MiB::SIZE
+        } else if map_identity::<Size4KiB>(mapper, frame_allocator, addr, flags)?
+            || identity_mapped(mapper, addr)
+        {
+            Size4KiB::SIZE
+        } else {
+            return Err(PageRangeError::AlreadyMapped(VirtAddr::new(addr)));
+        };
+    }
+
+    Ok(())
+}
+
+/// Identity maps the page of size `S` at `addr`, and returns whether the range was free.
+fn map_identity<S: PageSize>(
+    mapper: &mut impl Mapper<S>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    addr: u64,
+    flags: PageTableFlags,
+) -> Result<bool, PageRangeError> {
+    let frame = PhysFrame::<S>::containing_address(PhysAddr::new(addr));
+    match map_page(mapper, frame_allocator, addr, frame, flags) {
+        Ok(()) => Ok(true),
+        Err(MapToError::FrameAllocationFailed) => {
+            Err(PageRangeError::OutOfFrames(VirtAddr::new(addr)))
+        }
+        Err(_) => Ok(false),
+    }
+}
+
+/// Whether the 4KiB page at `addr` is identity mapped, by a page of any size.
+fn identity_mapped(mapper: &impl MapperAllSizes, addr: u64) -> bool {
+    fn identity<S: PageSize>(mapper: &impl Mapper<S>, addr: u64) -> bool {
+        let page = Page::<S>::containing_address(VirtAddr::new(addr));
+        mapper.translate_page(page).map_or(false, |frame| {
+            frame.start_address().as_u64() == page.start_address().as_u64()
+        })
+    }
+
+    identity::<Size4KiB>(mapper, addr)
+        || identity::<Size2MiB>(mapper, addr)
+        || identity::<Size1GiB>(mapper, addr)
+}
+
+/// Whether a page of size `S` starting at `addr` fits into the `remaining` bytes.
+#[inline]
+fn fits<S: PageSize>(addr: u64, remaining: u64) -> bool {
+    addr % S::SIZE == 0 && remaining >= S::SIZE
+}
+
+#[inline]
+fn map_page<S: PageSize>(
+    mapper: &mut impl Mapper<S>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    addr: u64,
+    frame: PhysFrame<S>,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<S>> {
+    let page = Page::<S>::from_start_address(VirtAddr::new(addr)).unwrap();
+    let flags = supported(flags);
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator) }.map(|flush| flush.flush())
+}
+
+/// Maps a newly allocated frame, failing only if no frame is left for the page tables. The
+/// frame is given back then.
+#[inline]
+fn map_new_page<S: PageSize>(
+    mapper: &mut impl Mapper<S>,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    addr: u64,
+    frame: PhysFrame<S>,
+    flags: PageTableFlags,
+) -> Result<(), VirtAddr> {
+    match map_page(mapper, frame_allocator, addr, frame, flags) {
+        Err(MapToError::FrameAllocationFailed) => {
+            deallocate_frames(frame_allocator, frame.start_address(), frame.size());
+            Err(VirtAddr::new(addr))
+        }
+        result => {
+            result.unwrap();
+            Ok(())
+        }
+    }
+}
+
+/// Gives the 4KiB frames of `[start, start + size)` back to the frame allocator.
+fn deallocate_frames(
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+    start: PhysAddr,
+    size: u64,
 ) {
-    let frame_range = {
-        let start = PhysAddr::new(start);
-        let end = PhysAddr::new(end);
-        let start_frame = PhysFrame::containing_address(start);
-        let end_frame = PhysFrame::containing_address(end);
-        PhysFrame::range_inclusive(start_frame, end_frame)
+    let first = PhysFrame::containing_address(start);
+    let last = PhysFrame::containing_address(start + (size - 1));
+    for frame in PhysFrame::range_inclusive(first, last) {
+        unsafe { frame_deallocator.deallocate_frame(frame) };
+    }
+}
+
+/// Errors of the operations working on a range of pages.
+#[derive(Debug, Clone, Copy)]
+pub enum PageRangeError {
+    /// The address is not canonical.
+    InvalidAddress(u64),
+    /// The page at the address is not mapped.
+    NotMapped(VirtAddr),
+    /// The range covers only a part of the 2MiB/1GiB page at the address.
+    PartialHugePage(VirtAddr),
+    /// The page at the address is mapped to another frame.
+    AlreadyMapped(VirtAddr),
+    /// No frame was left for the page tables of the page at the address.
+    OutOfFrames(VirtAddr),
+}
+
+/// Marks the pages mapped by a [`map_mmio`] call in progress, so that they can be told apart from
+/// the pages identity mapped before.
+const MMIO_PENDING: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Marks the pages of [`map_mmio`] that [`unmap_mmio`] may unmap again. A page loses it once it
+/// is mapped without it, e.g. by the kernel for its own use.
+pub const MMIO_RELEASABLE: PageTableFlags = PageTableFlags::BIT_10;
+
+/// Identity maps `[start, end]` uncached like [`alloc_phys`], for MMIO found after boot. Pages
+/// that are identity mapped already are left as they are. The pages get the extra `flags`,
+/// either empty or [`MMIO_RELEASABLE`].
+///
+/// On failure, the pages mapped by this call are unmapped again.
+pub fn map_mmio(
+    mapper: &mut (impl MapperAllSizes + Translate),
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    start: u64,
+    end: u64,
+    flags: PageTableFlags,
+) -> Result<(), PageRangeError> {
+    VirtAddr::try_new(start).map_err(|_| PageRangeError::InvalidAddress(start))?;
+    VirtAddr::try_new(end).map_err(|_| PageRangeError::InvalidAddress(end))?;
+
+    let flags = flags
+        | PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::NO_EXECUTE;
+    let first = align_down(start, Size4KiB::SIZE);
+    let mut addr = first;
+    while addr <= end {
+        let virt = VirtAddr::new(addr);
+        let result = match mapper.translate(virt) {
+            TranslateResult::NotMapped => {
+                let frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(addr));
+                match map_page(mapper, frame_allocator, addr, frame, flags | MMIO_PENDING) {
+                    Ok(()) => Ok(()),
+                    Err(MapToError::FrameAllocationFailed) => {
+                        Err(PageRangeError::OutOfFrames(virt))
+                    }
+                    Err(_) => Err(PageRangeError::AlreadyMapped(virt)),
+                }
+            }
+            TranslateResult::Mapped {
+                frame,
+                offset,
+                flags: mapped,
+            } if frame.start_address() + offset == PhysAddr::new(addr) => {
+                if mapped.contains(MMIO_RELEASABLE) && !flags.contains(MMIO_RELEASABLE) {
+                    update_flags::<Size4KiB>(mapper, virt, mapped - MMIO_RELEASABLE);
+                }
+                Ok(())
+            }
+            _ => Err(PageRangeError::AlreadyMapped(virt)),
+        };
+        if let Err(err) = result {
+            finish_mmio(mapper, first, addr, false);
+            return Err(err);
+        }
+        addr += Size4KiB::SIZE;
+    }
+
+    finish_mmio(mapper, first, addr, true);
+    Ok(())
+}
+
+/// Clears [`MMIO_PENDING`] from the pages of `[start, limit)` mapped by [`map_mmio`], or unmaps
+/// them if not `keep`.
+fn finish_mmio(mapper: &mut (impl MapperAllSizes + Translate), start: u64, limit: u64, keep: bool) {
+    for addr in (start..limit).step_by(Size4KiB::SIZE as usize) {
+        let page = VirtAddr::new(addr);
+        if let TranslateResult::Mapped { frame, flags, .. } = mapper.translate(page) {
+            if !flags.contains(MMIO_PENDING) {
+                continue;
+            }
+            if keep {
+                update_flags::<Size4KiB>(mapper, page, flags - MMIO_PENDING);
+            } else {
+                unmap_page(mapper, page, &frame);
+            }
+        }
+    }
+}
+
+/// Unmaps the pages of `[start, end]` mapped by [`map_mmio`] with [`MMIO_RELEASABLE`], except the
+/// ones for which `in_use` returns `true`. The other pages are left as they are.
+pub fn unmap_mmio(
+    mapper: &mut (impl MapperAllSizes + Translate),
+    start: u64,
+    end: u64,
+    in_use: impl Fn(u64) -> bool,
+) {
+    for addr in (align_down(start, Size4KiB::SIZE)..=end).step_by(Size4KiB::SIZE as usize) {
+        let page = VirtAddr::new(addr);
+        if let TranslateResult::Mapped { frame, flags, .. } = mapper.translate(page) {
+            if flags.contains(MMIO_RELEASABLE) && !in_use(addr) {
+                unmap_page(mapper, page, &frame);
+            }
+        }
+    }
+}
+
+/// Unmaps `[start, end]` and gives the frames back to the frame allocator.
+///
+/// Unmapped pages in the range are skipped.
+pub fn dealloc_virt(
+    mapper: &mut (impl MapperAllSizes + Translate),
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+    start: u64,
+    end: u64,
+) -> Result<(), PageRangeError> {
+    for_each_page(mapper, start, end, |mapper, page, frame, _| {
+        unmap_page(mapper, page, frame);
+        deallocate_frames(frame_deallocator, frame.start_address(), frame.size());
+        Ok(())
+    })
+}
+
+/// Unmaps `[start, end]` without freeing the frames, e.g. MMIO windows mapped by [`alloc_phys`].
+///
+/// Unmapped pages in the range are skipped.
+pub fn dealloc_phys(
+    mapper: &mut (impl MapperAllSizes + Translate),
+    start: u64,
+    end: u64,
+) -> Result<(), PageRangeError> {
+    for_each_page(mapper, start, end, |mapper, page, frame, _| {
+        unmap_page(mapper, page, frame);
+        Ok(())
+    })
+}
+
+/// Drops `NO_EXECUTE` if EFER.NXE is not enabled, as the bit is reserved then.
+fn supported(mut flags: PageTableFlags) -> PageTableFlags {
+    if !Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+        flags.remove(PageTableFlags::NO_EXECUTE);
+    }
+    flags
+}
+
+/// Flags that [`protect`] keeps, as they describe the memory type rather than the access rights.
+const KEPT_FLAGS: PageTableFlags = PageTableFlags::WRITE_THROUGH
+    .union(PageTableFlags::NO_CACHE)
+    .union(PageTableFlags::GLOBAL);
+
+/// Replaces the flags of all pages in `[start, end]` with `PRESENT | flags`, keeping the cache
+/// attributes and `GLOBAL` of each page.
+///
+/// `NO_EXECUTE` is dropped if EFER.NXE is not enabled, as the bit is reserved then.
+pub fn protect(
+    mapper: &mut (impl MapperAllSizes + Translate),
+    start: u64,
+    end: u64,
+    flags: PageTableFlags,
+) -> Result<(), PageRangeError> {
+    let flags = supported(PageTableFlags::PRESENT | flags);
+
+    let mut addr = align_down(start, Size4KiB::SIZE);
+    while addr <= end {
+        let virt = VirtAddr::new(addr);
+        if let TranslateResult::NotMapped = mapper.translate(virt) {
+            return Err(PageRangeError::NotMapped(virt));
+        }
+        addr += Size4KiB::SIZE;
+    }
+
+    for_each_page(mapper, start, end, |mapper, page, frame, current| {
+        let flags = flags | (current & KEPT_FLAGS);
+        match frame {
+            MappedFrame::Size4KiB(_) => update_flags::<Size4KiB>(mapper, page, flags),
+            MappedFrame::Size2MiB(_) => update_flags::<Size2MiB>(mapper, page, flags),
+            MappedFrame::Size1GiB(_) => update_flags::<Size1GiB>(mapper, page, flags),
+        }
+        Ok(())
+    })
+}
+
+extern "C" {
+    /// Start of the kernel image, defined by the linker.
+    static __ehdr_start: u8;
+    /// End of the kernel text, defined by the linker.
+    static _etext: u8;
+}
+
+/// Enforces W^X on the active page table: the kernel image up to the end of its text is made
+/// read-only, and every other writable mapping is marked no-execute.
+///
+/// Must be called after [`crate::allocator::init`].
+pub fn enforce_wx(mapper: &mut OffsetPageTable) {
+    let text_start = unsafe { &__ehdr_start } as *const u8 as u64;
+    let text_end = unsafe { &_etext } as *const u8 as u64;
+    protect(mapper, text_start, text_end - 1, PageTableFlags::empty()).unwrap();
+
+    if !Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+        log!(
+            "W^X not enforced, no NX: text {:#x}..{:#x} read-only",
+            text_start,
+            text_end
+        );
+        return;
+    }
+
+    let mut writable = Vec::new();
+    walk(mapper, |run| {
+        if run.flags.contains(PageTableFlags::WRITABLE)
+            && !run.flags.contains(PageTableFlags::NO_EXECUTE)
+        {
+            writable.push(run);
+        }
+    });
+
+    for run in &writable {
+        let start = run.virt.as_u64();
+        let flags = run.flags | PageTableFlags::NO_EXECUTE;
+        protect(mapper, start, start + run.size - 1, flags).unwrap();
+    }
+
+    log!(
+        "W^X enforced, text {:#x}..{:#x} read-only, {} writable ranges no-execute",
+        text_start,
+        text_end,
+        writable.len()
+    );
+}
+
+/// Calls `f` with every mapped page in `[start, end]`, together with the frame and flags it is
+/// mapped to. Fails if a 2MiB/1GiB page is only partially covered by the range.
+fn for_each_page<M: MapperAllSizes + Translate>(
+    mapper: &mut M,
+    start: u64,
+    end: u64,
+    mut f: impl FnMut(&mut M, VirtAddr, &MappedFrame, PageTableFlags) -> Result<(), PageRangeError>,
+) -> Result<(), PageRangeError> {
+    VirtAddr::try_new(start).map_err(|_| PageRangeError::InvalidAddress(start))?;
+    VirtAddr::try_new(end).map_err(|_| PageRangeError::InvalidAddress(end))?;
+
+    let limit = align_down(end, Size4KiB::SIZE) + Size4KiB::SIZE;
+    let mut addr = align_down(start, Size4KiB::SIZE);
+    while addr < limit {
+        let page = VirtAddr::new(addr);
+        match mapper.translate(page) {
+            TranslateResult::Mapped { frame, flags, .. } => {
+                let size = frame.size();
+                if addr % size != 0 || addr + size > limit {
+                    return Err(PageRangeError::PartialHugePage(page));
+                }
+                f(mapper, page, &frame, flags)?;
+                addr += size;
+            }
+            _ => addr += Size4KiB::SIZE,
+        }
+    }
+    Ok(())
+}
+
+fn unmap_page(mapper: &mut impl MapperAllSizes, page: VirtAddr, frame: &MappedFrame) {
+    match frame {
+        MappedFrame::Size4KiB(_) => {
+            let page = Page::<Size4KiB>::containing_address(page);
+            Mapper::<Size4KiB>::unmap(mapper, page).unwrap().1.flush();
+        }
+        MappedFrame::Size2MiB(_) => {
+            let page = Page::<Size2MiB>::containing_address(page);
+            Mapper::<Size2MiB>::unmap(mapper, page).unwrap().1.flush();
+        }
+        MappedFrame::Size1GiB(_) => {
+            let page = Page::<Size1GiB>::containing_address(page);
+            Mapper::<Size1GiB>::unmap(mapper, page).unwrap().1.flush();
+        }
+    }
+}
+
+fn update_flags<S: PageSize>(mapper: &mut impl Mapper<S>, page: VirtAddr, flags: PageTableFlags) {
+    let page = Page::<S>::containing_address(page);
+    unsafe { mapper.update_flags(page, flags) }.unwrap().flush();
+}
+
+/// A run of virtually and physically contiguous pages with the same flags.
+#[derive(Debug, Clone, Copy)]
+pub struct Mapping {
+    pub virt: VirtAddr,
+    pub phys: PhysAddr,
+    pub size: u64,
+    pub flags: PageTableFlags,
+}
+
+/// Walks the active page table and calls `f` with every [`Mapping`], in ascending virtual
+/// address order. The `ACCESSED`, `DIRTY` and `HUGE_PAGE` flags are ignored when merging runs.
+pub fn walk(mapper: &mut OffsetPageTable, mut f: impl FnMut(Mapping)) {
+    const IGNORED: PageTableFlags = PageTableFlags::ACCESSED
+        .union(PageTableFlags::DIRTY)
+        .union(PageTableFlags::HUGE_PAGE);
+
+    let mut run: Option<Mapping> = None;
+    let mut push = |virt: u64, phys: PhysAddr, size: u64, flags: PageTableFlags| {
+        let virt = VirtAddr::new_truncate(virt);
+        let flags = flags - IGNORED;
+        match run.as_mut() {
+            Some(run)
+                if run.virt.as_u64().wrapping_add(run.size) == virt.as_u64()
+                    && run.phys + run.size == phys
+                    && run.flags == flags =>
+            {
+                run.size += size
+            }
+            _ => {
+                if let Some(run) = run.replace(Mapping {
+                    virt,
+                    phys,
+                    size,
+                    flags,
+                }) {
+                    f(run);
+                }
+            }
+        }
     };
 
-    for frame in frame_range {
-        let flags = PageTableFlags::PRESENT
-            | flags.unwrap_or(PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE);
-        unsafe { mapper.identity_map(frame, flags, frame_allocator) }
-            .unwrap()
-            .flush();
+    /// Returns the next level table of `entry`.
+    unsafe fn table(addr: PhysAddr) -> &'static PageTable {
+        &*phys2virt(addr.as_u64()).as_ptr()
+    }
+
+    for (i4, p4) in mapper.level_4_table().iter().enumerate() {
+        if p4.is_unused() {
+            continue;
+        }
+        for (i3, p3) in unsafe { table(p4.addr()) }.iter().enumerate() {
+            let virt = (i4 as u64) << 39 | (i3 as u64) << 30;
+            if p3.is_unused() {
+                continue;
+            } else if p3.flags().contains(PageTableFlags::HUGE_PAGE) {
+                push(virt, p3.addr(), Size1GiB::SIZE, p3.flags());
+                continue;
+            }
+            for (i2, p2) in unsafe { table(p3.addr()) }.iter().enumerate() {
+                let virt = virt | (i2 as u64) << 21;
+                if p2.is_unused() {
+                    continue;
+                } else if p2.flags().contains(PageTableFlags::HUGE_PAGE) {
+                    push(virt, p2.addr(), Size2MiB::SIZE, p2.flags());
+                    continue;
+                }
+                for (i1, p1) in unsafe { table(p2.addr()) }.iter().enumerate() {
+                    if !p1.is_unused() {
+                        let virt = virt | (i1 as u64) << 12;
+                        push(virt, p1.addr(), Size4KiB::SIZE, p1.flags());
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(run) = run {
+        f(run);
     }
 }
 
-type UsableFrameIterator = impl Iterator<Item = PhysFrame>;
+/// Marks the end of the lists of skipped frame ranges and of deallocated frames.
+const NO_RANGE: u64 = u64::MAX;
+
+/// Header written into the first frame of a range skipped to align a large frame, linking the
+/// skipped ranges into a list without allocating.
+#[repr(C)]
+struct SkippedRange {
+    /// End address of the range, exclusive.
+    end: u64,
+    /// Start address of the next range, or [`NO_RANGE`].
+    next: u64,
+}
+
+impl SkippedRange {
+    /// # Safety
+    ///
+    /// `start` must be the start address of a frame that is not in use.
+    unsafe fn at(start: u64) -> &'static mut Self {
+        &mut *phys2virt(start).as_mut_ptr()
+    }
+}
 
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
 pub struct GlobalFrameAllocator {
-    iter: UsableFrameIterator,
-    deallocated: Option<VecDeque<PhysFrame>>,
+    regions: &'static MemoryRegions,
+    /// Index of the usable region that `next` points into.
+    region: usize,
+    /// Start address of the first frame that has never been handed out.
+    next: u64,
+    /// Start address of the first range skipped while aligning 2MiB/1GiB frames, reused for
+    /// 4KiB frames, or [`NO_RANGE`]. See [`SkippedRange`].
+    skipped: u64,
+    /// Start address of the last deallocated frame, or [`NO_RANGE`]. Each deallocated frame
+    /// starts with the address of the one deallocated before it, so that deallocating never
+    /// allocates.
+    deallocated: u64,
+    /// Number of usable 4KiB frames.
+    total: u64,
+    /// Number of 4KiB frames handed out and not deallocated.
+    used: u64,
 }
 
 impl GlobalFrameAllocator {
@@ -111,35 +689,183 @@ impl GlobalFrameAllocator {
     /// memory map is valid. The main requirement is that all frames that are marked
     /// as `USABLE` in it are really unused.
     pub unsafe fn init(memory_regions: &'static MemoryRegions) -> Self {
-        // get usable regions from memory map
-        let regions = memory_regions.iter();
-        let usable_regions = regions.filter(|r| r.kind == MemoryRegionKind::Usable);
-        // map each region to its address range
-        let addr_ranges = usable_regions.map(|r| (r.start)..(r.end));
-        // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(Size4KiB::SIZE as usize));
-        // create `PhysFrame` types from the start addresses
-        let iter = frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)));
+        let (region, next) = memory_regions
+            .iter()
+            .enumerate()
+            .find(|(_, r)| r.kind == MemoryRegionKind::Usable)
+            .map_or((memory_regions.len(), 0), |(index, r)| (index, r.start));
+        let total = memory_regions
+            .iter()
+            .filter(|r| r.kind == MemoryRegionKind::Usable)
+            .map(|r| (r.end - r.start) / Size4KiB::SIZE)
+            .sum();
 
         GlobalFrameAllocator {
-            iter,
-            deallocated: None,
+            regions: memory_regions,
+            region,
+            next,
+            skipped: NO_RANGE,
+            deallocated: NO_RANGE,
+            total,
+            used: 0,
         }
     }
+
+    /// Returns the number of usable 4KiB frames.
+    pub fn total_frames(&self) -> u64 {
+        self.total
+    }
+
+    /// Whether `[start, end]` overlaps RAM, either handed out as frames or used by the kernel
+    /// and the bootloader.
+    pub fn is_ram(&self, start: u64, end: u64) -> bool {
+        self.regions.iter().any(|r| {
+            matches!(
+                r.kind,
+                MemoryRegionKind::Usable | MemoryRegionKind::Bootloader
+            ) && r.start <= end
+                && start < r.end
+        })
+    }
+
+    /// Returns the number of 4KiB frames in use.
+    pub fn used_frames(&self) -> u64 {
+        self.used
+    }
+
+    /// Allocates `size` bytes of physically contiguous frames below `limit` for DMA, starting
+    /// at a multiple of `align`, which must be a power of two. They are freed one by one.
+    pub fn allocate_dma(&mut self, size: u64, align: u64, limit: u64) -> Option<PhysAddr> {
+        let size = align_up(size, Size4KiB::SIZE);
+        let addr = self.allocate_contiguous(size, align.max(Size4KiB::SIZE), limit)?;
+        self.used += size / Size4KiB::SIZE;
+        Some(addr)
+    }
+
+    /// Allocates `size` bytes of physically contiguous, never used frames whose start address
+    /// is aligned to `align` and that end below `limit`. The frames jumped over for the
+    /// alignment are kept for later 4KiB allocations.
+    fn allocate_contiguous(&mut self, size: u64, align: u64, limit: u64) -> Option<PhysAddr> {
+        let (index, start) = self
+            .regions
+            .iter()
+            .enumerate()
+            .skip(self.region)
+            .filter(|(_, r)| r.kind == MemoryRegionKind::Usable)
+            .find_map(|(index, r)| {
+                let base = if index == self.region {
+                    r.start.max(self.next)
+                } else {
+                    r.start
+                };
+                let start = align_up(base, align);
+                (start + size <= r.end.min(limit)).then(|| (index, start))
+            })?;
+
+        if index == self.region {
+            self.skip(self.next, start);
+        } else {
+            self.skip(self.next, self.regions[self.region].end);
+            self.skip(self.regions[index].start, start);
+        }
+        self.region = index;
+        self.next = start + size;
+
+        Some(PhysAddr::new(start))
+    }
+
+    /// Puts the frames in `[start, end)` on the list of skipped ranges.
+    fn skip(&mut self, start: u64, end: u64) {
+        let start = align_up(start, Size4KiB::SIZE);
+        let end = align_down(end, Size4KiB::SIZE);
+        if start < end {
+            unsafe {
+                *SkippedRange::at(start) = SkippedRange {
+                    end,
+                    next: self.skipped,
+                }
+            };
+            self.skipped = start;
+        }
+    }
+
+    /// Takes the last deallocated frame.
+    fn allocate_deallocated(&mut self) -> Option<PhysFrame> {
+        if self.deallocated == NO_RANGE {
+            return None;
+        }
+
+        let addr = self.deallocated;
+        self.deallocated = unsafe { *phys2virt(addr).as_ptr::<u64>() };
+        Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    /// Takes the last frame of the first skipped range, so that the header stays in place.
+    fn allocate_skipped(&mut self) -> Option<PhysFrame> {
+        if self.skipped == NO_RANGE {
+            return None;
+        }
+
+        let start = self.skipped;
+        let range = unsafe { SkippedRange::at(start) };
+        range.end -= Size4KiB::SIZE;
+        if range.end == start {
+            self.skipped = range.next;
+        }
+        Some(PhysFrame::containing_address(PhysAddr::new(range.end)))
+    }
 }
 
+/// The memory map is never modified after boot, so the allocator can be moved into
+/// [`FRAME_ALLOCATOR`].
+unsafe impl Send for GlobalFrameAllocator {}
+
 unsafe impl FrameAllocator<Size4KiB> for GlobalFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        self.deallocated
-            .as_mut()
-            .and_then(|deallocated| deallocated.pop_front())
-            .or(self.iter.next())
+        let frame = self
+            .allocate_deallocated()
+            .or_else(|| self.allocate_skipped())
+            .or_else(|| {
+                self.allocate_contiguous(Size4KiB::SIZE, Size4KiB::SIZE, u64::MAX)
+                    .map(PhysFrame::containing_address)
+            })?;
+        self.used += 1;
+        Some(frame)
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for GlobalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let addr = self.allocate_contiguous(Size2MiB::SIZE, Size2MiB::SIZE, u64::MAX)?;
+        self.used += Size2MiB::SIZE / Size4KiB::SIZE;
+        Some(PhysFrame::containing_address(addr))
+    }
+}
+
+unsafe impl FrameAllocator<Size1GiB> for GlobalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size1GiB>> {
+        let addr = self.allocate_contiguous(Size1GiB::SIZE, Size1GiB::SIZE, u64::MAX)?;
+        self.used += Size1GiB::SIZE / Size4KiB::SIZE;
+        Some(PhysFrame::containing_address(addr))
     }
 }
 
 impl FrameDeallocator<Size4KiB> for GlobalFrameAllocator {
+    /// Frames outside the usable regions, e.g. of devices, were never handed out and are
+    /// refused.
     unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
-        let deallocated = self.deallocated.get_or_insert(VecDeque::new());
-        deallocated.push_back(frame);
+        let addr = frame.start_address().as_u64();
+        if !self
+            .regions
+            .iter()
+            .any(|r| r.kind == MemoryRegionKind::Usable && (r.start..r.end).contains(&addr))
+        {
+            log!("Refused to free frame {:#x}, it is not usable memory", addr);
+            return;
+        }
+
+        *phys2virt(addr).as_mut_ptr::<u64>() = self.deallocated;
+        self.deallocated = addr;
+        self.used = self.used.saturating_sub(1);
     }
 }
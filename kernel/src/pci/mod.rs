@@ -0,0 +1,463 @@
+/// https://wiki.osdev.org/PCI
+/// https://wiki.osdev.org/PCI_Express
+pub mod msi;
+
+use {
+    crate::{acpi::PCI_CONFIG_REGIONS, log, memory::alloc_phys, sync::IrqMutex},
+    acpi::PciConfigRegions,
+    alloc::{collections::BTreeSet, vec::Vec},
+    core::fmt,
+    spin::Once,
+    x86_64::{
+        instructions::port::Port,
+        structures::paging::{mapper::MapperAllSizes, FrameAllocator, Size4KiB},
+    },
+};
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+const VENDOR_ID: u16 = 0x00;
+const COMMAND: u16 = 0x04;
+const STATUS: u16 = 0x06;
+const REVISION: u16 = 0x08;
+const HEADER_TYPE: u16 = 0x0e;
+const BAR0: u16 = 0x10;
+const SECONDARY_BUS: u16 = 0x19;
+const CAPABILITIES_POINTER: u16 = 0x34;
+const INTERRUPT_LINE: u16 = 0x3c;
+/// The first extended capability, only reachable through ECAM.
+const EXTENDED_CAPABILITIES: u16 = 0x100;
+
+/// I/O space and memory space decoding of the command register.
+const COMMAND_DECODE: u32 = 0b11;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+/// Lets the function write to memory, for DMA and MSI.
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+/// Stops the function from asserting its legacy INTx pin.
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+/// The capabilities list bit of the status register.
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// Bound on the capabilities walked, in case a list loops.
+const MAX_CAPABILITIES: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Address {
+    pub const fn new(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            segment,
+            bus,
+            device,
+            function,
+        }
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment, self.bus, self.device, self.function
+        )
+    }
+}
+
+/// How the configuration space is reached.
+enum Access {
+    /// Memory-mapped, identity mapped by [`init`]
+    Ecam(&'static PciConfigRegions),
+    /// Through `CONFIG_ADDRESS` and `CONFIG_DATA`, limited to segment 0 and the first 256 bytes
+    Port,
+}
+
+static ACCESS: Once<Access> = Once::new();
+/// Serializes the two port accesses of a configuration read or write.
+static PORT_LOCK: IrqMutex<()> = IrqMutex::new(());
+
+fn ecam_address(address: Address, offset: u16) -> Option<u64> {
+    match ACCESS.get()? {
+        Access::Ecam(regions) => regions
+            .physical_address(
+                address.segment,
+                address.bus,
+                address.device,
+                address.function,
+            )
+            .map(|base| base + offset as u64),
+        Access::Port => None,
+    }
+}
+
+fn port_address(address: Address, offset: u16) -> Option<u32> {
+    (address.segment == 0 && offset < 0x100).then(|| {
+        1 << 31
+            | (address.bus as u32) << 16
+            | (address.device as u32) << 11
+            | (address.function as u32) << 8
+            | (offset as u32 & 0xfc)
+    })
+}
+
+/// Reads the dword at `offset`, which is rounded down to a multiple of 4. Reads all ones if the
+/// function or the offset cannot be reached, like a missing function.
+pub fn read(address: Address, offset: u16) -> u32 {
+    let offset = offset & !0b11;
+    if let Some(addr) = ecam_address(address, offset) {
+        return unsafe { (addr as *const u32).read_volatile() };
+    }
+
+    match port_address(address, offset) {
+        Some(config_address) => {
+            let _guard = PORT_LOCK.lock();
+            unsafe {
+                Port::<u32>::new(CONFIG_ADDRESS).write(config_address);
+                Port::<u32>::new(CONFIG_DATA).read()
+            }
+        }
+        None => u32::MAX,
+    }
+}
+
+/// Writes the dword at `offset`, which is rounded down to a multiple of 4.
+pub fn write(address: Address, offset: u16, value: u32) {
+    let offset = offset & !0b11;
+    if let Some(addr) = ecam_address(address, offset) {
+        unsafe { (addr as *mut u32).write_volatile(value) };
+        return;
+    }
+
+    if let Some(config_address) = port_address(address, offset) {
+        let _guard = PORT_LOCK.lock();
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(config_address);
+            Port::<u32>::new(CONFIG_DATA).write(value);
+        }
+    }
+}
+
+pub fn read_u8(address: Address, offset: u16) -> u8 {
+    (read(address, offset) >> ((offset & 0b11) * 8)) as u8
+}
+
+pub fn read_u16(address: Address, offset: u16) -> u16 {
+    (read(address, offset) >> ((offset & 0b10) * 8)) as u16
+}
+
+/// Writes the word at `offset` by rewriting its dword, so the other half must not have bits that
+/// are cleared by writing ones, like the status register.
+pub fn write_u16(address: Address, offset: u16, value: u16) {
+    let shift = (offset & 0b10) * 8;
+    let dword = read(address, offset) & !(0xffff << shift);
+    write(address, offset, dword | (value as u32) << shift);
+}
+
+/// Sets bits of the command register, without touching the status register.
+pub fn enable_command(address: Address, bits: u16) {
+    let command = read_u16(address, COMMAND);
+    write(address, COMMAND, (command | bits) as u32);
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BarKind {
+    Memory { prefetchable: bool, is_64: bool },
+    Io,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Bar {
+    pub index: u8,
+    pub kind: BarKind,
+    pub address: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Capability {
+    pub id: u16,
+    pub offset: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub address: Address,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    /// Without the multi-function bit
+    pub header_type: u8,
+    pub interrupt_line: u8,
+    pub interrupt_pin: u8,
+    pub bars: Vec<Bar>,
+    pub capabilities: Vec<Capability>,
+    pub extended_capabilities: Vec<Capability>,
+}
+
+impl Device {
+    fn probe(address: Address) -> Option<Self> {
+        let id = read(address, VENDOR_ID);
+        let vendor_id = id as u16;
+        if vendor_id == u16::MAX {
+            return None;
+        }
+
+        let class = read(address, REVISION);
+        let header_type = read_u8(address, HEADER_TYPE) & 0x7f;
+        let interrupt = read(address, INTERRUPT_LINE);
+
+        Some(Self {
+            address,
+            vendor_id,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type,
+            interrupt_line: interrupt as u8,
+            interrupt_pin: (interrupt >> 8) as u8,
+            bars: probe_bars(address, header_type),
+            capabilities: capabilities(address, header_type),
+            extended_capabilities: extended_capabilities(address),
+        })
+    }
+
+    /// Returns the capability with `id`, if the device has it.
+    pub fn capability(&self, id: u16) -> Option<Capability> {
+        self.capabilities.iter().find(|cap| cap.id == id).copied()
+    }
+}
+
+/// Sizes the BARs by writing all ones and reading back which bits stick. Decoding is off
+/// meanwhile, so that the device does not answer at the temporary addresses.
+fn probe_bars(address: Address, header_type: u8) -> Vec<Bar> {
+    let count = match header_type {
+        0 => 6,
+        1 => 2,
+        _ => return Vec::new(),
+    };
+
+    // The upper half is the status register, whose bits are cleared by writing ones
+    let command = read(address, COMMAND) & 0xffff;
+    write(address, COMMAND, command & !COMMAND_DECODE);
+
+    let mut bars = Vec::new();
+    let mut index = 0;
+    while index < count {
+        let offset = BAR0 + index as u16 * 4;
+        let original = read(address, offset);
+        write(address, offset, u32::MAX);
+        let mask = read(address, offset);
+        write(address, offset, original);
+
+        let bar = if original & 1 == 1 {
+            let mask = mask & !0b11;
+            (mask != 0).then(|| Bar {
+                index,
+                kind: BarKind::Io,
+                address: (original & !0b11) as u64,
+                size: (!mask & 0xffff) as u64 + 1,
+            })
+        } else {
+            let prefetchable = original & 0b1000 != 0;
+            let is_64 = (original >> 1) & 0b11 == 0b10;
+            let (high, high_mask) = if is_64 {
+                let high = read(address, offset + 4);
+                write(address, offset + 4, u32::MAX);
+                let high_mask = read(address, offset + 4);
+                write(address, offset + 4, high);
+                (high, high_mask)
+            } else {
+                (0, u32::MAX)
+            };
+
+            let mask = mask & !0b1111;
+            let implemented = mask != 0 || (is_64 && high_mask != 0);
+            let mask = (high_mask as u64) << 32 | mask as u64;
+            let bar = implemented.then(|| Bar {
+                index,
+                kind: BarKind::Memory {
+                    prefetchable,
+                    is_64,
+                },
+                address: (high as u64) << 32 | (original & !0b1111) as u64,
+                size: (!mask).wrapping_add(1),
+            });
+            if is_64 {
+                index += 1;
+            }
+            bar
+        };
+
+        bars.extend(bar);
+        index += 1;
+    }
+
+    write(address, COMMAND, command);
+    bars
+}
+
+fn capabilities(address: Address, header_type: u8) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if read_u16(address, STATUS) & STATUS_CAPABILITIES == 0 || header_type > 1 {
+        return capabilities;
+    }
+
+    let mut offset = (read_u8(address, CAPABILITIES_POINTER) & !0b11) as u16;
+    while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+        let header = read_u16(address, offset);
+        capabilities.push(Capability {
+            id: header & 0xff,
+            offset,
+        });
+        offset = (header >> 8) & 0xfc;
+    }
+    capabilities
+}
+
+fn extended_capabilities(address: Address) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if ecam_address(address, EXTENDED_CAPABILITIES).is_none() {
+        return capabilities;
+    }
+
+    let mut offset = EXTENDED_CAPABILITIES;
+    while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+        let header = read(address, offset);
+        if header == 0 || header == u32::MAX {
+            break;
+        }
+        capabilities.push(Capability {
+            id: header as u16,
+            offset,
+        });
+        offset = (header >> 20) as u16 & !0b11;
+    }
+    capabilities
+}
+
+/// The functions found by [`init`].
+pub static DEVICES: Once<Vec<Device>> = Once::new();
+
+/// Maps the ECAM window of segment 0 if the MCFG describes one, then enumerates the functions
+/// reachable from the host bridges. Must be called after [`crate::acpi::init`].
+pub fn init(mapper: &mut impl MapperAllSizes, frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    let access = match PCI_CONFIG_REGIONS.get() {
+        Some(regions) if map_ecam(mapper, frame_allocator, regions) => Access::Ecam(regions),
+        _ => Access::Port,
+    };
+    ACCESS.call_once(|| access);
+
+    let mut scan = Scan {
+        devices: Vec::new(),
+        buses: BTreeSet::new(),
+    };
+    let host = Address::new(0, 0, 0, 0);
+    if read_u8(host, HEADER_TYPE) & 0x80 == 0 {
+        scan.bus(0);
+    } else {
+        // Each function of the host bridge controls the bus with its number
+        for function in 0..8 {
+            if read_u16(Address::new(0, 0, 0, function), VENDOR_ID) == u16::MAX {
+                break;
+            }
+            scan.bus(function);
+        }
+    }
+
+    for device in &scan.devices {
+        log!(
+            "PCI {:?}: {:04x}:{:04x} class {:02x}.{:02x}.{:02x}",
+            device.address,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass,
+            device.prog_if
+        );
+    }
+    DEVICES.call_once(|| scan.devices);
+}
+
+/// Identity maps the configuration space of the buses of segment 0, which is contiguous. Returns
+/// whether it is usable, otherwise the I/O ports are used.
+fn map_ecam(
+    mapper: &mut impl MapperAllSizes,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    regions: &PciConfigRegions,
+) -> bool {
+    let mut buses = (0..=u8::MAX).filter(|&bus| regions.physical_address(0, bus, 0, 0).is_some());
+    let (first, last) = match buses.next() {
+        Some(first) => (first, buses.last().unwrap_or(first)),
+        None => return true,
+    };
+
+    let start = regions.physical_address(0, first, 0, 0).unwrap();
+    // The last function of the last device, 4KiB each
+    let end = regions.physical_address(0, last, 31, 7).unwrap() + 0xfff;
+    if let Err(err) = alloc_phys(mapper, frame_allocator, start, end, None) {
+        log!("PCI: ECAM not mapped ({:?})", err);
+        return false;
+    }
+    log!("PCI: ECAM of buses {}..={} at {:#x}", first, last, start);
+    true
+}
+
+struct Scan {
+    devices: Vec<Device>,
+    /// Guards against bridges that lead back to a scanned bus
+    buses: BTreeSet<u8>,
+}
+
+impl Scan {
+    fn bus(&mut self, bus: u8) {
+        if !self.buses.insert(bus) {
+            return;
+        }
+        for device in 0..32 {
+            self.device(bus, device);
+        }
+    }
+
+    fn device(&mut self, bus: u8, device: u8) {
+        let address = Address::new(0, bus, device, 0);
+        if read_u16(address, VENDOR_ID) == u16::MAX {
+            return;
+        }
+
+        let functions = if read_u8(address, HEADER_TYPE) & 0x80 == 0 {
+            1
+        } else {
+            8
+        };
+        for function in 0..functions {
+            self.function(Address::new(0, bus, device, function));
+        }
+    }
+
+    fn function(&mut self, address: Address) {
+        let device = match Device::probe(address) {
+            Some(device) => device,
+            None => return,
+        };
+
+        // PCI-to-PCI bridge
+        let secondary_bus = (device.class == 0x6 && device.subclass == 0x4)
+            .then(|| read_u8(address, SECONDARY_BUS));
+        self.devices.push(device);
+        if let Some(secondary_bus) = secondary_bus {
+            self.bus(secondary_bus);
+        }
+    }
+}
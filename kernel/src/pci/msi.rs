@@ -0,0 +1,192 @@
+/// https://wiki.osdev.org/PCI#Message_Signaled_Interrupts
+use {
+    super::{
+        enable_command, read, read_u16, write, write_u16, Address, BarKind, Device,
+        COMMAND_BUS_MASTER, COMMAND_INTX_DISABLE, COMMAND_MEMORY,
+    },
+    crate::{
+        apic::LOCAL_APIC,
+        constant::{LOCAL_APIC_ID, MSI_VECTORS},
+        memory::{map_mmio, FRAME_ALLOCATOR, MAPPER},
+        sync::IrqMutex,
+    },
+    core::fmt,
+    x86_64::structures::paging::PageTableFlags,
+};
+
+pub const CAPABILITY_MSI: u16 = 0x05;
+pub const CAPABILITY_MSIX: u16 = 0x11;
+
+const MSI_ENABLE: u16 = 1;
+const MSI_64_BIT: u16 = 1 << 7;
+/// Multiple Message Enable, the log2 of the vectors granted.
+const MSI_MULTIPLE_MESSAGE: u16 = 0b111 << 4;
+
+const MSIX_TABLE_SIZE: u16 = 0x7ff;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_ENTRY_MASKED: u32 = 1;
+
+/// Called in the interrupt handler of the vector, so it must not allocate. Work should be left
+/// to [`crate::deferred`].
+pub type Handler = fn(vector: u8);
+
+const VECTORS: usize = (MSI_VECTORS.end - MSI_VECTORS.start) as usize;
+
+/// The handlers of [`MSI_VECTORS`], a vector is free if it has none.
+static HANDLERS: IrqMutex<[Option<Handler>; VECTORS]> = IrqMutex::new([None; VECTORS]);
+
+#[derive(Debug, Clone, Copy)]
+pub enum MsiError {
+    /// The function has no MSI or MSI-X capability.
+    NoCapability,
+    /// Messages target the local APIC, which is not used.
+    NoLocalApic,
+    /// Every vector of [`MSI_VECTORS`] is taken.
+    NoVector,
+    /// The MSI-X table has no entry with this index.
+    InvalidEntry(u16),
+    /// The MSI-X table is not in a memory BAR, or cannot be mapped.
+    InvalidTable,
+}
+
+impl fmt::Display for MsiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoCapability => write!(f, "no MSI capability"),
+            Self::NoLocalApic => write!(f, "no local APIC"),
+            Self::NoVector => write!(f, "no free interrupt vector"),
+            Self::InvalidEntry(entry) => write!(f, "no MSI-X entry {}", entry),
+            Self::InvalidTable => write!(f, "invalid MSI-X table"),
+        }
+    }
+}
+
+/// Binds `handler` to a free vector.
+pub fn alloc_vector(handler: Handler) -> Result<u8, MsiError> {
+    let mut handlers = HANDLERS.lock();
+    let index = handlers
+        .iter()
+        .position(Option::is_none)
+        .ok_or(MsiError::NoVector)?;
+    handlers[index] = Some(handler);
+    Ok(MSI_VECTORS.start + index as u8)
+}
+
+/// Frees a vector of [`alloc_vector`]. The device must not use it anymore.
+pub fn free_vector(vector: u8) {
+    if MSI_VECTORS.contains(&vector) {
+        HANDLERS.lock()[(vector - MSI_VECTORS.start) as usize] = None;
+    }
+}
+
+/// Runs the handler of `vector`, called by the interrupt handler of [`MSI_VECTORS`].
+pub fn dispatch(vector: u8) {
+    let handler = HANDLERS.lock()[(vector - MSI_VECTORS.start) as usize];
+    if let Some(handler) = handler {
+        handler(vector);
+    }
+}
+
+/// The address and data of a fixed, edge-triggered message to the local APIC.
+fn message(vector: u8) -> (u64, u32) {
+    (0xfee0_0000 | (LOCAL_APIC_ID as u64) << 12, vector as u32)
+}
+
+/// Sets up MSI with a single vector bound to `handler`, and disables INTx.
+pub fn enable_msi(device: &Device, handler: Handler) -> Result<u8, MsiError> {
+    let capability = device
+        .capability(CAPABILITY_MSI)
+        .ok_or(MsiError::NoCapability)?;
+    if !LOCAL_APIC.is_completed() {
+        return Err(MsiError::NoLocalApic);
+    }
+
+    let vector = alloc_vector(handler)?;
+    let (address, data) = message(vector);
+    let (at, offset) = (device.address, capability.offset);
+
+    let control = read_u16(at, offset + 2) & !(MSI_ENABLE | MSI_MULTIPLE_MESSAGE);
+    write_u16(at, offset + 2, control);
+    write(at, offset + 4, address as u32);
+    if control & MSI_64_BIT != 0 {
+        write(at, offset + 8, (address >> 32) as u32);
+        write_u16(at, offset + 12, data as u16);
+    } else {
+        write_u16(at, offset + 8, data as u16);
+    }
+
+    enable_command(at, COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE);
+    write_u16(at, offset + 2, control | MSI_ENABLE);
+    Ok(vector)
+}
+
+/// Programs and unmasks the MSI-X table entry `entry` with a vector bound to `handler`, then
+/// enables MSI-X and disables INTx. Other entries stay as they are, masked after reset.
+///
+/// The table is identity mapped, so this must be called after [`crate::memory::install`].
+pub fn enable_msix(device: &Device, entry: u16, handler: Handler) -> Result<u8, MsiError> {
+    let capability = device
+        .capability(CAPABILITY_MSIX)
+        .ok_or(MsiError::NoCapability)?;
+    if !LOCAL_APIC.is_completed() {
+        return Err(MsiError::NoLocalApic);
+    }
+
+    let (at, offset) = (device.address, capability.offset);
+    let control = read_u16(at, offset + 2);
+    if entry > control & MSIX_TABLE_SIZE {
+        return Err(MsiError::InvalidEntry(entry));
+    }
+
+    let entry_address = table(device, at, offset)? + entry as u64 * MSIX_ENTRY_SIZE;
+    let vector = alloc_vector(handler)?;
+    let (address, data) = message(vector);
+
+    enable_command(
+        at,
+        COMMAND_MEMORY | COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE,
+    );
+    let entry = entry_address as *mut u32;
+    unsafe {
+        entry.add(3).write_volatile(MSIX_ENTRY_MASKED);
+        entry.write_volatile(address as u32);
+        entry.add(1).write_volatile((address >> 32) as u32);
+        entry.add(2).write_volatile(data);
+        entry.add(3).write_volatile(0);
+    }
+    write_u16(
+        at,
+        offset + 2,
+        (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK,
+    );
+    Ok(vector)
+}
+
+/// Returns the address of the MSI-X table, after mapping it.
+fn table(device: &Device, at: Address, offset: u16) -> Result<u64, MsiError> {
+    let table = read(at, offset + 4);
+    let bar = device
+        .bars
+        .iter()
+        .find(|bar| bar.index as u32 == table & 0b111)
+        .filter(|bar| matches!(bar.kind, BarKind::Memory { .. }))
+        .ok_or(MsiError::InvalidTable)?;
+
+    let size = ((read_u16(at, offset + 2) & MSIX_TABLE_SIZE) as u64 + 1) * MSIX_ENTRY_SIZE;
+    let start = bar.address + (table & !0b111) as u64;
+    if start + size > bar.address + bar.size {
+        return Err(MsiError::InvalidTable);
+    }
+
+    map_mmio(
+        &mut *MAPPER.get().unwrap().lock(),
+        &mut *FRAME_ALLOCATOR.get().unwrap().lock(),
+        start,
+        start + size - 1,
+        PageTableFlags::empty(),
+    )
+    .map_err(|_| MsiError::InvalidTable)?;
+    Ok(start)
+}
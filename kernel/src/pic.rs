@@ -0,0 +1,116 @@
+/// https://wiki.osdev.org/8259_PIC
+use {
+    crate::constant::IOApicInt,
+    x86_64::instructions::port::{Port, PortWriteOnly},
+};
+
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xa0;
+const SLAVE_DATA: u16 = 0xa1;
+
+/// Starts the initialization, with ICW4
+const ICW1_INIT: u8 = 0x11;
+const ICW4_8086: u8 = 0x01;
+const OCW3_READ_ISR: u8 = 0x0b;
+const EOI: u8 = 0x20;
+/// The slave is wired to IRQ 2 of the master
+const CASCADE: u8 = 2;
+
+/// Masks every IRQ, for when the I/O APIC is used.
+pub fn disable() {
+    unsafe { PortWriteOnly::new(SLAVE_DATA).write(u8::MAX) };
+    unsafe { PortWriteOnly::new(MASTER_DATA).write(u8::MAX) };
+}
+
+/// Remaps the IRQs to the vectors of [`IOApicInt`], then masks all but the cascade.
+pub fn init() {
+    let mut master_command = PortWriteOnly::<u8>::new(MASTER_COMMAND);
+    let mut master_data = PortWriteOnly::<u8>::new(MASTER_DATA);
+    let mut slave_command = PortWriteOnly::<u8>::new(SLAVE_COMMAND);
+    let mut slave_data = PortWriteOnly::<u8>::new(SLAVE_DATA);
+
+    unsafe {
+        master_command.write(ICW1_INIT);
+        io_wait();
+        slave_command.write(ICW1_INIT);
+        io_wait();
+        master_data.write(IOApicInt::OFFSET);
+        io_wait();
+        slave_data.write(IOApicInt::OFFSET + 8);
+        io_wait();
+        master_data.write(1 << CASCADE);
+        io_wait();
+        slave_data.write(CASCADE);
+        io_wait();
+        master_data.write(ICW4_8086);
+        io_wait();
+        slave_data.write(ICW4_8086);
+        io_wait();
+
+        master_data.write(!(1 << CASCADE));
+        slave_data.write(u8::MAX);
+    }
+}
+
+/// Gives the PIC time to react, by writing to an unused port.
+fn io_wait() {
+    unsafe { PortWriteOnly::<u8>::new(0x80).write(0) };
+}
+
+pub fn enable_irq(irq: IOApicInt) {
+    set_masked(irq, false);
+}
+
+pub fn disable_irq(irq: IOApicInt) {
+    set_masked(irq, true);
+}
+
+fn set_masked(irq: IOApicInt, masked: bool) {
+    let line = irq as u8 - IOApicInt::OFFSET;
+    let (mut data, bit) = match line {
+        0..=7 => (Port::<u8>::new(MASTER_DATA), line),
+        _ => (Port::<u8>::new(SLAVE_DATA), line - 8),
+    };
+    unsafe {
+        let mask = data.read();
+        data.write(if masked {
+            mask | 1 << bit
+        } else {
+            mask & !(1 << bit)
+        });
+    }
+}
+
+/// Returns whether the interrupt `vector` is a spurious IRQ 7 or 15, which must not be
+/// acknowledged.
+pub fn is_spurious(vector: u8) -> bool {
+    let line = vector.wrapping_sub(IOApicInt::OFFSET);
+    let (command, bit) = match line {
+        7 => (MASTER_COMMAND, 7),
+        15 => (SLAVE_COMMAND, 7),
+        _ => return false,
+    };
+
+    let mut command = Port::<u8>::new(command);
+    let in_service = unsafe {
+        command.write(OCW3_READ_ISR);
+        command.read()
+    };
+    if in_service & (1 << bit) != 0 {
+        return false;
+    }
+
+    // The master did raise IRQ 2 for the slave
+    if line == 15 {
+        unsafe { PortWriteOnly::new(MASTER_COMMAND).write(EOI) };
+    }
+    true
+}
+
+pub fn end_of_interrupt(vector: u8) {
+    if vector >= IOApicInt::OFFSET + 8 {
+        unsafe { PortWriteOnly::new(SLAVE_COMMAND).write(EOI) };
+    }
+    unsafe { PortWriteOnly::new(MASTER_COMMAND).write(EOI) };
+}
@@ -0,0 +1,79 @@
+/// https://wiki.osdev.org/Programmable_Interval_Timer
+use {
+    core::sync::atomic::{AtomicU32, Ordering},
+    x86_64::instructions::port::{Port, PortWriteOnly},
+};
+
+/// Frequency of the oscillator in Hz.
+pub const FREQUENCY: u64 = 1_193_182;
+/// The longest interval of a channel, with the largest reload value.
+const MAX_MS: u32 = (u16::MAX as u64 * 1000 / FREQUENCY) as u32;
+
+const CHANNEL0: u16 = 0x40;
+const CHANNEL2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Gate of channel 2 (bit 0) and its output (bit 5)
+const CHANNEL2_GATE: u16 = 0x61;
+
+/// Interrupts per period of [`start_periodic`]
+static PERIODS: AtomicU32 = AtomicU32::new(1);
+static TICKS: AtomicU32 = AtomicU32::new(0);
+
+fn reload_value(ms: u32) -> u16 {
+    (FREQUENCY * ms as u64 / 1000) as u16
+}
+
+/// Busy-waits with channel 2, which does not raise interrupts.
+pub fn wait(ms: u32) {
+    let mut gate = Port::<u8>::new(CHANNEL2_GATE);
+    let mut command = PortWriteOnly::<u8>::new(COMMAND);
+    let mut channel2 = PortWriteOnly::<u8>::new(CHANNEL2);
+
+    let mut left = ms;
+    while left > 0 {
+        let step = left.min(MAX_MS);
+        let [low, high] = reload_value(step).to_le_bytes();
+        unsafe {
+            // Gate low and the speaker off while programming
+            let value = gate.read() & !0b11;
+            gate.write(value);
+            // Channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count)
+            command.write(0b1011_0000);
+            channel2.write(low);
+            channel2.write(high);
+            // Counting starts once the gate is high, the output goes high at zero
+            gate.write(value | 1);
+            while gate.read() & 0x20 == 0 {
+                core::hint::spin_loop();
+            }
+        }
+        left -= step;
+    }
+}
+
+/// Raises IRQ 0 periodically with channel 0. Periods longer than a channel can count are split
+/// into several interrupts, see [`tick`].
+pub fn start_periodic(ms: u32) {
+    let periods = ((ms + MAX_MS - 1) / MAX_MS).max(1);
+    PERIODS.store(periods, Ordering::Relaxed);
+    TICKS.store(0, Ordering::Relaxed);
+
+    let [low, high] = reload_value(ms / periods).to_le_bytes();
+    let mut channel0 = PortWriteOnly::<u8>::new(CHANNEL0);
+    unsafe {
+        // Channel 0, lobyte/hibyte, mode 2 (rate generator)
+        PortWriteOnly::<u8>::new(COMMAND).write(0b0011_0100);
+        channel0.write(low);
+        channel0.write(high);
+    }
+}
+
+/// Called on IRQ 0, returns whether a whole period of [`start_periodic`] has elapsed.
+pub fn tick() -> bool {
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    if ticks < PERIODS.load(Ordering::Relaxed) {
+        return false;
+    }
+    TICKS.store(0, Ordering::Relaxed);
+    true
+}
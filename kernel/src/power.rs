@@ -0,0 +1,436 @@
+/// https://wiki.osdev.org/Shutdown
+/// https://wiki.osdev.org/Reboot
+/// https://uefi.org/specs/ACPI/6.4/04_ACPI_Hardware_Specification/ACPI_Hardware_Specification.html#fixed-hardware-features
+use {
+    crate::{acpi::read_table, apic, constant::IOApicInt, deferred, log, memory::phys2virt},
+    acpi::{
+        address::{AddressSpace, GenericAddress},
+        fadt::Fadt,
+        sdt::SdtHeader,
+    },
+    core::{arch::asm, mem::size_of},
+    spin::Once,
+    x2apic::ioapic::IrqFlags,
+    x86_64::{
+        instructions::{
+            interrupts,
+            port::{Port, PortWriteOnly},
+            tables::lidt,
+        },
+        structures::DescriptorTablePointer,
+        VirtAddr,
+    },
+};
+
+/// Sleep type field of the PM1 control registers.
+const SLP_TYP_SHIFT: u64 = 10;
+const SLP_TYP_MASK: u64 = 0b111 << SLP_TYP_SHIFT;
+/// Enters the sleep state selected by the sleep type.
+const SLP_EN: u64 = 1 << 13;
+/// Set when the platform is in ACPI mode.
+const SCI_EN: u64 = 1;
+/// Power button bit of the PM1 status and enable registers.
+const PWRBTN: u64 = 1 << 8;
+
+/// Shutdown ports of emulators and the values to write to them, tried when ACPI fails: QEMU,
+/// older QEMU and Bochs, VirtualBox.
+pub const SHUTDOWN_PORTS: [(u16, u16); 3] = [(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)];
+/// Shutdown port of Cloud Hypervisor, which takes a byte.
+pub const SHUTDOWN_PORT_BYTE: (u16, u8) = (0x600, 0x34);
+/// Status and command port of the keyboard controller, which can pulse the reset line.
+pub const KEYBOARD_CONTROLLER: u16 = 0x64;
+
+/// Returns the I/O ports that [`poweroff`] and [`reboot`] fall back to.
+pub fn fallback_ports() -> impl Iterator<Item = u16> {
+    SHUTDOWN_PORTS
+        .into_iter()
+        .map(|(port, _)| port)
+        .chain([SHUTDOWN_PORT_BYTE.0, KEYBOARD_CONTROLLER])
+}
+
+/// Each register block is `None` if the FADT has no valid address for it.
+struct Power {
+    pm1a_control: Option<GenericAddress>,
+    pm1b_control: Option<GenericAddress>,
+    /// The PM1 status and enable registers
+    pm1a_event: Option<(GenericAddress, GenericAddress)>,
+    pm1b_event: Option<(GenericAddress, GenericAddress)>,
+    pm_timer: Option<GenericAddress>,
+    sci: Option<IOApicInt>,
+    /// `SLP_TYPa` and `SLP_TYPb` of `\_S5`, if found in the DSDT
+    s5: Option<(u64, u64)>,
+    /// The reset register and the value to write to it
+    reset: Option<(GenericAddress, u8)>,
+    smi_command: u16,
+    acpi_enable: u8,
+}
+
+static POWER: Once<Power> = Once::new();
+static POWER_BUTTON: Once<fn()> = Once::new();
+
+/// Sets the function called in the main loop when the power button is pressed.
+pub fn set_power_button_handler(handler: fn()) {
+    POWER_BUTTON.call_once(|| handler);
+}
+
+/// Reads what is needed to power off and reset from the FADT and the DSDT, then enables the
+/// power button event. Must be called after [`crate::acpi::init`] and [`crate::apic::init`].
+pub fn init(fadt: &Fadt) {
+    let s5 = read_table(b"DSDT", 0).and_then(|dsdt| parse_s5(&dsdt[size_of::<SdtHeader>()..]));
+    let reset = fadt
+        .reset_register()
+        .ok()
+        .filter(valid)
+        .map(|reset| (reset, fadt.reset_value));
+
+    let power = POWER.call_once(|| Power {
+        pm1a_control: fadt.pm1a_control_block().ok().filter(valid),
+        pm1b_control: fadt.pm1b_control_block().ok().flatten().filter(valid),
+        pm1a_event: fadt
+            .pm1a_event_block()
+            .ok()
+            .filter(valid)
+            .map(split_event_block),
+        pm1b_event: fadt
+            .pm1b_event_block()
+            .ok()
+            .flatten()
+            .filter(valid)
+            .map(split_event_block),
+        pm_timer: fadt.pm_timer_block().ok().flatten().filter(valid),
+        sci: u8::try_from(fadt.sci_interrupt)
+            .ok()
+            .and_then(IOApicInt::from_isa),
+        s5,
+        reset,
+        smi_command: fadt.smi_cmd_port as u16,
+        acpi_enable: fadt.acpi_enable,
+    });
+
+    log!(
+        "Power: S5 {:?}, reset register {}",
+        power.s5,
+        if power.reset.is_some() {
+            "present"
+        } else {
+            "missing"
+        }
+    );
+
+    enable_power_button(power);
+}
+
+/// Returns whether a register block of the FADT is present, old tables leave it zeroed.
+fn valid(block: &GenericAddress) -> bool {
+    block.address != 0
+}
+
+/// Splits a PM1 event block into its status and enable registers, each half of the block.
+fn split_event_block(block: GenericAddress) -> (GenericAddress, GenericAddress) {
+    let bit_width = block.bit_width / 2;
+    let status = GenericAddress { bit_width, ..block };
+    let enable = GenericAddress {
+        bit_width,
+        address: block.address + bit_width as u64 / 8,
+        ..block
+    };
+    (status, enable)
+}
+
+fn pm1_events(power: &Power) -> impl Iterator<Item = &(GenericAddress, GenericAddress)> {
+    power.pm1a_event.iter().chain(&power.pm1b_event)
+}
+
+/// Returns the I/O port ranges of the FADT registers the kernel uses, as `(base, count)`: the
+/// PM1 blocks, the PM timer, the reset register and the SMI command port. Empty before [`init`].
+pub fn acpi_ports() -> impl Iterator<Item = (u16, u32)> {
+    POWER.get().into_iter().flat_map(|power| {
+        let events = pm1_events(power).flat_map(|(status, enable)| [*status, *enable]);
+        power
+            .pm1a_control
+            .iter()
+            .chain(&power.pm1b_control)
+            .copied()
+            .chain(events)
+            .chain(power.pm_timer)
+            .chain(power.reset.map(|(reset, _)| reset))
+            .filter(|register| matches!(register.address_space, AddressSpace::SystemIo))
+            .map(|register| {
+                (
+                    register.address as u16,
+                    (register.bit_width as u32 / 8).max(1),
+                )
+            })
+            .chain((power.smi_command != 0).then(|| (power.smi_command, 1)))
+    })
+}
+
+/// Switches to ACPI mode and routes the SCI, so that pressing the power button raises it.
+fn enable_power_button(power: &Power) {
+    let sci = match power.sci {
+        Some(sci) => sci,
+        None => {
+            log!("Power button disabled, SCI is not an ISA IRQ");
+            return;
+        }
+    };
+
+    unsafe {
+        enable_acpi(power);
+        for (status, enable) in pm1_events(power) {
+            // The status bits are cleared by writing ones
+            write_register(status, PWRBTN);
+            let value = read_register(enable).unwrap_or(0);
+            write_register(enable, value | PWRBTN);
+        }
+    }
+    // The SCI is level-triggered and active-low unless overridden
+    apic::enable_irq(sci, IrqFlags::LEVEL_TRIGGERED | IrqFlags::LOW_ACTIVE);
+    log!("IRQ {:?} enabled for SCI", sci);
+}
+
+/// Returns the interrupt the SCI is routed to.
+pub fn sci() -> Option<IOApicInt> {
+    POWER.get().and_then(|power| power.sci)
+}
+
+/// Acknowledges the fixed events of the PM1 status registers. Called by the SCI handler, so it
+/// must not allocate.
+pub fn handle_sci() {
+    let power = match POWER.get() {
+        Some(power) => power,
+        None => return,
+    };
+
+    let mut pressed = false;
+    for (status, _) in pm1_events(power) {
+        let value = unsafe { read_register(status) }.unwrap_or(0);
+        if value & PWRBTN != 0 {
+            unsafe { write_register(status, PWRBTN) };
+            pressed = true;
+        }
+    }
+    if pressed {
+        deferred::defer(power_button, 0);
+    }
+}
+
+fn power_button(_: u64) {
+    log!("Power button pressed");
+    if let Some(handler) = POWER_BUTTON.get() {
+        handler();
+    }
+}
+
+/// Finds the package of `Name (\_S5, Package () { SLP_TYPa, SLP_TYPb, ... })` in the AML.
+///
+/// The DSDT of most firmware declares it at the top level, so there is no need for a full AML
+/// interpreter.
+pub fn parse_s5(aml: &[u8]) -> Option<(u64, u64)> {
+    const NAME_OP: u8 = 0x08;
+    const ROOT_CHAR: u8 = b'\\';
+    const PACKAGE_OP: u8 = 0x12;
+
+    let start = aml.windows(4).enumerate().find_map(|(i, name)| {
+        let declared = match i {
+            0 => false,
+            1 => aml[0] == NAME_OP,
+            _ => aml[i - 1] == NAME_OP || (aml[i - 1] == ROOT_CHAR && aml[i - 2] == NAME_OP),
+        };
+        (name == b"_S5_" && declared).then(|| i + 4)
+    })?;
+
+    let mut aml = aml.get(start..)?;
+    if *aml.first()? != PACKAGE_OP {
+        return None;
+    }
+    // The two high bits of the lead byte give the number of extra PkgLength bytes
+    let pkg_length = 1 + (*aml.get(1)? >> 6) as usize;
+    // Skip the opcode, the PkgLength and NumElements
+    aml = aml.get(1 + pkg_length + 1..)?;
+
+    let slp_typ_a = parse_integer(&mut aml)?;
+    let slp_typ_b = parse_integer(&mut aml)?;
+    Some((slp_typ_a, slp_typ_b))
+}
+
+/// Parses a constant integer and advances `aml` past it.
+fn parse_integer(aml: &mut &[u8]) -> Option<u64> {
+    const ZERO_OP: u8 = 0x00;
+    const ONE_OP: u8 = 0x01;
+    const BYTE_PREFIX: u8 = 0x0a;
+    const WORD_PREFIX: u8 = 0x0b;
+    const DWORD_PREFIX: u8 = 0x0c;
+
+    let (value, length) = match *aml.first()? {
+        ZERO_OP => (0, 1),
+        ONE_OP => (1, 1),
+        BYTE_PREFIX => (*aml.get(1)? as u64, 2),
+        WORD_PREFIX => (
+            u16::from_le_bytes(aml.get(1..3)?.try_into().ok()?) as u64,
+            3,
+        ),
+        DWORD_PREFIX => (
+            u32::from_le_bytes(aml.get(1..5)?.try_into().ok()?) as u64,
+            5,
+        ),
+        _ => return None,
+    };
+    *aml = &aml[length..];
+    Some(value)
+}
+
+/// Reads a register given by a generic address. Only system memory and I/O are supported.
+unsafe fn read_register(register: &GenericAddress) -> Option<u64> {
+    match register.address_space {
+        AddressSpace::SystemIo => {
+            let port = register.address as u16;
+            match register.bit_width {
+                8 => Some(Port::<u8>::new(port).read() as u64),
+                16 => Some(Port::<u16>::new(port).read() as u64),
+                32 => Some(Port::<u32>::new(port).read() as u64),
+                _ => None,
+            }
+        }
+        AddressSpace::SystemMemory => {
+            let addr = phys2virt(register.address).as_u64();
+            match register.bit_width {
+                8 => Some((addr as *const u8).read_volatile() as u64),
+                16 => Some((addr as *const u16).read_volatile() as u64),
+                32 => Some((addr as *const u32).read_volatile() as u64),
+                64 => Some((addr as *const u64).read_volatile()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Writes a register given by a generic address, returning whether it is supported.
+unsafe fn write_register(register: &GenericAddress, value: u64) -> bool {
+    match register.address_space {
+        AddressSpace::SystemIo => {
+            let port = register.address as u16;
+            match register.bit_width {
+                8 => PortWriteOnly::<u8>::new(port).write(value as u8),
+                16 => PortWriteOnly::<u16>::new(port).write(value as u16),
+                32 => PortWriteOnly::<u32>::new(port).write(value as u32),
+                _ => return false,
+            }
+        }
+        AddressSpace::SystemMemory => {
+            let addr = phys2virt(register.address).as_u64();
+            match register.bit_width {
+                8 => (addr as *mut u8).write_volatile(value as u8),
+                16 => (addr as *mut u16).write_volatile(value as u16),
+                32 => (addr as *mut u32).write_volatile(value as u32),
+                64 => (addr as *mut u64).write_volatile(value),
+                _ => return false,
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Switches the platform to ACPI mode if the firmware left it in legacy mode.
+unsafe fn enable_acpi(power: &Power) {
+    let enabled = || {
+        power
+            .pm1a_control
+            .as_ref()
+            .and_then(|pm1a| read_register(pm1a))
+            .map_or(true, |pm1a| pm1a & SCI_EN != 0)
+    };
+    if enabled() || power.smi_command == 0 || power.acpi_enable == 0 {
+        return;
+    }
+
+    PortWriteOnly::<u8>::new(power.smi_command).write(power.acpi_enable);
+    for _ in 0..1_000_000 {
+        if enabled() {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Enters the S5 sleep state. Returns if it is not supported.
+unsafe fn acpi_poweroff() {
+    let power = match POWER.get() {
+        Some(power) => power,
+        None => return,
+    };
+    let (slp_typ_a, slp_typ_b) = match power.s5 {
+        Some(s5) => s5,
+        None => return,
+    };
+    let pm1a_control = match &power.pm1a_control {
+        Some(pm1a_control) => pm1a_control,
+        None => return,
+    };
+
+    enable_acpi(power);
+
+    let sleep = |register: &GenericAddress, slp_typ: u64| {
+        let value = read_register(register).unwrap_or(0) & !SLP_TYP_MASK;
+        write_register(
+            register,
+            value | ((slp_typ << SLP_TYP_SHIFT) & SLP_TYP_MASK) | SLP_EN,
+        )
+    };
+    if let Some(pm1b_control) = &power.pm1b_control {
+        sleep(pm1b_control, slp_typ_b);
+    }
+    sleep(pm1a_control, slp_typ_a);
+}
+
+/// Powers off with ACPI, or with the shutdown ports of emulators if that fails.
+pub fn poweroff() -> ! {
+    log!("Powering off");
+    interrupts::disable();
+
+    unsafe {
+        acpi_poweroff();
+
+        for (port, value) in SHUTDOWN_PORTS {
+            PortWriteOnly::<u16>::new(port).write(value);
+        }
+        let (port, value) = SHUTDOWN_PORT_BYTE;
+        PortWriteOnly::<u8>::new(port).write(value);
+    }
+
+    log!("Power off failed, halting");
+    crate::hlt_loop();
+}
+
+/// Resets with the ACPI reset register, then the keyboard controller, then a triple fault.
+pub fn reboot() -> ! {
+    log!("Rebooting");
+    interrupts::disable();
+
+    unsafe {
+        if let Some((register, value)) = POWER.get().and_then(|power| power.reset.as_ref()) {
+            write_register(register, *value as u64);
+        }
+
+        // Pulse the reset line of the keyboard controller once its input buffer is empty
+        let mut status = Port::<u8>::new(KEYBOARD_CONTROLLER);
+        for _ in 0..1_000_000 {
+            if status.read() & 0b10 == 0 {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        status.write(0xfe);
+
+        // Any exception triple faults without an IDT
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        });
+        asm!("int3", options(nomem, nostack));
+    }
+
+    crate::hlt_loop();
+}
@@ -0,0 +1,81 @@
+//! Locks shared with interrupt handlers.
+
+use {
+    core::{
+        mem::ManuallyDrop,
+        ops::{Deref, DerefMut},
+    },
+    spin::{Mutex, MutexGuard},
+    x86_64::instructions::interrupts,
+};
+
+/// A spin lock that keeps interrupts disabled while it is held, so that an interrupt handler
+/// taking the same lock cannot deadlock with the code it interrupted.
+pub struct IrqMutex<T: ?Sized> {
+    inner: Mutex<T>,
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> IrqMutex<T> {
+    pub fn lock(&self) -> IrqMutexGuard<T> {
+        let enabled = interrupts::are_enabled();
+        interrupts::disable();
+        IrqMutexGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            enabled,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<T>> {
+        let enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqMutexGuard {
+                guard: ManuallyDrop::new(guard),
+                enabled,
+            }),
+            None => {
+                if enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+}
+
+pub struct IrqMutexGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    /// Whether interrupts were enabled before locking
+    enabled: bool,
+}
+
+impl<T: ?Sized> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.enabled {
+            interrupts::enable();
+        }
+    }
+}
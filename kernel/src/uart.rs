@@ -1,33 +1,331 @@
-use {spin::Once, uart_16550::SerialPort, x86_64::instructions::interrupts::without_interrupts};
+//! The serial console on COM1, written by [`print!`] and [`println!`], and the kernel logs of
+//! [`log!`]. The `console` of JavaScript prints through [`println!`] and [`eprintln!`] too.
+//!
+//! Once [`enable_interrupts`] is called, console output goes through [`TX`], a ring drained by
+//! the interrupt raised when the transmitter is empty, so that writers do not wait for the UART.
+//! When the ring is full, writers wait for room with interrupts enabled, unless they run with
+//! interrupts disabled. Before that, and after a panic, bytes are sent right away.
+//!
+//! With the `log-com2` feature, the logs are sent right away on COM2 instead, so that the
+//! console only carries what JavaScript prints, with the formatting of its `console`.
+
+use {
+    crate::{deferred, sync::IrqMutex},
+    core::sync::atomic::{AtomicBool, Ordering},
+    spin::Once,
+    uart_16550::SerialPort,
+    x86_64::instructions::{
+        interrupts::{self, without_interrupts},
+        port::Port,
+    },
+};
+
+const COM1: u16 = 0x3f8;
+#[cfg(feature = "log-com2")]
+const COM2: u16 = 0x2f8;
+
+/// Data register, the receiver buffer on reads and the transmitter holding register on writes.
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const INTERRUPT_ID: u16 = 2;
+const LINE_STATUS: u16 = 5;
+const MODEM_STATUS: u16 = 6;
+
+const IER_RX: u8 = 1;
+const IER_THR_EMPTY: u8 = 1 << 1;
+/// Clear while an interrupt is pending.
+const IIR_NONE: u8 = 1;
+const IIR_ID: u8 = 0b111 << 1;
+const IIR_MODEM_STATUS: u8 = 0b000 << 1;
+const IIR_LINE_STATUS: u8 = 0b011 << 1;
+/// Bound on the interrupts handled in one call, in case the UART never reports none pending.
+const MAX_PENDING: usize = 64;
+const LSR_DATA_READY: u8 = 1;
+/// Set when the transmitter FIFO is empty.
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// Bytes that can be written at once when the transmitter is empty.
+const FIFO_SIZE: usize = 16;
+pub const TX_CAPACITY: usize = 4096;
 
 pub static SERIAL1: Once<SerialPort> = Once::new();
+#[cfg(feature = "log-com2")]
+static SERIAL2: Once<SerialPort> = Once::new();
+
+struct Tx {
+    bytes: [u8; TX_CAPACITY],
+    head: usize,
+    len: usize,
+    /// Whether the transmitter empty interrupt is enabled, i.e. the ring is being drained
+    busy: bool,
+    /// Whether a write was cut short since the ring was last empty
+    refused: bool,
+}
+
+static TX: IrqMutex<Tx> = IrqMutex::new(Tx {
+    bytes: [0; TX_CAPACITY],
+    head: 0,
+    len: 0,
+    busy: false,
+    refused: false,
+});
+
+static INTERRUPT_DRIVEN: AtomicBool = AtomicBool::new(false);
+static DRAIN: Once<fn()> = Once::new();
+
+fn register(offset: u16) -> Port<u8> {
+    Port::new(COM1 + offset)
+}
+
+impl Tx {
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == TX_CAPACITY {
+            return false;
+        }
+        let tail = (self.head + self.len) % TX_CAPACITY;
+        self.bytes[tail] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.bytes[self.head];
+        self.head = (self.head + 1) % TX_CAPACITY;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    /// Enables the transmitter empty interrupt, which fires at once if the transmitter is idle.
+    fn start(&mut self) {
+        if !self.busy && self.len != 0 {
+            self.busy = true;
+            unsafe { register(INTERRUPT_ENABLE).write(IER_RX | IER_THR_EMPTY) };
+        }
+    }
+
+    /// Moves a FIFO worth of bytes to the UART if it is empty, and stops when the ring is.
+    fn fill_fifo(&mut self) {
+        if !self.busy {
+            return;
+        }
+        if unsafe { register(LINE_STATUS).read() } & LSR_THR_EMPTY != 0 {
+            for _ in 0..FIFO_SIZE {
+                match self.pop() {
+                    Some(byte) => unsafe { register(DATA).write(byte) },
+                    None => break,
+                }
+            }
+        }
+
+        if self.len == 0 {
+            self.busy = false;
+            unsafe { register(INTERRUPT_ENABLE).write(IER_RX) };
+            if core::mem::take(&mut self.refused) {
+                deferred::defer(drained, 0);
+            }
+        }
+    }
+
+    /// Sends the oldest byte, waiting for the UART.
+    fn send_oldest(&mut self) {
+        if let Some(byte) = self.pop() {
+            unsafe { &mut *SERIAL1.as_mut_ptr() }.send_raw(byte);
+        }
+    }
+}
+
+fn drained(_: u64) {
+    if let Some(handler) = DRAIN.get() {
+        handler();
+    }
+}
 
 pub fn init() {
     SERIAL1.call_once(|| {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
+        serial_port.init();
+        serial_port
+    });
+    #[cfg(feature = "log-com2")]
+    SERIAL2.call_once(|| {
+        let mut serial_port = unsafe { SerialPort::new(COM2) };
         serial_port.init();
         serial_port
     });
 }
 
+/// Switches output to [`TX`]. The COM1 IRQ must be enabled.
+pub fn enable_interrupts() {
+    INTERRUPT_DRIVEN.store(true, Ordering::SeqCst);
+}
+
+/// Sends the buffered output and every later write right away, as the interrupts may never come
+/// again. Called when panicking.
+pub fn set_synchronous() {
+    INTERRUPT_DRIVEN.store(false, Ordering::SeqCst);
+    // The lock may be held by the code that panicked
+    if let Some(mut tx) = TX.try_lock() {
+        while tx.len != 0 {
+            tx.send_oldest();
+        }
+    }
+}
+
+/// Sets the function called in the main loop when the output buffer is empty again, after
+/// [`try_write_bytes`] or [`try_write_str`] could not take everything.
+pub fn set_drain_handler(handler: fn()) {
+    DRAIN.call_once(|| handler);
+}
+
+/// Handles the COM1 interrupt until none is pending: passes the received bytes to `receive`,
+/// and refills the transmitter.
+pub fn handle_interrupt(receive: impl Fn(u8)) {
+    for _ in 0..MAX_PENDING {
+        let id = unsafe { register(INTERRUPT_ID).read() };
+        if id & IIR_NONE != 0 {
+            break;
+        }
+
+        // Both are cleared by reading their status register
+        match id & IIR_ID {
+            IIR_LINE_STATUS => unsafe {
+                register(LINE_STATUS).read();
+            },
+            IIR_MODEM_STATUS => unsafe {
+                register(MODEM_STATUS).read();
+            },
+            _ => (),
+        }
+
+        while unsafe { register(LINE_STATUS).read() } & LSR_DATA_READY != 0 {
+            receive(unsafe { register(DATA).read() });
+        }
+        TX.lock().fill_fifo();
+    }
+}
+
+fn write_sync(bytes: &[u8]) {
+    without_interrupts(|| {
+        let serial_port = unsafe { &mut *SERIAL1.as_mut_ptr() };
+        for &byte in bytes {
+            serial_port.send_raw(byte);
+        }
+    });
+}
+
+/// Writes `bytes` as they are, unlike [`SerialPort::send`] which rewrites backspaces. Waits for
+/// room if the output buffer is full, with interrupts enabled unless the caller disabled them.
+pub fn write_bytes(mut bytes: &[u8]) {
+    if !INTERRUPT_DRIVEN.load(Ordering::Relaxed) {
+        write_sync(bytes);
+        return;
+    }
+
+    let enabled = interrupts::are_enabled();
+    while !bytes.is_empty() {
+        {
+            let mut tx = TX.lock();
+            let written = bytes.iter().take_while(|&&byte| tx.push(byte)).count();
+            bytes = &bytes[written..];
+            if !bytes.is_empty() && !enabled {
+                // The interrupt cannot come before the caller enables it
+                tx.send_oldest();
+            }
+            tx.start();
+        }
+        if !bytes.is_empty() && enabled {
+            while pending() == TX_CAPACITY {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// Buffers as much of `bytes` as fits without waiting, and returns how much. See
+/// [`set_drain_handler`] to know when to write the rest.
+pub fn try_write_bytes(bytes: &[u8]) -> usize {
+    if !INTERRUPT_DRIVEN.load(Ordering::Relaxed) {
+        write_sync(bytes);
+        return bytes.len();
+    }
+
+    let mut tx = TX.lock();
+    let written = bytes.iter().take_while(|&&byte| tx.push(byte)).count();
+    if written < bytes.len() {
+        tx.refused = true;
+    }
+    tx.start();
+    written
+}
+
+/// Buffers the whole characters of `text` that fit without waiting, and returns their length in
+/// bytes. See [`set_drain_handler`] to know when to write the rest.
+pub fn try_write_str(text: &str) -> usize {
+    if !INTERRUPT_DRIVEN.load(Ordering::Relaxed) {
+        write_sync(text.as_bytes());
+        return text.len();
+    }
+
+    let mut tx = TX.lock();
+    let mut len = text.len().min(TX_CAPACITY - tx.len);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    for &byte in &text.as_bytes()[..len] {
+        tx.push(byte);
+    }
+    if len < text.len() {
+        tx.refused = true;
+    }
+    tx.start();
+    len
+}
+
+/// Returns the number of bytes waiting in the output buffer.
+pub fn pending() -> usize {
+    TX.lock().len
+}
+
+struct Writer;
+
+impl core::fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
     use core::fmt::Write;
 
+    Writer.write_fmt(args).unwrap();
+}
+
+#[doc(hidden)]
+pub fn _log(args: core::fmt::Arguments) {
+    #[cfg(not(feature = "log-com2"))]
+    _print(args);
+    #[cfg(feature = "log-com2")]
     without_interrupts(|| {
-        unsafe { &mut *SERIAL1.as_mut_ptr() }
+        use core::fmt::Write;
+
+        unsafe { &mut *SERIAL2.as_mut_ptr() }
             .write_fmt(args)
             .unwrap()
     });
 }
 
-/// Prints to the host through the serial interface.
+/// Prints to the console.
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::uart::_print(format_args!($($arg)*)));
 }
 
-/// Prints to the host through the serial interface, appending a newline.
+/// Prints to the console, appending a newline.
 #[macro_export]
 macro_rules! println {
     () => ($crate::print!("\n"));
@@ -43,3 +341,13 @@ macro_rules! eprintln {
         $crate::uart::_print(format_args_nl!($($arg)*));
     })
 }
+
+/// Prints a line of the kernel logs, on COM2 with the `log-com2` feature and on the console
+/// otherwise.
+#[macro_export]
+macro_rules! log {
+    () => ($crate::uart::_log(format_args!("\n")));
+    ($($arg:tt)*) => ({
+        $crate::uart::_log(format_args_nl!($($arg)*));
+    })
+}
@@ -1,8 +1,12 @@
 import {
   basename,
   dirname,
+  HEAP_DEBUG,
+  HEAP_FIRST_FIT,
+  HEAP_TRACE,
   join,
   KERNEL_DIR,
+  LOG_COM2,
   PKG,
   PROD,
   TARGET_BIN_DIR,
@@ -21,8 +25,17 @@ export const images = await (async () => {
     if (TEST) cmd.push("test", "--no-run");
     else cmd.push("build");
     if (PROD) cmd.push("--release");
+    if (HEAP_DEBUG) cmd.push("--features", "heap-debug");
+    if (HEAP_FIRST_FIT) cmd.push("--features", "heap-first-fit");
+    if (HEAP_TRACE) cmd.push("--features", "heap-trace");
+    if (LOG_COM2) cmd.push("--features", "log-com2");
 
-    const build = Deno.run({ cmd, stdout: "inherit", stderr: "inherit" });
+    // Call stacks are found by following the frame pointers
+    const env: Record<string, string> = HEAP_TRACE
+      ? { RUSTFLAGS: "-C force-frame-pointers=yes" }
+      : {};
+
+    const build = Deno.run({ cmd, env, stdout: "inherit", stderr: "inherit" });
     if (!(await build.status()).success) throw new Error("build failed");
 
     const mainBin = join(TARGET_BIN_DIR, PKG);
@@ -30,7 +43,7 @@ export const images = await (async () => {
     if (!TEST) kernelBinaryPaths.push(mainBin);
     else {
       cmd.push("--message-format", "json");
-      const res = Deno.run({ cmd, stdout: "piped", stderr: "inherit" });
+      const res = Deno.run({ cmd, env, stdout: "piped", stderr: "inherit" });
       if (!(await res.status()).success) throw new Error("build failed");
 
       for (const line of textDecoder.decode(await res.output()).split("\n")) {
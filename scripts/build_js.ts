@@ -1,4 +1,11 @@
-import { DIST_DIR, join, KERNEL_DIR, PROD, USER_DIR } from "./env.ts";
+import {
+  basename,
+  DIST_DIR,
+  join,
+  KERNEL_DIR,
+  PROD,
+  USER_DIR,
+} from "./env.ts";
 import { build, stop } from "https://deno.land/x/esbuild@v0.14.36/mod.js";
 import type { BuildOptions } from "https://deno.land/x/esbuild@v0.14.36/mod.js";
 
@@ -39,6 +46,9 @@ export async function buildjs() {
     ...basicConfig,
 
     define: {
+      USER_NAMES: JSON.stringify(
+        outputFiles.map(({ path }) => basename(path, ".js")),
+      ),
       USER_CODES: JSON.stringify(
         outputFiles.map(({ contents }) => [...contents]),
       ),
@@ -7,6 +7,10 @@ import {
 export { basename, dirname, join };
 
 export const TEST = Deno.args.includes("--test");
+export const HEAP_DEBUG = Deno.args.includes("--heap-debug");
+export const HEAP_FIRST_FIT = Deno.args.includes("--heap-first-fit");
+export const HEAP_TRACE = Deno.args.includes("--heap-trace");
+export const LOG_COM2 = Deno.args.includes("--log-com2");
 export const MODE: "debug" | "release" = Deno.args.includes("--release")
   ? "release"
   : "debug";
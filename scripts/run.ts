@@ -1,4 +1,4 @@
-import { join, ROOT_DIR, TEST } from "./env.ts";
+import { join, LOG_COM2, ROOT_DIR, TARGET_DIR, TEST } from "./env.ts";
 import { images } from "./build.ts";
 
 /** Follow {@link https://gil0mendes.io/blog/an-efi-app-a-bit-rusty/} */
@@ -31,8 +31,13 @@ const cmd = [
   "-smp", "1,maxcpus=1"
 ];
 
+// The second serial port is COM2, which gets the kernel logs
+if (LOG_COM2) cmd.push("-serial", `file:${join(TARGET_DIR, "kernel.log")}`);
+
 if (TEST) {
   cmd.push("-device", "isa-debug-exit,iobase=0xf4,iosize=0x04");
+  // A device with MSI-X for tests/msi.rs
+  cmd.push("-device", "virtio-rng-pci");
 
   for (const image of images) {
     const run = Deno.run({
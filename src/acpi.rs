@@ -0,0 +1,133 @@
+use {
+    alloc::{string::String, vec::Vec},
+    boa_engine::{
+        builtins::array_buffer::ArrayBuffer,
+        object::{JsArray, JsObject, ObjectData, ObjectInitializer},
+        property::Attribute,
+        Context, JsResult, JsValue,
+    },
+    ingram_kernel::acpi::{read_table, tables, PlatformCapabilities, CAPABILITIES},
+};
+
+/// Creates an `ArrayBuffer` holding `bytes`.
+pub fn array_buffer(bytes: Vec<u8>, context: &mut Context) -> JsResult<JsValue> {
+    let prototype = context
+        .global_object()
+        .clone()
+        .get("ArrayBuffer", context)?
+        .as_object()
+        .ok_or(context.construct_type_error("missing ArrayBuffer"))?
+        .get("prototype", context)?
+        .as_object()
+        .cloned();
+
+    let byte_length = bytes.len();
+    let buffer = ArrayBuffer {
+        array_buffer_data: Some(bytes),
+        array_buffer_byte_length: byte_length,
+        array_buffer_detach_key: JsValue::undefined(),
+    };
+
+    Ok(JsObject::from_proto_and_data(prototype, ObjectData::array_buffer(buffer)).into())
+}
+
+/// Creates an `ArrayBuffer` over the `length` bytes at `ptr`, outside of the heap, e.g. a DMA
+/// buffer or MMIO. Its bytes must be taken back with [`detach_at`] before it is collected, as
+/// the heap would free them otherwise.
+///
+/// # Safety
+///
+/// `ptr` must be nonzero and the bytes must stay mapped until [`detach_at`].
+pub unsafe fn array_buffer_at(
+    ptr: *mut u8,
+    length: usize,
+    context: &mut Context,
+) -> JsResult<JsObject> {
+    let buffer = array_buffer(Vec::new(), context)?
+        .as_object()
+        .unwrap()
+        .clone();
+    if let Some(buffer) = buffer.borrow_mut().as_array_buffer_mut() {
+        buffer.array_buffer_data = Some(Vec::from_raw_parts(ptr, length, length));
+        buffer.array_buffer_byte_length = length;
+    }
+    Ok(buffer)
+}
+
+/// Detaches a buffer of [`array_buffer_at`] and forgets its bytes, unless the engine moved them
+/// into the heap, which only loses what was written since.
+pub fn detach_at(buffer: &JsObject, ptr: *mut u8) {
+    let bytes = match buffer.borrow_mut().as_array_buffer_mut() {
+        Some(buffer) => {
+            buffer.array_buffer_byte_length = 0;
+            buffer.array_buffer_data.take()
+        }
+        None => None,
+    };
+    if let Some(bytes) = bytes.filter(|bytes| bytes.as_ptr() == ptr as *const u8) {
+        core::mem::forget(bytes);
+    }
+}
+
+fn list_tables(_this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let signatures = tables()
+        .into_iter()
+        .map(|table| String::from_utf8_lossy(&table.signature).as_ref().into())
+        .collect::<Vec<JsValue>>();
+
+    Ok(JsArray::from_iter(signatures, context).into())
+}
+
+/// Returns a copy of the table, as an `ArrayBuffer` cannot be made read-only, so writing to it does
+/// not change the firmware tables.
+fn get_table(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let signature = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing signature"))?
+        .to_string(context)?;
+    if signature.len() != 4 {
+        return Err(context.construct_range_error("signature must be 4 characters"));
+    }
+
+    let index = match args.get(1) {
+        Some(index) => index.to_index(context)?,
+        None => 0,
+    };
+
+    match read_table(signature.as_bytes(), index) {
+        Some(bytes) => array_buffer(bytes, context),
+        None => Ok(JsValue::null()),
+    }
+}
+
+pub fn init(obj: &mut ObjectInitializer) {
+    let PlatformCapabilities {
+        pm_timer,
+        hpet,
+        apic,
+        pci_express,
+        processor_info,
+        century,
+    } = *CAPABILITIES.get().unwrap();
+
+    let capabilities = ObjectInitializer::new(&mut *obj.context)
+        .property("pmTimer", pm_timer, Attribute::default())
+        .property("hpet", hpet, Attribute::default())
+        .property("apic", apic, Attribute::default())
+        .property("pciExpress", pci_express, Attribute::default())
+        .property("processorInfo", processor_info, Attribute::default())
+        .property(
+            "century",
+            century.map_or(JsValue::null(), |century| JsValue::Integer(century as i32)),
+            Attribute::default(),
+        )
+        .build();
+
+    let acpi = ObjectInitializer::new(&mut *obj.context)
+        .function(list_tables, "tables", 0)
+        .function(get_table, "getTable", 2)
+        .property("capabilities", capabilities, Attribute::default())
+        .build();
+
+    obj.property("acpi", acpi, Attribute::default());
+}
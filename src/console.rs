@@ -0,0 +1,80 @@
+use {
+    alloc::{format, string::String},
+    boa_engine::{object::ObjectInitializer, property::Attribute, Context, JsResult, JsValue},
+    ingram_kernel::{
+        console::{self, Mode},
+        uart,
+    },
+};
+
+/// Takes up to `max` bytes of the input typed so far, as a string that is empty if there is
+/// none. The input is a line at a time in canonical mode.
+pub fn read(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let max = match args.get(0) {
+        Some(max) if !max.is_undefined() => max.to_index(context)?,
+        _ => usize::MAX,
+    };
+    let bytes = console::read(max);
+
+    Ok(String::from_utf8_lossy(&bytes).as_ref().into())
+}
+
+/// Writes a string as it is, e.g. a prompt without the newline of `console.log`. Returns the
+/// length of the whole characters that were buffered, in UTF-16 code units like
+/// `String.prototype.length`, so that `text.slice(written)` is the rest. The `drain` event tells
+/// when to write it.
+fn write(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let text = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing text"))?
+        .to_string(context)?;
+
+    let written = uart::try_write_str(&text);
+    let written = text[..written].encode_utf16().count();
+    Ok(JsValue::Rational(written as f64))
+}
+
+fn set_mode(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let mode = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing mode"))?
+        .to_string(context)?;
+    let mode = match mode.as_str() {
+        "canonical" => Mode::Canonical,
+        "raw" => Mode::Raw,
+        _ => return Err(context.construct_range_error(format!("unknown mode {}", mode))),
+    };
+
+    console::set_mode(mode);
+    Ok(JsValue::undefined())
+}
+
+fn mode(_this: &JsValue, _args: &[JsValue], _context: &mut Context) -> JsResult<JsValue> {
+    Ok(match console::mode() {
+        Mode::Canonical => "canonical",
+        Mode::Raw => "raw",
+    }
+    .into())
+}
+
+fn set_echo(_this: &JsValue, args: &[JsValue], _context: &mut Context) -> JsResult<JsValue> {
+    console::set_echo(args.get(0).map_or(true, JsValue::to_boolean));
+    Ok(JsValue::undefined())
+}
+
+fn overruns(_this: &JsValue, _args: &[JsValue], _context: &mut Context) -> JsResult<JsValue> {
+    Ok(JsValue::Rational(console::overruns() as f64))
+}
+
+pub fn init(obj: &mut ObjectInitializer) {
+    let console = ObjectInitializer::new(&mut *obj.context)
+        .function(read, "read", 1)
+        .function(write, "write", 1)
+        .function(set_mode, "setMode", 1)
+        .function(mode, "mode", 0)
+        .function(set_echo, "setEcho", 1)
+        .function(overruns, "overruns", 0)
+        .build();
+
+    obj.property("console", console, Attribute::default());
+}
@@ -0,0 +1,28 @@
+use {
+    boa_engine::{object::ObjectInitializer, property::Attribute},
+    ingram_kernel::cpu::Hardening,
+};
+
+pub fn init(obj: &mut ObjectInitializer) {
+    let Hardening {
+        nx,
+        smep,
+        smap,
+        umip,
+        write_protect,
+    } = Hardening::current();
+
+    let features = ObjectInitializer::new(&mut *obj.context)
+        .property("nx", nx, Attribute::default())
+        .property("smep", smep, Attribute::default())
+        .property("smap", smap, Attribute::default())
+        .property("umip", umip, Attribute::default())
+        .property("writeProtect", write_protect, Attribute::default())
+        .build();
+
+    let cpu = ObjectInitializer::new(&mut *obj.context)
+        .property("features", features, Attribute::default())
+        .build();
+
+    obj.property("cpu", cpu, Attribute::default());
+}
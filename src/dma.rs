@@ -0,0 +1,102 @@
+use {
+    crate::acpi::{array_buffer_at, detach_at},
+    alloc::boxed::Box,
+    boa_engine::{
+        object::{JsObject, ObjectData, ObjectInitializer},
+        property::Attribute,
+        Context, JsResult, JsValue,
+    },
+    boa_gc::{unsafe_empty_trace, Finalize, Trace},
+    core::cell::RefCell,
+    ingram_kernel::dma,
+};
+
+/// A DMA buffer, whose frames go back to the frame allocator once it is collected or freed.
+#[derive(Debug)]
+struct Dma {
+    ptr: *mut u8,
+    /// The `ArrayBuffer` over the frames, rooted until they are released so that it is never
+    /// collected with the frames still in it.
+    buffer: RefCell<Option<JsObject>>,
+}
+
+unsafe impl Trace for Dma {
+    unsafe_empty_trace!();
+}
+
+impl Finalize for Dma {
+    fn finalize(&self) {
+        if let Some(buffer) = self.buffer.borrow_mut().take() {
+            detach_at(&buffer, self.ptr);
+            dma::release(self.ptr);
+        }
+    }
+}
+
+fn alloc(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let size = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing size"))?
+        .to_index(context)?;
+    if size == 0 {
+        return Err(context.construct_range_error("size must be positive"));
+    }
+
+    let (align, below_4g) = match args.get(1).and_then(|options| options.as_object()) {
+        Some(options) => {
+            let align = options.get("align", context)?;
+            let align = if align.is_undefined() {
+                1
+            } else {
+                align.to_index(context)?
+            };
+            (align, options.get("below4G", context)?.to_boolean())
+        }
+        None => (1, false),
+    };
+    if !align.is_power_of_two() {
+        return Err(context.construct_range_error("align must be a power of two"));
+    }
+
+    let buffer = dma::alloc(size as u64, align as u64, below_4g).ok_or(
+        context.construct_range_error("out of contiguous physical memory or buffer slots"),
+    )?;
+    let array_buffer = match unsafe { array_buffer_at(buffer.ptr, size, context) } {
+        Ok(array_buffer) => array_buffer,
+        Err(err) => {
+            dma::release(buffer.ptr);
+            return Err(err);
+        }
+    };
+
+    let dma = Dma {
+        ptr: buffer.ptr,
+        buffer: RefCell::new(Some(array_buffer.clone())),
+    };
+    Ok(ObjectInitializer {
+        context,
+        object: JsObject::from_proto_and_data(None, ObjectData::native_object(Box::new(dma))),
+    }
+    .property("buffer", array_buffer, Attribute::default())
+    .property(
+        "physicalAddress",
+        JsValue::Rational(buffer.phys.as_u64() as f64),
+        Attribute::default(),
+    )
+    .function(free, "free", 0)
+    .build()
+    .into())
+}
+
+/// Detaches the buffer and gives the frames back right away.
+fn free(this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    this.as_object()
+        .and_then(|object| object.downcast_ref::<Dma>().map(|dma| dma.finalize()))
+        .ok_or(context.construct_type_error("not a DMA buffer"))?;
+
+    Ok(JsValue::undefined())
+}
+
+pub fn init(obj: &mut ObjectInitializer) {
+    obj.function(alloc, "allocDma", 2);
+}
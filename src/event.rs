@@ -0,0 +1,73 @@
+use {
+    crate::process::KERNEL_MICROTASKS,
+    alloc::{format, vec::Vec},
+    boa_engine::{
+        object::{JsObject, ObjectInitializer},
+        Context, JsResult, JsValue,
+    },
+    ingram_kernel::{console, power::set_power_button_handler, uart},
+    spin::Mutex,
+};
+
+/// Events the kernel delivers to the listeners registered with `Kernel.on`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    PowerButton,
+    /// Console input became readable with `Kernel.console.read`
+    Input,
+    /// Ctrl-C was typed on the console
+    Interrupt,
+    /// The console output buffer is empty again after refusing a write
+    Drain,
+}
+
+impl Event {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "powerbutton" => Some(Self::PowerButton),
+            "input" => Some(Self::Input),
+            "interrupt" => Some(Self::Interrupt),
+            "drain" => Some(Self::Drain),
+            _ => None,
+        }
+    }
+}
+
+static LISTENERS: Mutex<Vec<(Event, JsObject)>> = Mutex::new(Vec::new());
+
+/// Queues the listeners of `event` as microtasks.
+pub fn emit(event: Event) {
+    let microtasks = unsafe { KERNEL_MICROTASKS.get_unchecked() };
+    for (_, listener) in LISTENERS.lock().iter().filter(|(e, _)| *e == event) {
+        if microtasks.push(listener.clone()).is_err() {
+            log!("Microtask queue is full, dropped an event listener");
+        }
+    }
+}
+
+fn on(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let name = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing event"))?
+        .to_string(context)?;
+    let event = Event::from_name(&name)
+        .ok_or(context.construct_range_error(format!("unknown event {}", name)))?;
+    let listener = args
+        .get(1)
+        .and_then(|listener| listener.as_object())
+        .filter(|listener| listener.is_callable())
+        .ok_or(context.construct_type_error("listener must be a function"))?
+        .clone();
+
+    LISTENERS.lock().push((event, listener));
+    Ok(JsValue::undefined())
+}
+
+pub fn init(obj: &mut ObjectInitializer) {
+    set_power_button_handler(|| emit(Event::PowerButton));
+    console::set_input_handler(|| emit(Event::Input));
+    console::set_interrupt_handler(|| emit(Event::Interrupt));
+    uart::set_drain_handler(|| emit(Event::Drain));
+
+    obj.function(on, "on", 2);
+}
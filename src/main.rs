@@ -9,8 +9,18 @@
 extern crate ingram_kernel;
 extern crate alloc;
 
+mod acpi;
+mod console;
+mod cpu;
+mod dma;
+mod event;
+mod memory;
+mod mmio;
+mod pci;
 mod port;
+mod power;
 mod process;
+mod repl;
 mod rtc;
 
 use ingram_kernel::{entry_point, BootInfo};
@@ -23,7 +33,7 @@ entry_point!(test_kernel_main);
 #[cfg(not(test))]
 pub fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     use ingram_kernel::{
-        acpi, allocator, apic, constant::PHYS_OFFSET, gdt, interrupt, memory, uart,
+        acpi, allocator, apic, constant::PHYS_OFFSET, cpu, gdt, interrupt, memory, pci, power, uart,
     };
 
     uart::init();
@@ -36,33 +46,49 @@ pub fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
 
     gdt::init();
     interrupt::init();
+    cpu::init();
     let (mut mapper, mut frame_allocator) = unsafe { memory::init(&boot_info.memory_regions) };
     allocator::init(&mut mapper, &mut frame_allocator);
-    let (pm_timer, hpet_info, apic, fadt) = acpi::init(rsdp_addr);
-    assert_ne!(fadt.century, 0);
-    apic::init(&mut mapper, &mut frame_allocator, pm_timer, hpet_info, apic);
-
-    println!("██╗███╗   ██╗ ██████╗ ██████╗  █████╗ ███╗   ███╗");
-    println!("██║████╗  ██║██╔════╝ ██╔══██╗██╔══██╗████╗ ████║");
-    println!("██║██╔██╗ ██║██║  ███╗██████╔╝███████║██╔████╔██║");
-    println!("██║██║╚██╗██║██║   ██║██╔══██╗██╔══██║██║╚██╔╝██║");
-    println!("██║██║ ╚████║╚██████╔╝██║  ██║██║  ██║██║ ╚═╝ ██║");
-    println!("╚═╝╚═╝  ╚═══╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚═╝     ╚═╝");
-
-    js_kernel_main(fadt.century)
+    let platform = acpi::init(rsdp_addr);
+    apic::init(
+        &mut mapper,
+        &mut frame_allocator,
+        platform.pm_timer,
+        platform.hpet,
+        platform.apic,
+    );
+    uart::enable_interrupts();
+    if let Some(fadt) = &platform.fadt {
+        power::init(fadt);
+    }
+    pci::init(&mut mapper, &mut frame_allocator);
+    memory::enforce_wx(&mut mapper);
+    memory::install(mapper, frame_allocator);
+
+    log!("██╗███╗   ██╗ ██████╗ ██████╗  █████╗ ███╗   ███╗");
+    log!("██║████╗  ██║██╔════╝ ██╔══██╗██╔══██╗████╗ ████║");
+    log!("██║██╔██╗ ██║██║  ███╗██████╔╝███████║██╔████╔██║");
+    log!("██║██║╚██╗██║██║   ██║██╔══██╗██╔══██║██║╚██╔╝██║");
+    log!("██║██║ ╚████║╚██████╔╝██║  ██║██║  ██║██║ ╚═╝ ██║");
+    log!("╚═╝╚═╝  ╚═══╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚═╝     ╚═╝");
+
+    js_kernel_main()
 }
 
-pub fn js_kernel_main(century: u8) -> ! {
+pub fn js_kernel_main() -> ! {
     use {
         boa_engine::{
             object::{JsObject, ObjectData, ObjectInitializer},
             property::Attribute,
             Context, JsValue,
         },
+        ingram_kernel::{allocator::oom, deferred},
         process::KERNEL_MICROTASKS,
+        x86_64::instructions::interrupts,
     };
 
     boa_engine::init();
+    oom::set_collector(memory::collect);
     let mut context = Context::default();
 
     let mut kernel = ObjectInitializer {
@@ -70,9 +96,19 @@ pub fn js_kernel_main(century: u8) -> ! {
         object: JsObject::from_proto_and_data(None, ObjectData::ordinary()),
     };
 
-    rtc::init(&mut kernel, century);
+    rtc::init(&mut kernel);
     port::init(&mut kernel);
+    console::init(&mut kernel);
+    memory::init(&mut kernel);
+    mmio::init(&mut kernel);
+    dma::init(&mut kernel);
+    cpu::init(&mut kernel);
+    acpi::init(&mut kernel);
+    pci::init(&mut kernel);
+    power::init(&mut kernel);
     process::init(&mut kernel);
+    repl::init(&mut kernel);
+    event::init(&mut kernel);
     let kernel = kernel.build();
 
     context.register_global_property("Kernel", kernel, Attribute::default());
@@ -94,18 +130,37 @@ pub fn js_kernel_main(century: u8) -> ! {
         panic!("{}", err.to_string(&mut context).unwrap());
     }
 
-    while let Some(f) = unsafe { KERNEL_MICROTASKS.get_unchecked() }.pop() {
-        let _ = f.call(&JsValue::null(), &[], &mut context).unwrap();
+    // The main loop, runs the work deferred by interrupt handlers between microtasks
+    loop {
+        deferred::run();
+
+        match unsafe { KERNEL_MICROTASKS.get_unchecked() }.pop() {
+            Some(f) => {
+                let _ = f.call(&JsValue::null(), &[], &mut context).unwrap();
+            }
+            None => {
+                interrupts::disable();
+                if deferred::is_empty() {
+                    interrupts::enable_and_hlt();
+                } else {
+                    interrupts::enable();
+                }
+            }
+        }
     }
-
-    ingram_kernel::hlt_loop();
 }
 
 #[cfg(test)]
-pub fn test_kernel_main(_boot_info: &'static mut BootInfo) -> ! {
-    use ingram_kernel::{uart, QEMUExit, QEMU_EXIT_HANDLE};
+pub fn test_kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    use ingram_kernel::{allocator, gdt, interrupt, memory, uart, QEMUExit, QEMU_EXIT_HANDLE};
     uart::init();
-    print!("");
+    gdt::init();
+    interrupt::init();
+    let (mut mapper, mut frame_allocator) = unsafe { memory::init(&boot_info.memory_regions) };
+    allocator::init(&mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+    boa_engine::init();
+
     test_main();
     QEMU_EXIT_HANDLE.exit_success()
 }
@@ -0,0 +1,340 @@
+use {
+    crate::mmio,
+    alloc::{collections::BTreeMap, format, vec::Vec},
+    boa_engine::{
+        object::{JsArray, ObjectInitializer},
+        property::Attribute,
+        Context, JsResult, JsValue,
+    },
+    core::sync::atomic::{AtomicUsize, Ordering},
+    ingram_kernel::{
+        allocator::{self, trace, HeapStats},
+        memory::{
+            alloc_virt, dealloc_virt, protect, virt2phys, walk, PageRangeError, FRAME_ALLOCATOR,
+            MAPPER,
+        },
+    },
+    spin::Mutex,
+    x86_64::{
+        align_down,
+        structures::paging::{PageSize, PageTableFlags, Size4KiB},
+        VirtAddr,
+    },
+};
+
+/// Ranges `[start, end]` mapped by `alloc`, keyed by their start. `free` and `protect` only accept
+/// these, so that the kernel's own memory is never unmapped, made writable or handed to the frame
+/// allocator.
+static ALLOCATED: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+
+/// Heap bytes freed by the last garbage collection run by [`collect`].
+static LAST_FREED: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs a full garbage collection and records what it freed for `Kernel.memoryUsage()`.
+///
+/// `boa_gc` does not report the collections it starts by itself when its threshold is reached,
+/// so only these are measured.
+pub fn collect() {
+    let before = allocator::stats().used;
+    boa_gc::force_collect();
+    let after = allocator::stats().used;
+
+    LAST_FREED.store(before.saturating_sub(after), Ordering::Relaxed);
+}
+
+/// Returns the inclusive range `[addr, addr + length - 1]` given by the first two arguments.
+fn range(args: &[JsValue], context: &mut Context) -> JsResult<(u64, u64)> {
+    let addr = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing address"))?
+        .to_index(context)? as u64;
+
+    let length = args
+        .get(1)
+        .ok_or(context.construct_type_error("missing length"))?
+        .to_index(context)? as u64;
+    if length == 0 {
+        return Err(context.construct_range_error("length must be positive"));
+    }
+
+    Ok((addr, addr + length - 1))
+}
+
+pub fn range_error(err: PageRangeError, context: &mut Context) -> JsValue {
+    let message = match err {
+        PageRangeError::InvalidAddress(addr) => format!("invalid address {:#x}", addr),
+        PageRangeError::NotMapped(addr) => format!("{:#x} is not mapped", addr.as_u64()),
+        PageRangeError::PartialHugePage(addr) => {
+            format!("range covers part of the huge page at {:#x}", addr.as_u64())
+        }
+        PageRangeError::AlreadyMapped(addr) => {
+            format!("{:#x} is mapped to another address", addr.as_u64())
+        }
+        PageRangeError::OutOfFrames(addr) => {
+            format!("out of physical memory mapping {:#x}", addr.as_u64())
+        }
+    };
+    context.construct_range_error(message)
+}
+
+/// Releases a region of `Kernel.mapMmio`, the only ranges that can be unmapped without freeing
+/// their frames.
+fn unmap(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let (start, end) = range(args, context)?;
+
+    if !mmio::release_range(start, end) {
+        return Err(context.construct_range_error(format!(
+            "{:#x}..{:#x} was not mapped by Kernel.mapMmio",
+            start,
+            end + 1
+        )));
+    }
+
+    Ok(JsValue::undefined())
+}
+
+/// Returns the page aligned range `[start, end]` covering the first two arguments.
+fn page_range(args: &[JsValue], context: &mut Context) -> JsResult<(u64, u64)> {
+    let (start, end) = range(args, context)?;
+    let start = align_down(start, Size4KiB::SIZE);
+    let end = align_down(end, Size4KiB::SIZE) + Size4KiB::SIZE - 1;
+    for addr in [start, end] {
+        VirtAddr::try_new(addr)
+            .map_err(|_| range_error(PageRangeError::InvalidAddress(addr), context))?;
+    }
+    Ok((start, end))
+}
+
+fn alloc(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let (start, end) = page_range(args, context)?;
+    let flags = flags(args.get(2), context)?;
+
+    let mut mapper = MAPPER.get().unwrap().lock();
+    for addr in (start..end).step_by(Size4KiB::SIZE as usize) {
+        if virt2phys(&*mapper, addr).is_some() {
+            let err = PageRangeError::AlreadyMapped(VirtAddr::new(addr));
+            return Err(range_error(err, context));
+        }
+    }
+
+    let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+    if let Err(addr) = alloc_virt(&mut *mapper, &mut *frame_allocator, start, end, Some(flags)) {
+        if addr.as_u64() > start {
+            dealloc_virt(
+                &mut *mapper,
+                &mut *frame_allocator,
+                start,
+                addr.as_u64() - 1,
+            )
+            .unwrap();
+        }
+        return Err(context.construct_range_error("out of physical memory"));
+    }
+
+    ALLOCATED.lock().insert(start, end);
+    Ok(JsValue::undefined())
+}
+
+fn free(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let (start, end) = page_range(args, context)?;
+
+    let mut allocated = ALLOCATED.lock();
+    if allocated.get(&start) != Some(&end) {
+        return Err(context.construct_range_error(format!(
+            "{:#x}..{:#x} was not allocated by Kernel.memory.alloc",
+            start,
+            end + 1
+        )));
+    }
+
+    dealloc_virt(
+        &mut *MAPPER.get().unwrap().lock(),
+        &mut *FRAME_ALLOCATOR.get().unwrap().lock(),
+        start,
+        end,
+    )
+    .map_err(|err| range_error(err, context))?;
+    allocated.remove(&start);
+
+    Ok(JsValue::undefined())
+}
+
+/// Returns the flags given by `{ writable, executable }`, read-only and no-execute by default.
+/// Pages cannot be both writable and executable, like the kernel's own since boot.
+fn flags(options: Option<&JsValue>, context: &mut Context) -> JsResult<PageTableFlags> {
+    let mut flags = PageTableFlags::empty();
+    if let Some(options) = options.and_then(|options| options.as_object()) {
+        if options.get("writable", context)?.to_boolean() {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if !options.get("executable", context)?.to_boolean() {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+    } else {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    if flags.contains(PageTableFlags::WRITABLE) && !flags.contains(PageTableFlags::NO_EXECUTE) {
+        return Err(context.construct_range_error("pages cannot be writable and executable"));
+    }
+    Ok(flags)
+}
+
+/// Only takes ranges inside of ones mapped by `alloc`.
+fn protect_range(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let (start, end) = page_range(args, context)?;
+    let flags = flags(args.get(2), context)?;
+
+    let allocated = ALLOCATED
+        .lock()
+        .range(..=start)
+        .next_back()
+        .map_or(false, |(_, &allocated_end)| end <= allocated_end);
+    if !allocated {
+        return Err(context.construct_range_error(format!(
+            "{:#x}..{:#x} was not allocated by Kernel.memory.alloc",
+            start,
+            end + 1
+        )));
+    }
+
+    protect(&mut *MAPPER.get().unwrap().lock(), start, end, flags)
+        .map_err(|err| range_error(err, context))?;
+
+    Ok(JsValue::undefined())
+}
+
+fn translate(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let addr = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing address"))?
+        .to_index(context)? as u64;
+
+    Ok(
+        virt2phys(&*MAPPER.get().unwrap().lock(), addr).map_or(JsValue::null(), |phys| {
+            JsValue::Rational(phys.as_u64() as f64)
+        }),
+    )
+}
+
+fn mappings(_this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let mut runs = Vec::new();
+    walk(&mut *MAPPER.get().unwrap().lock(), |run| runs.push(run));
+
+    let runs = runs
+        .into_iter()
+        .map(|run| {
+            let flags = run.flags;
+            ObjectInitializer::new(context)
+                .property(
+                    "virt",
+                    JsValue::Rational(run.virt.as_u64() as f64),
+                    Attribute::default(),
+                )
+                .property(
+                    "phys",
+                    JsValue::Rational(run.phys.as_u64() as f64),
+                    Attribute::default(),
+                )
+                .property(
+                    "size",
+                    JsValue::Rational(run.size as f64),
+                    Attribute::default(),
+                )
+                .property(
+                    "writable",
+                    flags.contains(PageTableFlags::WRITABLE),
+                    Attribute::default(),
+                )
+                .property(
+                    "executable",
+                    !flags.contains(PageTableFlags::NO_EXECUTE),
+                    Attribute::default(),
+                )
+                .property(
+                    "cached",
+                    !flags.contains(PageTableFlags::NO_CACHE),
+                    Attribute::default(),
+                )
+                .build()
+                .into()
+        })
+        .collect::<Vec<JsValue>>();
+
+    Ok(JsArray::from_iter(runs, context).into())
+}
+
+fn dump_allocations(
+    _this: &JsValue,
+    _args: &[JsValue],
+    _context: &mut Context,
+) -> JsResult<JsValue> {
+    trace::dump();
+    Ok(JsValue::undefined())
+}
+
+/// Like `Deno.memoryUsage()`, returns the heap and physical memory usage in bytes.
+pub fn memory_usage(
+    _this: &JsValue,
+    _args: &[JsValue],
+    context: &mut Context,
+) -> JsResult<JsValue> {
+    let HeapStats {
+        size,
+        used,
+        free,
+        holes,
+        largest_hole,
+        slab_size,
+        slab_used,
+    } = allocator::stats();
+
+    let (frames_total, frames_used) = {
+        let frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+        (
+            frame_allocator.total_frames() * Size4KiB::SIZE,
+            frame_allocator.used_frames() * Size4KiB::SIZE,
+        )
+    };
+
+    Ok(ObjectInitializer::new(context)
+        .property("heapTotal", size as f64, Attribute::default())
+        .property("heapUsed", used as f64, Attribute::default())
+        .property("heapFree", free as f64, Attribute::default())
+        .property("freeBlocks", holes as f64, Attribute::default())
+        .property(
+            "largestFreeBlock",
+            largest_hole as f64,
+            Attribute::default(),
+        )
+        .property("slabTotal", slab_size as f64, Attribute::default())
+        .property("slabUsed", slab_used as f64, Attribute::default())
+        .property(
+            "gcLastFreed",
+            LAST_FREED.load(Ordering::Relaxed) as f64,
+            Attribute::default(),
+        )
+        .property("physicalTotal", frames_total as f64, Attribute::default())
+        .property("physicalUsed", frames_used as f64, Attribute::default())
+        .property(
+            "physicalFree",
+            (frames_total - frames_used) as f64,
+            Attribute::default(),
+        )
+        .build()
+        .into())
+}
+
+pub fn init(obj: &mut ObjectInitializer) {
+    let memory = ObjectInitializer::new(&mut *obj.context)
+        .function(alloc, "alloc", 3)
+        .function(unmap, "unmap", 2)
+        .function(free, "free", 2)
+        .function(protect_range, "protect", 3)
+        .function(translate, "translate", 1)
+        .function(mappings, "mappings", 0)
+        .function(dump_allocations, "dumpAllocations", 0)
+        .build();
+
+    obj.property("memory", memory, Attribute::default())
+        .function(memory_usage, "memoryUsage", 0);
+}
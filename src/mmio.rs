@@ -0,0 +1,288 @@
+use {
+    crate::{
+        acpi::{array_buffer_at, detach_at},
+        memory::range_error,
+    },
+    alloc::{boxed::Box, collections::BTreeMap, format},
+    boa_engine::{
+        object::{JsObject, ObjectData, ObjectInitializer},
+        property::Attribute,
+        Context, JsBigInt, JsResult, JsValue,
+    },
+    boa_gc::{unsafe_empty_trace, Finalize, Trace},
+    core::{
+        mem::size_of,
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+    ingram_kernel::{
+        log,
+        memory::{map_mmio, unmap_mmio, FRAME_ALLOCATOR, MAPPER, MMIO_RELEASABLE},
+    },
+    spin::Mutex,
+    x86_64::{
+        align_down,
+        structures::paging::{PageSize, Size4KiB},
+    },
+};
+
+/// An identity mapped MMIO range, released when collected.
+#[derive(Debug)]
+struct Mmio {
+    id: usize,
+    base: u64,
+    length: u64,
+}
+
+unsafe impl Trace for Mmio {
+    unsafe_empty_trace!();
+}
+
+impl Finalize for Mmio {
+    fn finalize(&self) {
+        release(self.id);
+    }
+}
+
+/// A live [`Mmio`] range `[start, end]`.
+struct Region {
+    start: u64,
+    end: u64,
+    /// The `ArrayBuffer` over the range, rooted until it is released so that its `Vec` never
+    /// reaches the allocator.
+    buffer: Option<JsObject>,
+}
+
+/// The live ranges by id.
+static REGIONS: Mutex<BTreeMap<usize, Region>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The largest range mapped at once, so that a typo cannot map most of the address space.
+const MAX_LENGTH: u64 = 1 << 30;
+
+/// Detaches the buffer of the range `id` and unmaps the pages that `Kernel.mapMmio` mapped for
+/// it, unless another live range uses them. Returns `false` if it is released already.
+fn release(id: usize) -> bool {
+    let region = match REGIONS.lock().remove(&id) {
+        Some(region) => region,
+        None => return false,
+    };
+    if let Some(buffer) = &region.buffer {
+        detach_at(buffer, region.start as *mut u8);
+    }
+
+    // A finalizer may run while the page table is locked, the pages stay mapped then
+    match MAPPER.get().and_then(|mapper| mapper.try_lock()) {
+        Some(mut mapper) => {
+            let regions = REGIONS.lock();
+            unmap_mmio(&mut *mapper, region.start, region.end, |addr| {
+                regions.values().any(|other| {
+                    align_down(other.start, Size4KiB::SIZE) <= addr && addr <= other.end
+                })
+            });
+        }
+        None => log!(
+            "MMIO {:#x}..={:#x} released but left mapped",
+            region.start,
+            region.end
+        ),
+    }
+    true
+}
+
+/// Releases the range `[start, end]` mapped by `Kernel.mapMmio`, given as it was mapped.
+pub fn release_range(start: u64, end: u64) -> bool {
+    let id = REGIONS
+        .lock()
+        .iter()
+        .find(|(_, region)| region.start == start && region.end == end)
+        .map(|(&id, _)| id);
+    id.map_or(false, release)
+}
+
+/// Returns the address of the access of `size` bytes at the offset in `args`, which must be
+/// aligned and in bounds.
+fn address(this: &JsValue, args: &[JsValue], size: u64, context: &mut Context) -> JsResult<u64> {
+    let (base, length) = this
+        .as_object()
+        .and_then(|object| {
+            object
+                .downcast_ref::<Mmio>()
+                .filter(|mmio| REGIONS.lock().contains_key(&mmio.id))
+                .map(|mmio| (mmio.base, mmio.length))
+        })
+        .ok_or(context.construct_type_error("not a mapped MMIO region"))?;
+
+    let offset = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing offset"))?
+        .to_index(context)? as u64;
+    if offset % size != 0 {
+        return Err(context.construct_range_error("misaligned offset"));
+    }
+    if offset + size > length {
+        return Err(context.construct_range_error("offset out of bounds"));
+    }
+    Ok(base + offset)
+}
+
+fn read<T: Into<f64>>(
+    this: &JsValue,
+    args: &[JsValue],
+    context: &mut Context,
+) -> JsResult<JsValue> {
+    let addr = address(this, args, size_of::<T>() as u64, context)?;
+    let value = unsafe { (addr as *const T).read_volatile() };
+
+    Ok(JsValue::Rational(value.into()))
+}
+
+fn write<T: TryFrom<u32>>(
+    this: &JsValue,
+    args: &[JsValue],
+    context: &mut Context,
+) -> JsResult<JsValue> {
+    let addr = address(this, args, size_of::<T>() as u64, context)?;
+    let value = args
+        .get(1)
+        .ok_or(context.construct_type_error("missing value"))?
+        .to_u32(context)?;
+    let value =
+        T::try_from(value).map_err(|_| context.construct_range_error("value out of range"))?;
+    unsafe { (addr as *mut T).write_volatile(value) };
+
+    Ok(JsValue::undefined())
+}
+
+fn read64(this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let addr = address(this, args, 8, context)?;
+    let value = unsafe { (addr as *const u64).read_volatile() };
+
+    Ok(JsValue::BigInt(JsBigInt::from(value)))
+}
+
+/// Takes a BigInt, or a Number for values that fit in one.
+fn write64(this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let addr = address(this, args, 8, context)?;
+    let value = match args.get(1) {
+        Some(JsValue::BigInt(value)) => u64::try_from(value.as_inner())
+            .map_err(|_| context.construct_range_error("value out of range"))?,
+        Some(value) => value.to_index(context)? as u64,
+        None => return Err(context.construct_type_error("missing value")),
+    };
+    unsafe { (addr as *mut u64).write_volatile(value) };
+
+    Ok(JsValue::undefined())
+}
+
+fn map(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let base = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing address"))?
+        .to_index(context)? as u64;
+    let length = args
+        .get(1)
+        .ok_or(context.construct_type_error("missing length"))?
+        .to_index(context)? as u64;
+    if length == 0 || length > MAX_LENGTH {
+        return Err(context
+            .construct_range_error(format!("length must be between 1 and {:#x}", MAX_LENGTH)));
+    }
+    let end = base
+        .checked_add(length - 1)
+        .ok_or(context.construct_range_error("range overflows"))?;
+    let buffer = match args.get(2).and_then(|options| options.as_object()) {
+        Some(options) => options.get("buffer", context)?.to_boolean(),
+        None => false,
+    };
+    // The buffer takes the range as the allocation of a `Vec`, whose pointer must not be null
+    if buffer && (base == 0 || base % Size4KiB::SIZE != 0) {
+        return Err(context
+            .construct_range_error("the address of a buffer must be a nonzero multiple of 4KiB"));
+    }
+
+    {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+        if frame_allocator.is_ram(base, end) {
+            return Err(
+                context.construct_range_error(format!("{:#x}..={:#x} overlaps RAM", base, end))
+            );
+        }
+        map_mmio(
+            &mut *MAPPER.get().unwrap().lock(),
+            &mut *frame_allocator,
+            base,
+            end,
+            MMIO_RELEASABLE,
+        )
+        .map_err(|err| range_error(err, context))?;
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    REGIONS.lock().insert(
+        id,
+        Region {
+            start: base,
+            end,
+            buffer: None,
+        },
+    );
+
+    let mut mmio = ObjectInitializer {
+        context,
+        object: JsObject::from_proto_and_data(
+            None,
+            ObjectData::native_object(Box::new(Mmio { id, base, length })),
+        ),
+    };
+    mmio.property(
+        "address",
+        JsValue::Rational(base as f64),
+        Attribute::default(),
+    )
+    .property(
+        "length",
+        JsValue::Rational(length as f64),
+        Attribute::default(),
+    )
+    .function(read::<u8>, "read8", 1)
+    .function(read::<u16>, "read16", 1)
+    .function(read::<u32>, "read32", 1)
+    .function(read64, "read64", 1)
+    .function(write::<u8>, "write8", 2)
+    .function(write::<u16>, "write16", 2)
+    .function(write::<u32>, "write32", 2)
+    .function(write64, "write64", 2)
+    .function(release_region, "release", 0);
+
+    if buffer {
+        // The accesses of typed arrays are bounds checked, but not volatile
+        let buffer = unsafe { array_buffer_at(base as *mut u8, length as usize, mmio.context) };
+        let buffer = match buffer {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                release(id);
+                return Err(err);
+            }
+        };
+        if let Some(region) = REGIONS.lock().get_mut(&id) {
+            region.buffer = Some(buffer.clone());
+        }
+        mmio.property("buffer", buffer, Attribute::default());
+    }
+
+    Ok(mmio.build().into())
+}
+
+fn release_region(this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let id = this
+        .as_object()
+        .and_then(|object| object.downcast_ref::<Mmio>().map(|mmio| mmio.id))
+        .ok_or(context.construct_type_error("not an MMIO region"))?;
+    release(id);
+
+    Ok(JsValue::undefined())
+}
+
+pub fn init(obj: &mut ObjectInitializer) {
+    obj.function(map, "mapMmio", 3);
+}
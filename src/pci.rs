@@ -0,0 +1,106 @@
+use {
+    alloc::vec::Vec,
+    boa_engine::{
+        object::{JsArray, JsObject, ObjectInitializer},
+        property::Attribute,
+        Context, JsResult, JsValue,
+    },
+    ingram_kernel::pci::{Bar, BarKind, Capability, Device, DEVICES},
+};
+
+fn bar_object(bar: &Bar, context: &mut Context) -> JsObject {
+    let (kind, prefetchable, is_64) = match bar.kind {
+        BarKind::Memory {
+            prefetchable,
+            is_64,
+        } => ("memory", prefetchable, is_64),
+        BarKind::Io => ("io", false, false),
+    };
+
+    ObjectInitializer::new(context)
+        .property("index", bar.index, Attribute::default())
+        .property("kind", kind, Attribute::default())
+        .property(
+            "address",
+            JsValue::Rational(bar.address as f64),
+            Attribute::default(),
+        )
+        .property(
+            "size",
+            JsValue::Rational(bar.size as f64),
+            Attribute::default(),
+        )
+        .property("prefetchable", prefetchable, Attribute::default())
+        .property("is64", is_64, Attribute::default())
+        .build()
+}
+
+fn capability_array(capabilities: &[Capability], context: &mut Context) -> JsArray {
+    let capabilities = capabilities
+        .iter()
+        .map(|capability| {
+            ObjectInitializer::new(context)
+                .property("id", capability.id, Attribute::default())
+                .property("offset", capability.offset, Attribute::default())
+                .build()
+                .into()
+        })
+        .collect::<Vec<JsValue>>();
+
+    JsArray::from_iter(capabilities, context)
+}
+
+fn device_object(device: &Device, context: &mut Context) -> JsObject {
+    let bars = device
+        .bars
+        .iter()
+        .map(|bar| bar_object(bar, context).into())
+        .collect::<Vec<JsValue>>();
+    let bars = JsArray::from_iter(bars, context);
+    let capabilities = capability_array(&device.capabilities, context);
+    let extended_capabilities = capability_array(&device.extended_capabilities, context);
+
+    let address = device.address;
+    ObjectInitializer::new(context)
+        .property("segment", address.segment, Attribute::default())
+        .property("bus", address.bus, Attribute::default())
+        .property("device", address.device, Attribute::default())
+        .property("function", address.function, Attribute::default())
+        .property("vendorId", device.vendor_id, Attribute::default())
+        .property("deviceId", device.device_id, Attribute::default())
+        .property("class", device.class, Attribute::default())
+        .property("subclass", device.subclass, Attribute::default())
+        .property("progIf", device.prog_if, Attribute::default())
+        .property("revision", device.revision, Attribute::default())
+        .property("headerType", device.header_type, Attribute::default())
+        .property("interruptLine", device.interrupt_line, Attribute::default())
+        .property("interruptPin", device.interrupt_pin, Attribute::default())
+        .property("bars", bars, Attribute::default())
+        .property("capabilities", capabilities, Attribute::default())
+        .property(
+            "extendedCapabilities",
+            extended_capabilities,
+            Attribute::default(),
+        )
+        .build()
+}
+
+/// Returns a snapshot of the functions enumerated at boot.
+fn devices(_this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let devices = DEVICES
+        .get()
+        .unwrap()
+        .iter()
+        .map(|device| device_object(device, context).into())
+        .collect::<Vec<JsValue>>();
+
+    Ok(JsArray::from_iter(devices, context).into())
+}
+
+pub fn init(obj: &mut ObjectInitializer) {
+    let pci = ObjectInitializer::new(&mut *obj.context)
+        .function(devices, "devices", 0)
+        .build();
+
+    obj.property("pci", pci, Attribute::default());
+}
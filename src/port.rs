@@ -1,54 +1,373 @@
 use {
-    boa_engine::{object::ObjectInitializer, Context, JsResult, JsValue},
-    core::fmt::Debug,
+    alloc::{
+        boxed::Box,
+        collections::BTreeMap,
+        format,
+        string::{String, ToString},
+        vec::Vec,
+    },
+    boa_engine::{
+        object::{JsArray, JsObject, ObjectData, ObjectInitializer},
+        property::Attribute,
+        Context, JsResult, JsValue,
+    },
+    boa_gc::{unsafe_empty_trace, Finalize, Trace},
+    core::{arch::asm, cell::Cell, mem::size_of},
+    ingram_kernel::power::{self, KEYBOARD_CONTROLLER, SHUTDOWN_PORTS, SHUTDOWN_PORT_BYTE},
+    spin::{Mutex, Once},
     x86_64::instructions::port::{PortRead, PortReadOnly, PortWrite, PortWriteOnly},
 };
 
+/// Ranges of fixed devices used by the kernel itself, which cannot be requested.
+const FIXED: [(u16, u32, &str); 8] = [
+    (0x20, 2, "kernel:pic"),
+    (0x40, 4, "kernel:pit"),
+    (0x61, 1, "kernel:pit"),
+    (0xa0, 2, "kernel:pic"),
+    (0xf4, 1, "kernel:qemu-exit"),
+    (0x2f8, 8, "kernel:com2"),
+    (0x3f8, 8, "kernel:com1"),
+    (0xcf8, 8, "kernel:pci"),
+];
+
+/// The ranges that cannot be requested: [`FIXED`], the registers of the FADT and the ports that
+/// power off and reboot fall back to.
+static RESERVED: Once<Vec<(u16, u32, &'static str)>> = Once::new();
+
+fn reserved() -> Vec<(u16, u32, &'static str)> {
+    let mut reserved = Vec::from(FIXED);
+    reserved.extend(power::acpi_ports().map(|(base, count)| (base, count, "kernel:acpi")));
+    reserved.extend(
+        SHUTDOWN_PORTS
+            .iter()
+            .map(|&(port, _)| (port, 2, "kernel:power")),
+    );
+    reserved.push((SHUTDOWN_PORT_BYTE.0, 1, "kernel:power"));
+    reserved.push((KEYBOARD_CONTROLLER, 1, "kernel:power"));
+    reserved
+}
+
+struct Grant {
+    count: u32,
+    owner: String,
+}
+
+/// The granted ranges by first port.
+static GRANTS: Mutex<BTreeMap<u16, Grant>> = Mutex::new(BTreeMap::new());
+
+/// A granted range, released when collected.
+#[derive(Debug)]
+struct Ports {
+    base: u16,
+    count: u32,
+    released: Cell<bool>,
+}
+
+unsafe impl Trace for Ports {
+    unsafe_empty_trace!();
+}
+
+impl Finalize for Ports {
+    fn finalize(&self) {
+        if !self.released.replace(true) {
+            GRANTS.lock().remove(&self.base);
+        }
+    }
+}
+
+fn grant(base: u16, count: u32, owner: String) -> Result<(), String> {
+    let mut grants = GRANTS.lock();
+    let end = base as u32 + count;
+    let conflict = RESERVED
+        .get()
+        .into_iter()
+        .flatten()
+        .copied()
+        .chain(
+            grants
+                .iter()
+                .map(|(&start, grant)| (start, grant.count, grant.owner.as_str())),
+        )
+        .find(|&(start, count, _)| (start as u32) < end && (base as u32) < start as u32 + count);
+    if let Some((start, count, owner)) = conflict {
+        return Err(format!(
+            "ports {:#x}..{:#x} are held by {}",
+            start,
+            start as u32 + count,
+            owner
+        ));
+    }
+
+    grants.insert(base, Grant { count, owner });
+    Ok(())
+}
+
+/// Returns the port at the offset in `args`, if the access of `T` fits in the range of `this`.
+fn port<T>(this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<u16> {
+    let (base, count) = this
+        .as_object()
+        .and_then(|object| {
+            object
+                .downcast_ref::<Ports>()
+                .filter(|ports| !ports.released.get())
+                .map(|ports| (ports.base, ports.count))
+        })
+        .ok_or(context.construct_type_error("not a granted port range"))?;
+
+    let offset = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing offset"))?
+        .to_index(context)?;
+    match offset.checked_add(size_of::<T>()) {
+        Some(end) if end <= count as usize => Ok(base + offset as u16),
+        _ => Err(context.construct_range_error(format!(
+            "offset {:#x} out of the {} ports at {:#x}",
+            offset, count, base
+        ))),
+    }
+}
+
 fn port_in<T: PortRead + Into<f64>>(
-    _this: &JsValue,
+    this: &JsValue,
     args: &[JsValue],
     context: &mut Context,
 ) -> JsResult<JsValue> {
-    let port = args
-        .get(0)
-        .ok_or(context.construct_type_error("missing port"))?
-        .to_uint16(context)?;
-
-    let mut port = PortReadOnly::<T>::new(port);
+    let mut port = PortReadOnly::<T>::new(port::<T>(this, args, context)?);
     let value = unsafe { port.read() };
 
     Ok(JsValue::Rational(value.into()))
 }
 
-fn port_out<T: PortWrite + TryFrom<u32> + Default>(
-    _this: &JsValue,
+fn port_out<T: PortWrite + TryFrom<u32>>(
+    this: &JsValue,
     args: &[JsValue],
     context: &mut Context,
-) -> JsResult<JsValue>
-where
-    T::Error: Debug,
-{
-    let port = args
-        .get(0)
-        .ok_or(context.construct_type_error("missing port"))?
-        .to_uint16(context)?;
+) -> JsResult<JsValue> {
+    let port = port::<T>(this, args, context)?;
 
     let value = args
         .get(1)
-        .ok_or(context.construct_type_error("missing port"))?
-        .to_u32(context)?;
+        .ok_or(context.construct_type_error("missing value"))?
+        .to_number(context)?;
+    let value = (value.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&value))
+        .then(|| T::try_from(value as u32).ok())
+        .flatten()
+        .ok_or(context.construct_range_error(format!("value {} out of range", value)))?;
 
     let mut port = PortWriteOnly::<T>::new(port);
-    unsafe { port.write(T::try_from(value).unwrap()) };
+    unsafe { port.write(value) };
 
     Ok(JsValue::undefined())
 }
 
+/// `rep ins` and `rep outs` of `Self`, which move `count` values between a port and memory.
+trait PortString {
+    unsafe fn ins(port: u16, dst: *mut u8, count: usize);
+    unsafe fn outs(port: u16, src: *const u8, count: usize);
+}
+
+macro_rules! impl_port_string {
+    ($ty:ty, $ins:literal, $outs:literal) => {
+        impl PortString for $ty {
+            unsafe fn ins(port: u16, dst: *mut u8, count: usize) {
+                asm!($ins, in("dx") port, inout("rdi") dst => _, inout("rcx") count => _,
+                    options(nostack, preserves_flags));
+            }
+
+            unsafe fn outs(port: u16, src: *const u8, count: usize) {
+                asm!($outs, in("dx") port, inout("rsi") src => _, inout("rcx") count => _,
+                    options(nostack, readonly, preserves_flags));
+            }
+        }
+    };
+}
+
+impl_port_string!(u8, "rep insb", "rep outsb");
+impl_port_string!(u16, "rep insw", "rep outsw");
+impl_port_string!(u32, "rep insd", "rep outsd");
+
+/// Returns the `ArrayBuffer` viewed by the typed array or `ArrayBuffer` in `value`, with the
+/// offset and length of the view in bytes.
+fn view(value: Option<&JsValue>, context: &mut Context) -> JsResult<(JsObject, usize, usize)> {
+    let object = value
+        .and_then(JsValue::as_object)
+        .ok_or(context.construct_type_error("missing buffer"))?;
+    let data = object.borrow();
+
+    if let Some(array) = data.as_typed_array() {
+        let buffer = array
+            .viewed_array_buffer()
+            .cloned()
+            .ok_or(context.construct_type_error("detached buffer"))?;
+        Ok((buffer, array.byte_offset(), array.byte_length()))
+    } else if let Some(buffer) = data.as_array_buffer() {
+        Ok((object.clone(), 0, buffer.array_buffer_byte_length))
+    } else {
+        Err(context.construct_type_error("expect a typed array or ArrayBuffer"))
+    }
+}
+
+/// Fills the buffer in `args` with as many values as fit, and returns their count.
+fn port_ins<T: PortString>(
+    this: &JsValue,
+    args: &[JsValue],
+    context: &mut Context,
+) -> JsResult<JsValue> {
+    let port = port::<T>(this, args, context)?;
+    let (buffer, offset, length) = view(args.get(1), context)?;
+    let count = length / size_of::<T>();
+
+    let mut buffer = buffer.borrow_mut();
+    let bytes = buffer
+        .as_array_buffer_mut()
+        .and_then(|buffer| buffer.array_buffer_data.as_mut())
+        .ok_or(context.construct_type_error("detached buffer"))?;
+    let bytes = &mut bytes[offset..offset + length];
+    unsafe { T::ins(port, bytes.as_mut_ptr(), count) };
+
+    Ok(JsValue::Rational(count as f64))
+}
+
+/// Writes the values of the buffer in `args`, and returns their count.
+fn port_outs<T: PortString>(
+    this: &JsValue,
+    args: &[JsValue],
+    context: &mut Context,
+) -> JsResult<JsValue> {
+    let port = port::<T>(this, args, context)?;
+    let (buffer, offset, length) = view(args.get(1), context)?;
+    let count = length / size_of::<T>();
+
+    let buffer = buffer.borrow();
+    let bytes = buffer
+        .as_array_buffer()
+        .and_then(|buffer| buffer.array_buffer_data.as_ref())
+        .ok_or(context.construct_type_error("detached buffer"))?;
+    let bytes = &bytes[offset..offset + length];
+    unsafe { T::outs(port, bytes.as_ptr(), count) };
+
+    Ok(JsValue::Rational(count as f64))
+}
+
+/// Runs a list of `[op, offset, value?]`, where `op` is the name of a single access, in one call.
+/// Returns the value read by each op, `undefined` for writes.
+fn port_ops(this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let ops = args
+        .get(0)
+        .and_then(JsValue::as_object)
+        .ok_or(context.construct_type_error("missing ops"))?;
+    let length = ops.get("length", context)?.to_length(context)?;
+
+    let mut results = Vec::with_capacity(length);
+    for i in 0..length {
+        let op = ops.get(i, context)?;
+        let op = op
+            .as_object()
+            .ok_or(context.construct_type_error("expect [op, offset, value?]"))?;
+        let name = op.get(0, context)?.to_string(context)?;
+        let args = [op.get(1, context)?, op.get(2, context)?];
+
+        let result = match name.as_str() {
+            "inb" => port_in::<u8>(this, &args, context),
+            "outb" => port_out::<u8>(this, &args, context),
+            "inw" => port_in::<u16>(this, &args, context),
+            "outw" => port_out::<u16>(this, &args, context),
+            "inl" => port_in::<u32>(this, &args, context),
+            "outl" => port_out::<u32>(this, &args, context),
+            _ => Err(context.construct_range_error(format!("unknown op {}", name.as_str()))),
+        }?;
+        results.push(result);
+    }
+
+    Ok(JsArray::from_iter(results, context).into())
+}
+
+fn release(this: &JsValue, _args: &[JsValue], _context: &mut Context) -> JsResult<JsValue> {
+    if let Some(ports) = this
+        .as_object()
+        .and_then(|object| object.downcast_ref::<Ports>())
+    {
+        ports.finalize();
+    }
+    Ok(JsValue::undefined())
+}
+
+fn request(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let base = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing base"))?
+        .to_index(context)?;
+    let count = args
+        .get(1)
+        .ok_or(context.construct_type_error("missing count"))?
+        .to_index(context)?;
+    if count == 0 || base + count > 0x10000 {
+        return Err(
+            context.construct_range_error(format!("invalid port range {:#x}+{:#x}", base, count))
+        );
+    }
+    let owner = match args.get(2) {
+        Some(owner) if !owner.is_undefined() => owner.to_string(context)?.to_string(),
+        _ => "js".into(),
+    };
+
+    let (base, count) = (base as u16, count as u32);
+    grant(base, count, owner).map_err(|err| context.construct_error(err))?;
+
+    let ports = Ports {
+        base,
+        count,
+        released: Cell::new(false),
+    };
+    Ok(ObjectInitializer {
+        context,
+        object: JsObject::from_proto_and_data(None, ObjectData::native_object(Box::new(ports))),
+    }
+    .property("base", base, Attribute::default())
+    .property("count", count, Attribute::default())
+    .function(port_in::<u8>, "inb", 1)
+    .function(port_out::<u8>, "outb", 2)
+    .function(port_in::<u16>, "inw", 1)
+    .function(port_out::<u16>, "outw", 2)
+    .function(port_in::<u32>, "inl", 1)
+    .function(port_out::<u32>, "outl", 2)
+    .function(port_ins::<u8>, "insb", 2)
+    .function(port_outs::<u8>, "outsb", 2)
+    .function(port_ins::<u16>, "insw", 2)
+    .function(port_outs::<u16>, "outsw", 2)
+    .function(port_ins::<u32>, "insl", 2)
+    .function(port_outs::<u32>, "outsl", 2)
+    .function(port_ops, "portOps", 1)
+    .function(release, "release", 0)
+    .build()
+    .into())
+}
+
+fn list_grants(_this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let grants = GRANTS
+        .lock()
+        .iter()
+        .map(|(&base, grant)| (base, grant.count, grant.owner.clone()))
+        .collect::<Vec<_>>();
+
+    let grants = grants
+        .into_iter()
+        .map(|(base, count, owner)| {
+            ObjectInitializer::new(context)
+                .property("base", base, Attribute::default())
+                .property("count", count, Attribute::default())
+                .property("owner", owner, Attribute::default())
+                .build()
+                .into()
+        })
+        .collect::<Vec<JsValue>>();
+
+    Ok(JsArray::from_iter(grants, context).into())
+}
+
 pub fn init(obj: &mut ObjectInitializer) {
-    obj.function(port_in::<u8>, "inb", 1)
-        .function(port_out::<u8>, "outb", 2)
-        .function(port_in::<u16>, "inw", 1)
-        .function(port_out::<u16>, "outw", 2)
-        .function(port_in::<u32>, "inl", 1)
-        .function(port_out::<u32>, "outl", 2);
+    RESERVED.call_once(reserved);
+
+    obj.function(request, "requestPorts", 3)
+        .function(list_grants, "portGrants", 0);
 }
@@ -0,0 +1,21 @@
+use {
+    boa_engine::{object::ObjectInitializer, Context, JsResult, JsValue},
+    ingram_kernel::power::{poweroff, reboot},
+};
+
+pub fn init(obj: &mut ObjectInitializer) {
+    obj.function(
+        |_this: &JsValue, _args: &[JsValue], _context: &mut Context| -> JsResult<JsValue> {
+            poweroff()
+        },
+        "poweroff",
+        0,
+    )
+    .function(
+        |_this: &JsValue, _args: &[JsValue], _context: &mut Context| -> JsResult<JsValue> {
+            reboot()
+        },
+        "reboot",
+        0,
+    );
+}
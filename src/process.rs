@@ -1,4 +1,8 @@
 use {
+    crate::{
+        console,
+        memory::{self, memory_usage},
+    },
     alloc::boxed::Box,
     boa_engine::{
         object::{JsObject, ObjectData, ObjectInitializer},
@@ -12,6 +16,10 @@ use {
         sync::atomic::{AtomicI32, Ordering},
     },
     crossbeam_queue::ArrayQueue,
+    ingram_kernel::allocator::{
+        oom,
+        trace::{with_owner, Owner},
+    },
     spin::Once,
     x86_64::{
         instructions::segmentation::{Segment, DS},
@@ -22,6 +30,14 @@ use {
 pub const START_PID: i32 = 1;
 static PID: AtomicI32 = AtomicI32::new(START_PID + 1);
 
+/// The process killed to free memory, dropped before its next slice.
+static KILLED: AtomicI32 = AtomicI32::new(0);
+
+fn kill(pid: i32) -> bool {
+    KILLED.store(pid, Ordering::SeqCst);
+    true
+}
+
 #[derive(Finalize, Debug)]
 pub struct Process {
     pub id: i32,
@@ -40,13 +56,21 @@ impl Process {
     where
         S: AsRef<[u8]>,
     {
-        let mut context = Context::default();
-        context.parse_and_compile(code)?;
-
         let id = PID.fetch_add(1, Ordering::SeqCst);
 
+        let mut context = with_owner(Owner::Process(id), || -> JsResult<Context> {
+            let mut context = Context::default();
+            context.parse_and_compile(code)?;
+            Ok(context)
+        })?;
+
+        let stdin = ObjectInitializer::new(&mut context)
+            .function(console::read, "read", 1)
+            .build();
         let deno_obj = ObjectInitializer::new(&mut context)
             .property("pid", JsValue::Integer(id), Attribute::default())
+            .property("stdin", stdin, Attribute::default())
+            .function(memory_usage, "memoryUsage", 0)
             .build();
 
         context.register_global_property("deno", deno_obj, Attribute::default());
@@ -68,6 +92,7 @@ where
     S: AsRef<[u8]>,
 {
     let (proc, mut context) = Process::try_new(code)?; // insert later
+    let id = proc.id;
 
     let proc = {
         let mut proc = ObjectInitializer {
@@ -82,8 +107,22 @@ where
                 let proc = this.as_object().unwrap().downcast_ref::<Process>().unwrap();
                 let mut ctx = proc.ctx.borrow_mut();
 
+                if ctx.is_some()
+                    && KILLED
+                        .compare_exchange(proc.id, 0, Ordering::SeqCst, Ordering::SeqCst)
+                        .is_ok()
+                {
+                    *ctx = None;
+                    memory::collect();
+                    oom::rearm();
+                    oom::shrink();
+                    log!("Process {} killed: out of memory", proc.id);
+                    return Ok(false.into());
+                }
+
                 if let Some(context) = ctx.as_mut() {
-                    let (_result, ret_type) = context.run_steps(STEPS)?;
+                    let (_result, ret_type) =
+                        with_owner(Owner::Process(proc.id), || context.run_steps(STEPS))?;
                     if let ReturnType::Yield = ret_type {
                         return Ok(true.into());
                     }
@@ -94,6 +133,7 @@ where
             "steps",
             0,
         )
+        .property("pid", JsValue::Integer(id), Attribute::default())
         .build()
     };
 
@@ -111,6 +151,7 @@ pub static KERNEL_MICROTASKS: Once<ArrayQueue<JsObject>> = Once::new();
 
 pub fn init(obj: &mut ObjectInitializer) {
     KERNEL_MICROTASKS.call_once(|| ArrayQueue::new(8));
+    oom::set_killer(kill);
 
     obj.function(
         |_this, args, context| {
@@ -145,3 +186,37 @@ pub fn init(obj: &mut ObjectInitializer) {
         1,
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, alloc::vec, ingram_kernel::deferred};
+
+    /// Keeps every block alive until the process is killed.
+    const SCRIPT: &str = "const blocks = []; while (true) blocks.push('x'.repeat(1 << 18));";
+
+    #[test_case]
+    fn out_of_memory_kills_the_largest_process() {
+        oom::set_collector(memory::collect);
+        oom::set_killer(kill);
+        let mut context = Context::default();
+
+        let proc = new_process(SCRIPT).unwrap();
+        let steps = proc.get("steps", &mut context).unwrap();
+        let steps = steps.as_object().unwrap().clone();
+        // The collection, and the kill, run from the main loop between slices
+        while steps
+            .call(&proc.clone().into(), &[], &mut context)
+            .unwrap()
+            .to_boolean()
+        {
+            deferred::run();
+        }
+
+        // Killed rather than finished, and the memory is back
+        let process = proc.downcast_ref::<Process>().unwrap();
+        assert!(process.ctx.borrow().is_none());
+        assert_eq!(KILLED.load(Ordering::SeqCst), 0);
+        let block = vec![1u8; 64 * 1024 * 1024];
+        assert!(block.iter().all(|&byte| byte == 1));
+    }
+}
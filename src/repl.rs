@@ -0,0 +1,168 @@
+use {
+    alloc::{
+        boxed::Box,
+        string::{String, ToString},
+        vec::Vec,
+    },
+    boa_engine::{
+        object::{JsObject, ObjectData, ObjectInitializer},
+        property::{Attribute, PropertyDescriptor, PropertyKey},
+        syntax::{parser::ParseError, Parser},
+        vm::ReturnType,
+        Context, JsResult, JsValue,
+    },
+    boa_gc::{unsafe_empty_trace, Finalize, Trace},
+    core::cell::{Cell, RefCell},
+};
+
+/// A context whose globals persist between the evaluations of a REPL.
+///
+/// An evaluation runs a slice of instructions per call to [`steps`], like a process, so that an
+/// endless loop does not hang the kernel and can be interrupted.
+#[derive(Finalize, Debug)]
+struct Repl {
+    ctx: RefCell<Context>,
+    /// Set while an evaluation has instructions left to run
+    running: Cell<bool>,
+    /// The result of an evaluation that failed to compile, returned by the next [`steps`]
+    failed: RefCell<Option<String>>,
+}
+
+unsafe impl Trace for Repl {
+    unsafe_empty_trace!();
+}
+
+fn this_repl<'a>(this: &'a JsValue, context: &mut Context) -> JsResult<&'a JsObject> {
+    this.as_object()
+        .filter(|object| object.downcast_ref::<Repl>().is_some())
+        .ok_or(context.construct_type_error("not a REPL"))
+}
+
+/// Returns the error as inspected in the REPL context.
+fn inspect_error(err: JsValue, ctx: &mut Context) -> String {
+    match err.to_string(ctx) {
+        Ok(message) => message.to_string(),
+        Err(_) => err.display().to_string(),
+    }
+}
+
+/// Starts evaluating the source in the REPL context, [`steps`] runs it. Values of the REPL
+/// context never leave it.
+fn eval(this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let source = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing source"))?
+        .to_string(context)?;
+    let object = this_repl(this, context)?;
+    let repl = object.downcast_ref::<Repl>().unwrap();
+    if repl.running.get() || repl.failed.borrow().is_some() {
+        return Err(context.construct_error("an evaluation is running"));
+    }
+
+    let mut ctx = repl.ctx.borrow_mut();
+    match ctx.parse_and_compile(source.as_bytes()) {
+        Ok(_) => repl.running.set(true),
+        Err(err) => *repl.failed.borrow_mut() = Some(inspect_error(err, &mut ctx)),
+    }
+    Ok(JsValue::undefined())
+}
+
+/// Runs a slice of the evaluation. Returns `undefined` while it has instructions left, then
+/// `{ ok, output }`, where `output` is the inspected result or the thrown error.
+fn steps(this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    const STEPS: usize = 512;
+
+    let object = this_repl(this, context)?;
+    let (ok, output) = {
+        let repl = object.downcast_ref::<Repl>().unwrap();
+        if let Some(output) = repl.failed.borrow_mut().take() {
+            (false, output)
+        } else if !repl.running.get() {
+            return Err(context.construct_error("no evaluation is running"));
+        } else {
+            let mut ctx = repl.ctx.borrow_mut();
+            let result = match ctx.run_steps(STEPS) {
+                Ok((_, ReturnType::Yield)) => return Ok(JsValue::undefined()),
+                Ok((value, _)) => (true, value.display().to_string()),
+                Err(err) => (false, inspect_error(err, &mut ctx)),
+            };
+            repl.running.set(false);
+            result
+        }
+    };
+
+    Ok(ObjectInitializer::new(context)
+        .property("ok", ok, Attribute::default())
+        .property("output", output, Attribute::default())
+        .build()
+        .into())
+}
+
+/// Returns a new context holding the properties that the evaluations added to the global object
+/// of `old`, as the VM of `old` cannot be unwound in the middle of a slice.
+fn carry_globals(old: &Context) -> Context {
+    let globals = old
+        .global_object()
+        .borrow()
+        .properties()
+        .iter()
+        .map(|(key, desc)| (key, desc.clone()))
+        .collect::<Vec<(PropertyKey, PropertyDescriptor)>>();
+
+    let mut ctx = Context::default();
+    let global = ctx.global_object().clone();
+    for (key, desc) in globals {
+        // The builtins of the new context are kept
+        if let Ok(false) = global.has_own_property(key.clone(), &mut ctx) {
+            let _ = global.define_property_or_throw(key, desc, &mut ctx);
+        }
+    }
+    ctx
+}
+
+/// Abandons the running evaluation. Its context is replaced, keeping the global variables and
+/// functions of the previous evaluations but not their `let`, `const` and `class` declarations.
+fn interrupt(this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let object = this_repl(this, context)?;
+    let repl = object.downcast_ref::<Repl>().unwrap();
+    *repl.failed.borrow_mut() = None;
+    if repl.running.replace(false) {
+        let mut ctx = repl.ctx.borrow_mut();
+        *ctx = carry_globals(&ctx);
+    }
+    Ok(JsValue::undefined())
+}
+
+/// Returns whether the source parses, or fails before its end. If not, more lines are needed.
+fn is_complete(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let source = args
+        .get(0)
+        .ok_or(context.construct_type_error("missing source"))?
+        .to_string(context)?;
+
+    let result = Parser::new(source.as_bytes(), false).parse_all(context);
+    Ok((!matches!(result, Err(ParseError::AbruptEnd))).into())
+}
+
+fn create(_this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let repl = Repl {
+        ctx: RefCell::new(Context::default()),
+        running: Cell::new(false),
+        failed: RefCell::new(None),
+    };
+
+    Ok(ObjectInitializer {
+        context,
+        object: JsObject::from_proto_and_data(None, ObjectData::native_object(Box::new(repl))),
+    }
+    .function(eval, "eval", 1)
+    .function(steps, "steps", 0)
+    .function(interrupt, "interrupt", 0)
+    .function(is_complete, "isComplete", 1)
+    .build()
+    .into())
+}
+
+pub fn init(obj: &mut ObjectInitializer) {
+    obj.function(create, "createRepl", 0);
+}
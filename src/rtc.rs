@@ -1,6 +1,11 @@
-use boa_engine::{object::ObjectInitializer, property::Attribute, JsValue};
+use {
+    boa_engine::{object::ObjectInitializer, property::Attribute, JsValue},
+    ingram_kernel::acpi::CAPABILITIES,
+};
 
-pub fn init(obj: &mut ObjectInitializer, century: u8) {
+pub fn init(obj: &mut ObjectInitializer) {
+    // 0 if the RTC has no century register
+    let century = CAPABILITIES.get().unwrap().century.unwrap_or(0);
     obj.property(
         "RTC_CENTURY_REG",
         JsValue::Integer(century as i32),
@@ -9,8 +9,12 @@ extern crate alloc;
 use {
     alloc::{boxed::Box, vec::Vec},
     ingram_kernel::{
-        allocator, constant::HEAP_SIZE, entry_point, gdt, interrupt, memory, uart, BootInfo,
-        QEMUExit, QEMU_EXIT_HANDLE,
+        allocator::{
+            self,
+            trace::{self, with_owner, Owner},
+        },
+        constant::HEAP_SIZE,
+        entry_point, gdt, interrupt, memory, uart, BootInfo, QEMUExit, QEMU_EXIT_HANDLE,
     },
 };
 
@@ -46,6 +50,17 @@ fn test_kernel_main(boot_info: &'static mut BootInfo) -> ! {
             assert_eq!(*x, i);
         }
     }
+    {
+        // owner_accounting
+        let block = with_owner(Owner::Process(7), || Box::new([0u8; 100]));
+        let usage = trace::usage(Owner::Process(7)).unwrap();
+        assert_eq!((usage.bytes, usage.blocks), (100, 1));
+        assert_eq!(trace::largest_process().unwrap().0, 7);
+
+        drop(block);
+        assert_eq!(trace::usage(Owner::Process(7)).unwrap().blocks, 0);
+        assert!(trace::largest_process().is_none());
+    }
 
     QEMU_EXIT_HANDLE.exit_success()
 }
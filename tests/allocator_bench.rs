@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, format_args_nl)]
+#![test_runner(ingram_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use {
+    alloc::{boxed::Box, vec::Vec},
+    boa_engine::Context,
+    core::arch::x86_64::_rdtsc,
+    ingram_kernel::{
+        allocator, entry_point, gdt, interrupt, memory, println, uart, BootInfo, QEMUExit,
+        QEMU_EXIT_HANDLE,
+    },
+};
+
+entry_point!(test_kernel_main);
+
+const ROUNDS: usize = 10_000;
+/// Live allocations kept around to fragment the free list, like a running interpreter.
+const LIVE: usize = 4096;
+/// Builds small objects, strings and arrays, and keeps one object in ten alive.
+const SCRIPT: &str = "
+    const kept = [];
+    for (let i = 0; i < 20000; i++) {
+        const object = { id: i, name: 'item ' + i, tags: [i, i * 2, i * 3] };
+        if (i % 10 === 0) kept.push(object);
+    }
+    kept.length
+";
+
+/// Returns the average cycles of allocating and freeing a `[u8; N]` while `LIVE` blocks of the
+/// same size are alive.
+fn bench<const N: usize>() -> u64 {
+    let mut live = Vec::with_capacity(LIVE);
+    let mut pads = Vec::with_capacity(LIVE);
+    for _ in 0..LIVE {
+        live.push(Box::new([0u8; N]));
+        pads.push(Box::new([0u8; 4000]));
+    }
+    // leaves a hole after every block
+    drop(pads);
+
+    let start = unsafe { _rdtsc() };
+    for i in 0..ROUNDS {
+        live[i % LIVE] = Box::new([i as u8; N]);
+    }
+    let cycles = unsafe { _rdtsc() } - start;
+
+    assert!(live.iter().all(|block| block[0] == block[N - 1]));
+    cycles / ROUNDS as u64
+}
+
+/// Returns the cycles of running [`SCRIPT`] and collecting its garbage.
+fn js_workload() -> u64 {
+    let mut context = Context::default();
+
+    let start = unsafe { _rdtsc() };
+    context.eval(SCRIPT).unwrap();
+    boa_gc::force_collect();
+    let cycles = unsafe { _rdtsc() } - start;
+
+    drop(context);
+    boa_gc::force_collect();
+    cycles
+}
+
+fn test_kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    uart::init();
+    gdt::init();
+    interrupt::init();
+    let (mut mapper, mut frame_allocator) = unsafe { memory::init(&boot_info.memory_regions) };
+    allocator::init(&mut mapper, &mut frame_allocator);
+
+    // served by the slabs
+    println!("32 B: {} cycles", bench::<32>());
+    println!("256 B: {} cycles", bench::<256>());
+    println!("2 KiB: {} cycles", bench::<2048>());
+    // served by the free list
+    println!("3 KiB: {} cycles", bench::<3072>());
+
+    // compare with the `heap-first-fit` feature
+    boa_engine::init();
+    let allocator = if cfg!(feature = "heap-first-fit") {
+        "first-fit"
+    } else {
+        "slabs"
+    };
+    println!("JS workload ({}): {} cycles", allocator, js_workload());
+
+    QEMU_EXIT_HANDLE.exit_success()
+}
+
+/// See https://github.com/rust-lang/libm/issues/258
+#[no_mangle]
+pub extern "C" fn fmin(x: f64, y: f64) -> f64 {
+    libm::fmin(x, y)
+}
+
+/// See https://github.com/rust-lang/libm/issues/258
+#[no_mangle]
+pub extern "C" fn fmax(x: f64, y: f64) -> f64 {
+    libm::fmax(x, y)
+}
+
+/// See https://github.com/rust-lang/libm/issues/258
+#[no_mangle]
+pub extern "C" fn fmod(x: f64, y: f64) -> f64 {
+    libm::fmod(x, y)
+}
@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, format_args_nl)]
+#![test_runner(ingram_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use {
+    core::sync::atomic::{AtomicUsize, Ordering},
+    ingram_kernel::{
+        allocator,
+        console::{self, Mode},
+        deferred, entry_point, gdt, interrupt, memory, println, uart, BootInfo, QEMUExit,
+        QEMU_EXIT_HANDLE,
+    },
+};
+
+entry_point!(test_kernel_main);
+
+static INPUTS: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Feeds `bytes` as if typed, then runs the line discipline.
+fn type_bytes(bytes: &[u8]) {
+    for &byte in bytes {
+        console::receive(byte);
+    }
+    deferred::run();
+}
+
+fn test_kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    uart::init();
+    gdt::init();
+    interrupt::init();
+    let (mut mapper, mut frame_allocator) = unsafe { memory::init(&boot_info.memory_regions) };
+    allocator::init(&mut mapper, &mut frame_allocator);
+
+    console::set_input_handler(|| {
+        INPUTS.fetch_add(1, Ordering::SeqCst);
+    });
+    console::set_interrupt_handler(|| {
+        INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+    });
+    console::set_echo(false);
+
+    // Nothing is readable before the end of the line
+    type_bytes(b"lx\x7fs");
+    assert!(console::read(usize::MAX).is_empty());
+    assert_eq!(INPUTS.load(Ordering::SeqCst), 0);
+
+    type_bytes(b" -l\r");
+    assert_eq!(INPUTS.load(Ordering::SeqCst), 1);
+    assert_eq!(console::read(3), b"ls ");
+    assert_eq!(console::read(usize::MAX), b"-l\n");
+
+    // A read stops before a character that does not fit
+    type_bytes("aé\n".as_bytes());
+    assert_eq!(console::read(2), b"a");
+    assert_eq!(console::read(1), b"");
+    assert_eq!(console::read(2), "é".as_bytes());
+    assert_eq!(console::read(usize::MAX), b"\n");
+
+    // Backspace erases a whole character, Ctrl-C the line
+    type_bytes("é\x08a\x03".as_bytes());
+    assert_eq!(INTERRUPTS.load(Ordering::SeqCst), 1);
+    type_bytes(b"\n");
+    assert_eq!(console::read(usize::MAX), b"\n");
+
+    console::set_mode(Mode::Raw);
+    type_bytes(b"\x03\x7f");
+    assert_eq!(INTERRUPTS.load(Ordering::SeqCst), 1);
+    assert_eq!(console::read(usize::MAX), b"\x03\x7f");
+
+    println!("test tests::console ... ok");
+    QEMU_EXIT_HANDLE.exit_success()
+}
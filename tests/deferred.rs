@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, format_args_nl)]
+#![test_runner(ingram_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use {
+    core::sync::atomic::{AtomicU64, Ordering},
+    ingram_kernel::{
+        deferred::{self, CAPACITY},
+        entry_point, println, uart, BootInfo, QEMUExit, QEMU_EXIT_HANDLE,
+    },
+};
+
+entry_point!(test_kernel_main);
+
+static LAST: AtomicU64 = AtomicU64::new(0);
+
+/// Checks that the work runs in order.
+fn record(arg: u64) {
+    assert_eq!(LAST.swap(arg, Ordering::SeqCst) + 1, arg);
+}
+
+fn test_kernel_main(_boot_info: &'static mut BootInfo) -> ! {
+    uart::init();
+
+    for arg in 1..=CAPACITY as u64 {
+        assert!(deferred::defer(record, arg));
+    }
+    assert!(!deferred::defer(record, 0));
+    assert_eq!(deferred::dropped(), 1);
+
+    assert_eq!(deferred::run(), CAPACITY);
+    assert!(deferred::is_empty());
+    assert_eq!(LAST.load(Ordering::SeqCst), CAPACITY as u64);
+
+    println!("test tests::deferred ... ok");
+    QEMU_EXIT_HANDLE.exit_success()
+}
@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, format_args_nl)]
+#![test_runner(ingram_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use {
+    core::arch::x86_64::_rdtsc,
+    ingram_kernel::{
+        allocator,
+        constant::{HEAP_MAX_SIZE, HEAP_START},
+        entry_point, gdt, interrupt,
+        memory::{self, alloc_virt, dealloc_virt},
+        println, uart, BootInfo, QEMUExit, QEMU_EXIT_HANDLE,
+    },
+    x86_64::structures::paging::{PageSize, Size2MiB, Size4KiB},
+};
+
+entry_point!(test_kernel_main);
+
+/// Scratch range right after the largest heap, so that it never collides with it.
+const START: u64 = HEAP_START + HEAP_MAX_SIZE;
+const SIZE: u64 = 64 * Size2MiB::SIZE;
+const END: u64 = START + SIZE - 1;
+
+/// Number of timed scans, of which the fastest is kept to leave out interrupts and cache misses.
+const SCANS: usize = 4;
+
+/// Reads one word of every cache line in the scratch range, like the marking phase of the GC
+/// scanning a heap much larger than the TLB covers.
+fn scan() -> u64 {
+    let start = unsafe { _rdtsc() };
+    for addr in (START..END).step_by(64) {
+        unsafe { (addr as *const u64).read_volatile() };
+    }
+    let end = unsafe { _rdtsc() };
+    end - start
+}
+
+fn test_kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    uart::init();
+    gdt::init();
+    interrupt::init();
+    let (mut mapper, mut frame_allocator) = unsafe { memory::init(&boot_info.memory_regions) };
+    allocator::init(&mut mapper, &mut frame_allocator);
+
+    // one call per page never gets a 2MiB page
+    let start = unsafe { _rdtsc() };
+    for addr in (START..END).step_by(Size4KiB::SIZE as usize) {
+        alloc_virt(&mut mapper, &mut frame_allocator, addr, addr, None).unwrap();
+    }
+    let small_map = unsafe { _rdtsc() } - start;
+    scan();
+    let small_scan = (0..SCANS).map(|_| scan()).min().unwrap();
+    dealloc_virt(&mut mapper, &mut frame_allocator, START, END).unwrap();
+
+    let start = unsafe { _rdtsc() };
+    alloc_virt(&mut mapper, &mut frame_allocator, START, END, None).unwrap();
+    let huge_map = unsafe { _rdtsc() } - start;
+    scan();
+    let huge_scan = (0..SCANS).map(|_| scan()).min().unwrap();
+    dealloc_virt(&mut mapper, &mut frame_allocator, START, END).unwrap();
+
+    println!(
+        "4KiB pages: map {} cycles, scan {} cycles",
+        small_map, small_scan
+    );
+    println!(
+        "2MiB pages: map {} cycles, scan {} cycles",
+        huge_map, huge_scan
+    );
+    assert!(huge_map < small_map);
+    // Fewer TLB misses, which is what the GC and the boot gain from the heap's huge pages
+    assert!(huge_scan < small_scan);
+
+    QEMU_EXIT_HANDLE.exit_success()
+}
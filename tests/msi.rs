@@ -0,0 +1,151 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, format_args_nl)]
+#![test_runner(ingram_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use {
+    core::sync::atomic::{AtomicU8, Ordering},
+    ingram_kernel::{
+        acpi, allocator,
+        apic::{self, LOCAL_APIC},
+        constant::MSI_VECTORS,
+        entry_point, gdt, interrupt, memory,
+        pci::{
+            self,
+            msi::{self, MsiError, CAPABILITY_MSIX},
+            Device, DEVICES,
+        },
+        uart, BootInfo, QEMUExit, QEMU_EXIT_HANDLE,
+    },
+};
+
+entry_point!(test_kernel_main);
+
+/// The vendor of virtio devices, the runner adds a `virtio-rng-pci` to the tests.
+const VIRTIO_VENDOR: u16 = 0x1af4;
+
+fn test_kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    uart::init();
+    let rsdp_addr = boot_info.rsdp_addr.into_option().unwrap();
+    gdt::init();
+    interrupt::init();
+    let (mut mapper, mut frame_allocator) = unsafe { memory::init(&boot_info.memory_regions) };
+    allocator::init(&mut mapper, &mut frame_allocator);
+    let platform = acpi::init(rsdp_addr);
+    apic::init(
+        &mut mapper,
+        &mut frame_allocator,
+        platform.pm_timer,
+        platform.hpet,
+        platform.apic,
+    );
+    pci::init(&mut mapper, &mut frame_allocator);
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    QEMU_EXIT_HANDLE.exit_success()
+}
+
+fn virtio() -> &'static Device {
+    DEVICES
+        .get()
+        .unwrap()
+        .iter()
+        .find(|device| {
+            device.vendor_id == VIRTIO_VENDOR && device.capability(CAPABILITY_MSIX).is_some()
+        })
+        .expect("no virtio device with MSI-X")
+}
+
+static LAST: AtomicU8 = AtomicU8::new(0);
+
+fn record(vector: u8) {
+    LAST.store(vector, Ordering::SeqCst);
+}
+
+/// The entry is programmed with a vector of [`MSI_VECTORS`] and unmasked, and MSI-X is enabled.
+#[test_case]
+fn msix_entry_is_programmed() {
+    let device = virtio();
+    let vector = msi::enable_msix(device, 0, record).unwrap();
+    assert!(MSI_VECTORS.contains(&vector));
+
+    let capability = device.capability(CAPABILITY_MSIX).unwrap();
+    let control = pci::read_u16(device.address, capability.offset + 2);
+    assert_ne!(control & (1 << 15), 0, "MSI-X is not enabled");
+
+    let table = pci::read(device.address, capability.offset + 4);
+    let bar = device
+        .bars
+        .iter()
+        .find(|bar| bar.index as u32 == table & 0b111)
+        .unwrap();
+    let entry = (bar.address + (table & !0b111) as u64) as *const u32;
+    unsafe {
+        assert_eq!(entry.read_volatile() & 0xfff0_0000, 0xfee0_0000);
+        assert_eq!(entry.add(2).read_volatile(), vector as u32);
+        assert_eq!(entry.add(3).read_volatile() & 1, 0, "the entry is masked");
+    }
+
+    msi::free_vector(vector);
+}
+
+/// Entries past the end of the table are refused before any vector is taken.
+#[test_case]
+fn msix_entry_out_of_table() {
+    let device = virtio();
+    let capability = device.capability(CAPABILITY_MSIX).unwrap();
+    let size = pci::read_u16(device.address, capability.offset + 2) & 0x7ff;
+
+    assert!(matches!(
+        msi::enable_msix(device, size + 1, record),
+        Err(MsiError::InvalidEntry(entry)) if entry == size + 1
+    ));
+}
+
+/// The host bridge has neither MSI nor MSI-X.
+#[test_case]
+fn no_capability() {
+    let bridge = &DEVICES.get().unwrap()[0];
+    assert!(matches!(
+        msi::enable_msix(bridge, 0, record),
+        Err(MsiError::NoCapability)
+    ));
+}
+
+/// Every vector can be taken once, and is reusable after being freed.
+#[test_case]
+fn vectors_run_out() {
+    let mut taken = 0;
+    while let Ok(vector) = msi::alloc_vector(record) {
+        assert!(MSI_VECTORS.contains(&vector));
+        taken += 1;
+    }
+    assert_eq!(taken, MSI_VECTORS.len());
+    assert!(matches!(msi::alloc_vector(record), Err(MsiError::NoVector)));
+
+    for vector in MSI_VECTORS {
+        msi::free_vector(vector);
+    }
+    let vector = msi::alloc_vector(record).unwrap();
+    msi::free_vector(vector);
+}
+
+/// A vector raised on the local APIC runs its handler, like a message from the device.
+#[test_case]
+fn vector_runs_handler() {
+    let vector = msi::alloc_vector(record).unwrap();
+    LAST.store(0, Ordering::SeqCst);
+
+    unsafe { (&mut *LOCAL_APIC.as_mut_ptr()).send_ipi_self(vector) };
+    for _ in 0..1_000_000 {
+        if LAST.load(Ordering::SeqCst) == vector {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    assert_eq!(LAST.load(Ordering::SeqCst), vector);
+
+    msi::free_vector(vector);
+}
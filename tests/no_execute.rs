@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt, custom_test_frameworks, format_args_nl)]
+#![test_runner(ingram_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use {
+    alloc::boxed::Box,
+    ingram_kernel::{
+        allocator, entry_point, gdt, memory, println, uart, BootInfo, QEMUExit, QEMU_EXIT_HANDLE,
+    },
+    spin::Lazy,
+    x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+};
+
+entry_point!(test_kernel_main);
+
+fn test_kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    uart::init();
+    gdt::init();
+    IDT.load();
+    let (mut mapper, mut frame_allocator) = unsafe { memory::init(&boot_info.memory_regions) };
+    allocator::init(&mut mapper, &mut frame_allocator);
+    memory::enforce_wx(&mut mapper);
+
+    // ret
+    let code = Box::leak(Box::new([0xc3u8; 16]));
+    let f: extern "C" fn() = unsafe { core::mem::transmute(code.as_ptr()) };
+    f();
+
+    panic!("Execution continued after jumping into the heap");
+}
+
+static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
+    let mut idt = InterruptDescriptorTable::new();
+    idt.page_fault.set_handler_fn(test_page_fault_handler);
+    idt
+});
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    assert!(error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH));
+    println!("test tests::no_execute ... ok");
+    QEMU_EXIT_HANDLE.exit_success();
+}
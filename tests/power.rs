@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, format_args_nl)]
+#![test_runner(ingram_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use ingram_kernel::{
+    entry_point,
+    power::{fallback_ports, parse_s5},
+    uart, BootInfo, QEMUExit, QEMU_EXIT_HANDLE,
+};
+
+entry_point!(test_kernel_main);
+
+fn test_kernel_main(_boot_info: &'static mut BootInfo) -> ! {
+    uart::init();
+    test_main();
+    QEMU_EXIT_HANDLE.exit_success()
+}
+
+/// `Name (_S5, Package (0x04) { 0x05, 0x05, Zero, Zero })` after another declaration.
+#[test_case]
+fn s5_with_byte_constants() {
+    let aml = [
+        0x08, b'_', b'S', b'4', b'_', 0x12, 0x06, 0x04, 0x01, 0x01, 0x00, 0x00, // _S4_
+        0x08, b'_', b'S', b'5', b'_', 0x12, 0x08, 0x04, 0x0a, 0x05, 0x0a, 0x05, 0x00, 0x00,
+    ];
+    assert_eq!(parse_s5(&aml), Some((5, 5)));
+}
+
+/// `Name (\_S5, Package (0x04) { Zero, Zero, Zero, Zero })`, as QEMU declares it.
+#[test_case]
+fn s5_in_root_scope() {
+    let aml = [
+        0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x00, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(parse_s5(&aml), Some((0, 0)));
+}
+
+/// A two byte PkgLength and word constants.
+#[test_case]
+fn s5_with_long_package() {
+    let aml = [
+        0x08, b'_', b'S', b'5', b'_', 0x12, 0x40, 0x00, 0x02, 0x0b, 0x07, 0x01, 0x0c, 0x02, 0x00,
+        0x00, 0x00,
+    ];
+    assert_eq!(parse_s5(&aml), Some((0x107, 2)));
+}
+
+/// A reference to `_S5_` that does not declare it, and truncated packages.
+#[test_case]
+fn s5_missing() {
+    assert_eq!(parse_s5(&[0x70, b'_', b'S', b'5', b'_', 0x60]), None);
+    assert_eq!(parse_s5(&[0x08, b'_', b'S', b'5', b'_', 0x12, 0x06]), None);
+    assert_eq!(
+        parse_s5(&[0x08, b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x0a]),
+        None
+    );
+    assert_eq!(parse_s5(&[]), None);
+}
+
+/// QEMU's shutdown port and the keyboard controller are always tried.
+#[test_case]
+fn fallback_ports_cover_qemu() {
+    assert!(fallback_ports().any(|port| port == 0x604));
+    assert!(fallback_ports().any(|port| port == 0x64));
+}
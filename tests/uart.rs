@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks, format_args_nl)]
+#![test_runner(ingram_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use {
+    core::sync::atomic::{AtomicUsize, Ordering},
+    ingram_kernel::{
+        deferred, entry_point, gdt, interrupt, println,
+        uart::{self, TX_CAPACITY},
+        BootInfo, QEMUExit, QEMU_EXIT_HANDLE,
+    },
+    x86_64::instructions::interrupts::without_interrupts,
+};
+
+entry_point!(test_kernel_main);
+
+static DRAINS: AtomicUsize = AtomicUsize::new(0);
+
+fn test_kernel_main(_boot_info: &'static mut BootInfo) -> ! {
+    uart::init();
+    gdt::init();
+    interrupt::init();
+
+    // The COM1 IRQ is not routed, the test plays the interrupt handler
+    uart::set_drain_handler(|| {
+        DRAINS.fetch_add(1, Ordering::SeqCst);
+    });
+    uart::enable_interrupts();
+
+    // The ring takes what fits without waiting, in whole characters
+    let filler = [b' '; TX_CAPACITY - 1];
+    assert_eq!(uart::try_write_bytes(&filler), TX_CAPACITY - 1);
+    assert_eq!(uart::try_write_str("é"), 0);
+    assert_eq!(uart::pending(), TX_CAPACITY - 1);
+    assert_eq!(uart::try_write_bytes(b"\n\n"), 1);
+    assert_eq!(uart::pending(), TX_CAPACITY);
+
+    // The interrupt moves a FIFO at a time, then the drain handler runs once in the main loop
+    let mut rounds = 0;
+    while uart::pending() > 0 {
+        uart::handle_interrupt(|_| ());
+        rounds += 1;
+    }
+    assert!(rounds > 1);
+    assert_eq!(DRAINS.load(Ordering::SeqCst), 0);
+    deferred::run();
+    assert_eq!(DRAINS.load(Ordering::SeqCst), 1);
+
+    // Writers that must not lose output wait for room instead, by sending bytes themselves when
+    // the interrupt cannot come
+    without_interrupts(|| {
+        uart::write_bytes(&filler);
+        uart::write_bytes(&filler);
+    });
+    assert_eq!(uart::pending(), TX_CAPACITY);
+
+    uart::set_synchronous();
+    assert_eq!(uart::pending(), 0);
+    println!();
+    println!("test tests::uart ... ok");
+    QEMU_EXIT_HANDLE.exit_success()
+}